@@ -0,0 +1,46 @@
+/*
+ * C interface to c-tree: an ordered map of byte string keys and values,
+ * saved to and loaded from a file. Link against the cdylib built from this
+ * crate. See src/ffi.rs for the ownership rules.
+ */
+#ifndef CTREE_H
+#define CTREE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CTREE_OK 0
+#define CTREE_NOT_FOUND 1
+#define CTREE_DONE 2
+#define CTREE_INVALID_ARGUMENT (-1)
+#define CTREE_IO_ERROR (-2)
+
+typedef struct CTree CTree;
+typedef struct CTreeIter CTreeIter;
+
+int ctree_open(const char *path, CTree **out);
+int ctree_save(const CTree *tree);
+void ctree_close(CTree *tree);
+
+int ctree_put(CTree *tree, const uint8_t *key, size_t key_len, const uint8_t *value,
+              size_t value_len);
+int ctree_get(const CTree *tree, const uint8_t *key, size_t key_len, uint8_t **value,
+              size_t *value_len);
+void ctree_free(uint8_t *bytes, size_t len);
+int ctree_delete(CTree *tree, const uint8_t *key, size_t key_len);
+
+int ctree_iter_range(const CTree *tree, const uint8_t *start, size_t start_len,
+                     const uint8_t *end, size_t end_len, CTreeIter **out);
+int ctree_iter_next(CTreeIter *iter, const uint8_t **key, size_t *key_len,
+                    const uint8_t **value, size_t *value_len);
+void ctree_iter_free(CTreeIter *iter);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,602 @@
+// A B-tree whose nodes also keep a summary of every entry below them, such
+// as a sum or a maximum. Summaries combine associatively, so the summary of a
+// key range is put together from the whole subtrees inside it plus the
+// entries along its two edges, one descent each.
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::ops::{Add, Bound, RangeBounds};
+
+// An associative way to summarise entries. `empty` combines with anything to
+// give it back unchanged, and `combine` is called with the left side first,
+// so it needn't be commutative.
+pub trait Monoid<K, V>: Clone {
+    fn empty() -> Self;
+    fn of(key: &K, value: &V) -> Self;
+    fn combine(&self, right: &Self) -> Self;
+}
+
+// No summary, for trees that only want the node code.
+impl<K, V> Monoid<K, V> for () {
+    fn empty() {}
+
+    fn of(_: &K, _: &V) {}
+
+    fn combine(&self, _: &()) {}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Count(pub usize);
+
+impl<K, V> Monoid<K, V> for Count {
+    fn empty() -> Count {
+        Count(0)
+    }
+
+    fn of(_: &K, _: &V) -> Count {
+        Count(1)
+    }
+
+    fn combine(&self, right: &Count) -> Count {
+        Count(self.0 + right.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sum<V>(pub V);
+
+impl<K, V: Clone + Default + Add<Output = V>> Monoid<K, V> for Sum<V> {
+    fn empty() -> Sum<V> {
+        Sum(V::default())
+    }
+
+    fn of(_: &K, value: &V) -> Sum<V> {
+        Sum(value.clone())
+    }
+
+    fn combine(&self, right: &Sum<V>) -> Sum<V> {
+        Sum(self.0.clone() + right.0.clone())
+    }
+}
+
+// The smallest and largest values, None over no entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Min<V>(pub Option<V>);
+
+impl<K, V: Ord + Clone> Monoid<K, V> for Min<V> {
+    fn empty() -> Min<V> {
+        Min(None)
+    }
+
+    fn of(_: &K, value: &V) -> Min<V> {
+        Min(Some(value.clone()))
+    }
+
+    fn combine(&self, right: &Min<V>) -> Min<V> {
+        match (&self.0, &right.0) {
+            (Some(a), Some(b)) => Min(Some(a.min(b).clone())),
+            (a, b) => Min(a.clone().or(b.clone())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Max<V>(pub Option<V>);
+
+impl<K, V: Ord + Clone> Monoid<K, V> for Max<V> {
+    fn empty() -> Max<V> {
+        Max(None)
+    }
+
+    fn of(_: &K, value: &V) -> Max<V> {
+        Max(Some(value.clone()))
+    }
+
+    fn combine(&self, right: &Max<V>) -> Max<V> {
+        Max(self.0.clone().max(right.0.clone()))
+    }
+}
+
+fn above_start<K: Ord>(key: &K, start: Bound<&K>) -> bool {
+    match start {
+        Bound::Included(start) => key >= start,
+        Bound::Excluded(start) => key > start,
+        Bound::Unbounded => true,
+    }
+}
+
+fn before_end<K: Ord>(key: &K, end: Bound<&K>) -> bool {
+    match end {
+        Bound::Included(end) => key <= end,
+        Bound::Excluded(end) => key < end,
+        Bound::Unbounded => true,
+    }
+}
+
+// How a node keeps its keys, in order. A Vec holds them as they are; other
+// layouts, like PrefixBTree's shared prefixes, can store them more compactly.
+pub trait Keys<K: Clone>: Default {
+    fn len(&self) -> usize;
+    fn key(&self, i: usize) -> Cow<'_, K>;
+    fn search(&self, key: &K) -> Result<usize, usize>;
+    fn insert(&mut self, i: usize, key: K);
+    fn remove(&mut self, i: usize) -> K;
+    // Moves the keys from `at` on into a new store.
+    fn split_off(&mut self, at: usize) -> Self;
+    // Adds keys that all sort after these.
+    fn append(&mut self, other: Self);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn replace(&mut self, i: usize, key: K) -> K {
+        let old = self.remove(i);
+        self.insert(i, key);
+        old
+    }
+}
+
+impl<K: Ord + Clone> Keys<K> for Vec<K> {
+    fn len(&self) -> usize {
+        <[K]>::len(self)
+    }
+
+    fn key(&self, i: usize) -> Cow<'_, K> {
+        Cow::Borrowed(&self[i])
+    }
+
+    fn search(&self, key: &K) -> Result<usize, usize> {
+        self.binary_search(key)
+    }
+
+    fn insert(&mut self, i: usize, key: K) {
+        Vec::insert(self, i, key)
+    }
+
+    fn remove(&mut self, i: usize) -> K {
+        Vec::remove(self, i)
+    }
+
+    fn split_off(&mut self, at: usize) -> Vec<K> {
+        Vec::split_off(self, at)
+    }
+
+    fn append(&mut self, mut other: Vec<K>) {
+        Vec::append(self, &mut other)
+    }
+
+    fn replace(&mut self, i: usize, key: K) -> K {
+        std::mem::replace(&mut self[i], key)
+    }
+}
+
+// Trees built on this one, like CountedBTree, walk the nodes themselves for
+// queries that need more than a summary.
+pub(crate) struct Node<K, V, M, S = Vec<K>> {
+    pub(crate) keys: S,
+    pub(crate) values: Vec<V>,
+    pub(crate) children: Vec<Node<K, V, M, S>>,
+    // Summary of every entry in this subtree.
+    pub(crate) summary: M,
+    key_type: PhantomData<K>,
+}
+
+impl<
+        K: Ord + Clone + std::fmt::Debug,
+        V: Ord + Clone + std::fmt::Debug,
+        M: Monoid<K, V>,
+        S: Keys<K>,
+    > Node<K, V, M, S>
+{
+    fn new() -> Node<K, V, M, S> {
+        Node {
+            keys: S::default(),
+            values: Vec::new(),
+            children: Vec::new(),
+            summary: M::empty(),
+            key_type: PhantomData,
+        }
+    }
+
+    // Recomputes the summary from the entries and the children's summaries,
+    // in key order.
+    fn update(&mut self) {
+        // A summary with no data, like (), never changes, and a key store
+        // may have to put each key together to hand it over.
+        if std::mem::size_of::<M>() == 0 {
+            return;
+        }
+
+        let mut summary = M::empty();
+        for i in 0..=self.keys.len() {
+            if let Some(child) = self.children.get(i) {
+                summary = summary.combine(&child.summary);
+            }
+            if i < self.keys.len() {
+                summary = summary.combine(&M::of(&self.keys.key(i), &self.values[i]));
+            }
+        }
+        self.summary = summary;
+    }
+
+    // Returns the replaced value, or the separator and right half if the node
+    // split.
+    fn insert(&mut self, node_size: usize, key: K, value: V) -> Inserted<K, V, M, S> {
+        let i = match self.keys.search(&key) {
+            Ok(i) => {
+                let old = std::mem::replace(&mut self.values[i], value);
+                self.update();
+                return (Some(old), None);
+            }
+            Err(i) => i,
+        };
+
+        if self.children.is_empty() {
+            self.keys.insert(i, key);
+            self.values.insert(i, value);
+        } else {
+            match self.children[i].insert(node_size, key, value) {
+                (Some(old), _) => {
+                    self.update();
+                    return (Some(old), None);
+                }
+                (None, Some((key, value, right))) => {
+                    self.keys.insert(i, key);
+                    self.values.insert(i, value);
+                    self.children.insert(i + 1, right);
+                }
+                (None, None) => {}
+            }
+        }
+
+        let split = (self.keys.len() > node_size).then(|| self.split());
+        self.update();
+        (None, split)
+    }
+
+    fn split(&mut self) -> Split<K, V, M, S> {
+        let mid = self.keys.len() / 2;
+
+        let mut right = Node::new();
+        right.keys = self.keys.split_off(mid + 1);
+        right.values = self.values.split_off(mid + 1);
+        if !self.children.is_empty() {
+            right.children = self.children.split_off(mid + 1);
+        }
+        right.update();
+
+        (self.keys.remove(mid), self.values.pop().unwrap(), right)
+    }
+
+    fn remove(&mut self, min: usize, key: &K) -> Option<V> {
+        let value = match self.keys.search(key) {
+            Ok(i) if self.children.is_empty() => {
+                self.keys.remove(i);
+                self.values.remove(i)
+            }
+            Ok(i) => {
+                // Replace the entry with its predecessor from the left subtree.
+                let (key, value) = self.children[i].pop_max(min);
+                self.keys.replace(i, key);
+                let old = std::mem::replace(&mut self.values[i], value);
+                self.fix_child(min, i);
+                old
+            }
+            Err(_) if self.children.is_empty() => return None,
+            Err(i) => {
+                let old = self.children[i].remove(min, key)?;
+                self.fix_child(min, i);
+                old
+            }
+        };
+
+        self.update();
+        Some(value)
+    }
+
+    fn pop_max(&mut self, min: usize) -> (K, V) {
+        let entry = if self.children.is_empty() {
+            let last = self.keys.len() - 1;
+            (self.keys.remove(last), self.values.pop().unwrap())
+        } else {
+            let last = self.children.len() - 1;
+            let entry = self.children[last].pop_max(min);
+            self.fix_child(min, last);
+            entry
+        };
+
+        self.update();
+        entry
+    }
+
+    // Refills children[index] after a removal left it with too few keys, by
+    // borrowing from a sibling or merging with one.
+    fn fix_child(&mut self, min: usize, index: usize) {
+        if self.children[index].keys.len() >= min {
+            return;
+        }
+
+        if index > 0 && self.children[index - 1].keys.len() > min {
+            let (left, right) = self.children.split_at_mut(index);
+            let (left, child) = (&mut left[index - 1], &mut right[0]);
+
+            let last = left.keys.len() - 1;
+            let key = self.keys.replace(index - 1, left.keys.remove(last));
+            let value = std::mem::replace(&mut self.values[index - 1], left.values.pop().unwrap());
+            child.keys.insert(0, key);
+            child.values.insert(0, value);
+            if let Some(grandchild) = left.children.pop() {
+                child.children.insert(0, grandchild);
+            }
+            left.update();
+            child.update();
+        } else if index + 1 < self.children.len() && self.children[index + 1].keys.len() > min {
+            let (left, right) = self.children.split_at_mut(index + 1);
+            let (child, right) = (&mut left[index], &mut right[0]);
+
+            let key = self.keys.replace(index, right.keys.remove(0));
+            let value = std::mem::replace(&mut self.values[index], right.values.remove(0));
+            child.keys.insert(child.keys.len(), key);
+            child.values.push(value);
+            if !right.children.is_empty() {
+                child.children.push(right.children.remove(0));
+            }
+            child.update();
+            right.update();
+        } else if index > 0 {
+            self.merge_children(index - 1);
+        } else if self.children.len() > 1 {
+            self.merge_children(index);
+        }
+    }
+
+    // Merges children[index + 1] and the key separating them into children[index].
+    fn merge_children(&mut self, index: usize) {
+        let right = self.children.remove(index + 1);
+        let key = self.keys.remove(index);
+        let value = self.values.remove(index);
+
+        let left = &mut self.children[index];
+        left.keys.insert(left.keys.len(), key);
+        left.values.push(value);
+        left.keys.append(right.keys);
+        left.values.extend(right.values);
+        left.children.extend(right.children);
+        left.update();
+    }
+
+    // Summarises the entries between `start` and `end`. A bound is Unbounded
+    // once every key in the subtree is known to satisfy it, and a subtree
+    // with neither bound left is answered by its summary.
+    fn query(&self, start: Bound<&K>, end: Bound<&K>) -> M {
+        if let (Bound::Unbounded, Bound::Unbounded) = (start, end) {
+            return self.summary.clone();
+        }
+
+        let mut summary = M::empty();
+        let mut previous: Option<Cow<'_, K>> = None;
+        for i in 0..=self.keys.len() {
+            if previous.as_deref().is_some_and(|key| !before_end(key, end)) {
+                break;
+            }
+
+            let key = (i < self.keys.len()).then(|| self.keys.key(i));
+            let below = key.as_deref().is_some_and(|key| !above_start(key, start));
+            if let (Some(child), false) = (self.children.get(i), below) {
+                let start = match previous.as_deref() {
+                    Some(previous) if above_start(previous, start) => Bound::Unbounded,
+                    _ => start,
+                };
+                let end = match key.as_deref() {
+                    Some(key) if before_end(key, end) => Bound::Unbounded,
+                    _ => end,
+                };
+                summary = summary.combine(&child.query(start, end));
+            }
+
+            if let Some(key) = key.as_deref() {
+                if above_start(key, start) && before_end(key, end) {
+                    summary = summary.combine(&M::of(key, &self.values[i]));
+                }
+            }
+            previous = key;
+        }
+
+        summary
+    }
+}
+
+// Separator entry and right half of a split node.
+type Split<K, V, M, S> = (K, V, Node<K, V, M, S>);
+
+// The replaced value, or the split if there was no old value.
+type Inserted<K, V, M, S> = (Option<V>, Option<Split<K, V, M, S>>);
+
+pub struct AggregateBTree<
+    K: Ord + Clone + std::fmt::Debug,
+    V: Ord + Clone + std::fmt::Debug,
+    M: Monoid<K, V>,
+    S: Keys<K> = Vec<K>,
+> {
+    root: Node<K, V, M, S>,
+    node_size: usize,
+    len: usize,
+}
+
+impl<
+        K: Ord + Clone + std::fmt::Debug,
+        V: Ord + Clone + std::fmt::Debug,
+        M: Monoid<K, V>,
+        S: Keys<K>,
+    > AggregateBTree<K, V, M, S>
+{
+    pub fn new(node_size: usize) -> AggregateBTree<K, V, M, S> {
+        assert!(node_size >= 2, "a node must hold at least two keys");
+
+        AggregateBTree {
+            root: Node::new(),
+            node_size,
+            len: 0,
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let (old, split) = self.root.insert(self.node_size, key, value);
+        if let Some((key, value, right)) = split {
+            let left = std::mem::replace(&mut self.root, Node::new());
+            self.root.keys.insert(0, key);
+            self.root.values.push(value);
+            self.root.children = vec![left, right];
+            self.root.update();
+        }
+        if old.is_none() {
+            self.len += 1;
+        }
+
+        old
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.root.remove(self.node_size / 2, key)?;
+        self.len -= 1;
+
+        if self.root.keys.is_empty() {
+            if let Some(child) = self.root.children.pop() {
+                self.root = child;
+            }
+        }
+
+        Some(value)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let mut node = &self.root;
+        loop {
+            match node.keys.search(key) {
+                Ok(i) => return Some(&node.values[i]),
+                Err(i) => node = node.children.get(i)?,
+            }
+        }
+    }
+
+    pub(crate) fn root(&self) -> &Node<K, V, M, S> {
+        &self.root
+    }
+
+    // Summary of every entry.
+    pub fn aggregate(&self) -> M {
+        self.root.summary.clone()
+    }
+
+    // Summary of the entries whose keys lie in `range`.
+    pub fn aggregate_range<R: RangeBounds<K>>(&self, range: R) -> M {
+        self.root.query(range.start_bound(), range.end_bound())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    use crate::aggregate::{AggregateBTree, Count, Max, Min, Monoid, Sum};
+    use crate::testutil::Rng;
+
+    // Keys in order, which only comes out right if combine is called with
+    // the left side first.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct Keys(Vec<u64>);
+
+    impl Monoid<u64, u64> for Keys {
+        fn empty() -> Keys {
+            Keys(Vec::new())
+        }
+
+        fn of(key: &u64, _: &u64) -> Keys {
+            Keys(vec![*key])
+        }
+
+        fn combine(&self, right: &Keys) -> Keys {
+            Keys([self.0.as_slice(), &right.0].concat())
+        }
+    }
+
+    fn check<M: Monoid<u64, u64> + PartialEq + std::fmt::Debug>(
+        tree: &AggregateBTree<u64, u64, M>,
+        expected: &BTreeMap<u64, u64>,
+        range: (Bound<u64>, Bound<u64>),
+    ) {
+        let want = expected
+            .range(range)
+            .fold(M::empty(), |summary, (key, value)| {
+                summary.combine(&M::of(key, value))
+            });
+        assert_eq!(tree.aggregate_range(range), want, "{:?}", range);
+    }
+
+    #[test]
+    fn test_aggregate_btree() {
+        for node_size in [2, 3, 6] {
+            let mut sums = AggregateBTree::<u64, u64, Sum<u64>>::new(node_size);
+            let mut keys = AggregateBTree::<u64, u64, Keys>::new(node_size);
+            let mut expected = BTreeMap::new();
+            let mut rng = Rng::new();
+
+            for step in 0..3000 {
+                let state = rng.next_u64();
+                let key = state % 500;
+
+                if state.is_multiple_of(3) {
+                    assert_eq!(sums.remove(&key), expected.remove(&key));
+                    keys.remove(&key);
+                } else {
+                    let value = (state >> 20) % 1000;
+                    assert_eq!(sums.insert(key, value), expected.insert(key, value));
+                    keys.insert(key, value);
+                }
+                assert_eq!(sums.get(&key), expected.get(&key));
+
+                if step % 20 == 0 {
+                    let low = (state >> 8) % 520;
+                    let high = low + (state >> 32) % 200;
+                    for range in [
+                        (Bound::Included(low), Bound::Excluded(high)),
+                        (Bound::Excluded(low), Bound::Included(high)),
+                        (Bound::Unbounded, Bound::Included(high)),
+                        (Bound::Excluded(low), Bound::Unbounded),
+                    ] {
+                        check(&sums, &expected, range);
+                        check(&keys, &expected, range);
+                    }
+                }
+            }
+
+            assert_eq!(sums.len(), expected.len());
+            assert_eq!(sums.aggregate(), Sum(expected.values().sum()));
+            assert_eq!(keys.aggregate(), Keys(expected.keys().copied().collect()));
+        }
+
+        let mut counts = AggregateBTree::<u64, u64, Count>::new(3);
+        let mut mins = AggregateBTree::<u64, u64, Min<u64>>::new(3);
+        let mut maxes = AggregateBTree::<u64, u64, Max<u64>>::new(3);
+        for key in 0..100 {
+            let value = (key * 37) % 101;
+            counts.insert(key, value);
+            mins.insert(key, value);
+            maxes.insert(key, value);
+        }
+        assert_eq!(counts.aggregate_range(10..20), Count(10));
+        assert_eq!(mins.aggregate_range(10..20), Min(Some(3)));
+        assert_eq!(maxes.aggregate_range(10..20), Max(Some(97)));
+        assert_eq!(mins.aggregate_range(200..), Min(None));
+    }
+}
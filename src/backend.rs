@@ -0,0 +1,152 @@
+// The operations every ordered map in the crate offers, so code that only
+// needs an ordered map can take any of them, or a Box<dyn OrderedMapBackend>
+// picked at runtime. std's BTreeMap implements it too, as a reference to
+// compare the others against.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use crate::bplus::BPlusTree;
+use crate::BTree;
+
+// Iterator over the entries of a range, in key order.
+pub type Entries<'a, K, V> = Box<dyn Iterator<Item = (&'a K, &'a V)> + 'a>;
+
+pub trait OrderedMapBackend<K, V> {
+    // Returns the previous value if the key was present.
+    fn insert(&mut self, key: K, value: V) -> Option<V>;
+    fn get(&self, key: &K) -> Option<&V>;
+    fn remove(&mut self, key: &K) -> Option<V>;
+    fn range<'a>(&'a self, start: Bound<&K>, end: Bound<&K>) -> Entries<'a, K, V>;
+    fn len(&self) -> usize;
+
+    fn iter(&self) -> Entries<'_, K, V> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> OrderedMapBackend<K, V>
+    for BTree<K, V>
+{
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.add(key, value)
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        BTree::get(self, key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BTree::remove(self, key)
+    }
+
+    fn range<'a>(&'a self, start: Bound<&K>, end: Bound<&K>) -> Entries<'a, K, V> {
+        Box::new(BTree::range(self, (start, end)))
+    }
+
+    fn len(&self) -> usize {
+        BTree::len(self)
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> OrderedMapBackend<K, V>
+    for BPlusTree<K, V>
+{
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BPlusTree::insert(self, key, value)
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        BPlusTree::get(self, key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BPlusTree::remove(self, key)
+    }
+
+    fn range<'a>(&'a self, start: Bound<&K>, end: Bound<&K>) -> Entries<'a, K, V> {
+        Box::new(BPlusTree::range(self, (start, end)))
+    }
+
+    fn len(&self) -> usize {
+        BPlusTree::len(self)
+    }
+}
+
+impl<K: Ord, V> OrderedMapBackend<K, V> for BTreeMap<K, V> {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BTreeMap::insert(self, key, value)
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        BTreeMap::get(self, key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BTreeMap::remove(self, key)
+    }
+
+    fn range<'a>(&'a self, start: Bound<&K>, end: Bound<&K>) -> Entries<'a, K, V> {
+        Box::new(BTreeMap::range(self, (start, end)))
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    use crate::backend::OrderedMapBackend;
+    use crate::bplus::BPlusTree;
+    use crate::testutil::Rng;
+    use crate::BTree;
+
+    // Runs the same writes against a backend and returns what it ended with.
+    fn exercise(map: &mut dyn OrderedMapBackend<u64, u64>) -> Vec<(u64, u64)> {
+        let mut rng = Rng::new();
+        for step in 0..2000 {
+            let state = rng.next_u64();
+            let key = state % 300;
+            if state.is_multiple_of(3) {
+                map.remove(&key);
+            } else {
+                map.insert(key, step);
+            }
+        }
+
+        let some: Vec<(u64, u64)> = map
+            .range(Bound::Excluded(&100), Bound::Included(&200))
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        assert!(some.iter().all(|(key, _)| (101..=200).contains(key)));
+        assert_eq!(map.len(), map.iter().count());
+        map.iter().map(|(k, v)| (*k, *v)).collect()
+    }
+
+    #[test]
+    fn test_backends() {
+        let mut backends: Vec<Box<dyn OrderedMapBackend<u64, u64>>> = vec![
+            Box::new(BTreeMap::new()),
+            Box::new(BTree::new(3)),
+            Box::new(BPlusTree::new(4)),
+        ];
+
+        let results: Vec<_> = backends
+            .iter_mut()
+            .map(|map| exercise(&mut **map))
+            .collect();
+        assert!(!results[0].is_empty());
+        assert!(results.windows(2).all(|pair| pair[0] == pair[1]));
+        assert!(backends
+            .iter()
+            .all(|map| map.get(&results[0][0].0) == Some(&results[0][0].1)));
+    }
+}
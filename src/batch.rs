@@ -0,0 +1,150 @@
+// A list of inserts and removes across keys that is applied as one unit, so
+// readers see either none or all of it.
+
+use std::io::{self, Read};
+
+use crate::codec::{invalid_data, read_item, write_item, Codec};
+use crate::BTree;
+
+pub struct WriteBatch<K, V> {
+    // None marks a removal. Later writes to a key win.
+    writes: Vec<(K, Option<V>)>,
+}
+
+impl<K, V> WriteBatch<K, V> {
+    pub fn new() -> WriteBatch<K, V> {
+        WriteBatch { writes: Vec::new() }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> &mut WriteBatch<K, V> {
+        self.writes.push((key, Some(value)));
+        self
+    }
+
+    pub fn remove(&mut self, key: K) -> &mut WriteBatch<K, V> {
+        self.writes.push((key, None));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.writes.iter().map(|(key, _)| key)
+    }
+}
+
+impl<K, V> Default for WriteBatch<K, V> {
+    fn default() -> WriteBatch<K, V> {
+        WriteBatch::new()
+    }
+}
+
+impl<K, V> IntoIterator for WriteBatch<K, V> {
+    type Item = (K, Option<V>);
+    type IntoIter = std::vec::IntoIter<(K, Option<V>)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.writes.into_iter()
+    }
+}
+
+// Each write is a tag byte, 1 for an insert and 0 for a removal, then the
+// key and any value as length-prefixed items.
+impl<K: Codec, V: Codec> Codec for WriteBatch<K, V> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut buf = Vec::new();
+        for (key, write) in &self.writes {
+            out.push(write.is_some() as u8);
+            write_item(out, key, &mut buf).expect("the key fits in an item");
+            if let Some(value) = write {
+                write_item(out, value, &mut buf).expect("the value fits in an item");
+            }
+        }
+    }
+
+    fn decode(mut bytes: &[u8]) -> io::Result<Self> {
+        let mut batch = WriteBatch::new();
+        let mut buf = Vec::new();
+        while !bytes.is_empty() {
+            let mut tag = [0];
+            bytes.read_exact(&mut tag)?;
+            let key = read_item(&mut bytes, &mut buf)?;
+            match tag[0] {
+                0 => batch.remove(key),
+                1 => batch.insert(key, read_item(&mut bytes, &mut buf)?),
+                _ => return Err(invalid_data("unknown write tag")),
+            };
+        }
+        Ok(batch)
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    pub fn write(&mut self, batch: WriteBatch<K, V>) {
+        for (key, write) in batch {
+            match write {
+                Some(value) => {
+                    self.add(key, value);
+                }
+                None => {
+                    self.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::WriteBatch;
+    use crate::sharded::ShardedBTree;
+    use crate::BTree;
+
+    #[test]
+    fn test_write_batch() {
+        let mut tree = BTree::<u64, u64>::new(4);
+        let mut batch = WriteBatch::new();
+        batch.insert(1, 1).insert(2, 2).remove(1).insert(3, 3);
+        assert_eq!(batch.len(), 4);
+        tree.write(batch);
+        assert_eq!(tree.iter().map(|(key, _)| *key).collect::<Vec<_>>(), [2, 3]);
+
+        // Batches move units between accounts in different shards; a reader
+        // must never see the total change.
+        let accounts = ShardedBTree::<u64, u64>::new(4, vec![10, 20, 30]);
+        for account in 0..40 {
+            accounts.insert(account, 100);
+        }
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..2000u64 {
+                    let (from, to) = (i * 7 % 40, i * 13 % 40);
+                    if from == to {
+                        continue;
+                    }
+                    let balance = |account| accounts.get(&account).unwrap();
+
+                    let mut batch = WriteBatch::new();
+                    batch
+                        .insert(from, balance(from) - 1)
+                        .insert(to, balance(to) + 1);
+                    accounts.write(batch);
+                }
+            });
+
+            scope.spawn(|| {
+                for _ in 0..500 {
+                    let total: u64 = accounts.range_snapshot(..).iter().map(|(_, v)| v).sum();
+                    assert_eq!(total, 4000);
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,404 @@
+// A Bε-tree. Writes are messages that enter at the root and wait in the
+// buffers of internal nodes. A full buffer sends its largest group of
+// messages for one child down in a batch, so a node below is rewritten once
+// per batch rather than once per write. Reads pay for this by replaying the
+// messages they pass on the way to a leaf.
+
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+
+// Computes the new value from the old one, if any.
+pub type Upsert<V> = Box<dyn Fn(Option<&V>) -> V>;
+
+pub enum Message<V> {
+    Insert(V),
+    Delete,
+    Upsert(Upsert<V>),
+}
+
+// A message as it waits in a buffer. Upserts on top of upserts are kept in a
+// list rather than composed into one closure, so a hot key that never leaves
+// a buffer costs a loop to read, not a call as deep as its history.
+enum Buffered<V> {
+    Insert(V),
+    Delete,
+    // Oldest first.
+    Upserts(Vec<Upsert<V>>),
+}
+
+impl<V> From<Message<V>> for Buffered<V> {
+    fn from(message: Message<V>) -> Buffered<V> {
+        match message {
+            Message::Insert(value) => Buffered::Insert(value),
+            Message::Delete => Buffered::Delete,
+            Message::Upsert(f) => Buffered::Upserts(vec![f]),
+        }
+    }
+}
+
+impl<V: Clone + 'static> Buffered<V> {
+    fn apply(&self, old: Option<V>) -> Option<V> {
+        match self {
+            Buffered::Insert(value) => Some(value.clone()),
+            Buffered::Delete => None,
+            Buffered::Upserts(upserts) => {
+                let mut value = old;
+                for f in upserts {
+                    value = Some(f(value.as_ref()));
+                }
+                value
+            }
+        }
+    }
+
+    // Folds this message and an older one for the same key into one.
+    fn after(self, older: Buffered<V>) -> Buffered<V> {
+        match (self, older) {
+            (Buffered::Upserts(upserts), older @ (Buffered::Insert(_) | Buffered::Delete)) => {
+                Buffered::Insert(Buffered::Upserts(upserts).apply(older.apply(None)).unwrap())
+            }
+            (Buffered::Upserts(newer), Buffered::Upserts(mut upserts)) => {
+                upserts.extend(newer);
+                Buffered::Upserts(upserts)
+            }
+            (message, _) => message,
+        }
+    }
+}
+
+struct Node<K, V> {
+    // Entry keys in a leaf, pivots in an internal node. Child `i` holds the
+    // keys from `keys[i - 1]` up to `keys[i]`.
+    keys: Vec<K>,
+    // Empty in internal nodes.
+    values: Vec<V>,
+    // Empty in leaves.
+    children: Vec<Node<K, V>>,
+    // Messages not yet sent to the children.
+    buffer: BTreeMap<K, Buffered<V>>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug + 'static> Node<K, V> {
+    fn leaf() -> Node<K, V> {
+        Node {
+            keys: Vec::new(),
+            values: Vec::new(),
+            children: Vec::new(),
+            buffer: BTreeMap::new(),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    fn child_index(&self, key: &K) -> usize {
+        self.keys.partition_point(|k| k <= key)
+    }
+
+    // Takes in messages that are newer than everything below this node.
+    fn apply(&mut self, messages: BTreeMap<K, Buffered<V>>, node_size: usize, buffer_size: usize) {
+        if !self.is_leaf() {
+            for (key, message) in messages {
+                let message = match self.buffer.remove(&key) {
+                    Some(older) => message.after(older),
+                    None => message,
+                };
+                self.buffer.insert(key, message);
+            }
+            self.flush(node_size, buffer_size);
+            return;
+        }
+
+        for (key, message) in messages {
+            match self.keys.binary_search(&key) {
+                Ok(i) => match message.apply(Some(self.values[i].clone())) {
+                    Some(value) => self.values[i] = value,
+                    None => {
+                        self.keys.remove(i);
+                        self.values.remove(i);
+                    }
+                },
+                Err(i) => {
+                    if let Some(value) = message.apply(None) {
+                        self.keys.insert(i, key);
+                        self.values.insert(i, value);
+                    }
+                }
+            }
+        }
+    }
+
+    // Sends messages down until the buffer is within its size.
+    fn flush(&mut self, node_size: usize, buffer_size: usize) {
+        while self.buffer.len() > buffer_size {
+            let i = (0..self.children.len())
+                .max_by_key(|&i| self.buffer.range(self.child_bounds(i)).count())
+                .unwrap();
+            let messages = self.take_messages(i);
+
+            let child = &mut self.children[i];
+            child.apply(messages, node_size, buffer_size);
+            let siblings = child.split_excess(node_size);
+            for (j, (pivot, sibling)) in siblings.into_iter().enumerate() {
+                self.keys.insert(i + j, pivot);
+                self.children.insert(i + j + 1, sibling);
+            }
+
+            let child = &self.children[i];
+            if child.is_leaf() && child.keys.is_empty() && self.children.len() > 1 {
+                self.children.remove(i);
+                self.keys.remove(i.saturating_sub(1));
+            }
+        }
+    }
+
+    fn child_bounds(&self, i: usize) -> (Bound<K>, Bound<K>) {
+        let low = match i {
+            0 => Bound::Unbounded,
+            _ => Bound::Included(self.keys[i - 1].clone()),
+        };
+        let high = match self.keys.get(i) {
+            Some(pivot) => Bound::Excluded(pivot.clone()),
+            None => Bound::Unbounded,
+        };
+        (low, high)
+    }
+
+    fn take_messages(&mut self, i: usize) -> BTreeMap<K, Buffered<V>> {
+        let mut below = match self.keys.get(i) {
+            Some(high) => {
+                let above = self.buffer.split_off(high);
+                std::mem::replace(&mut self.buffer, above)
+            }
+            None => std::mem::take(&mut self.buffer),
+        };
+
+        if i == 0 {
+            return below;
+        }
+        let messages = below.split_off(&self.keys[i - 1]);
+        self.buffer.append(&mut below);
+        messages
+    }
+
+    // Splits off right siblings until this node holds at most `node_size`
+    // keys, returning them with the pivot in front of each.
+    fn split_excess(&mut self, node_size: usize) -> Vec<(K, Node<K, V>)> {
+        if self.keys.len() <= node_size {
+            return Vec::new();
+        }
+
+        let mid = self.keys.len() / 2;
+        let (pivot, mut right) = if self.is_leaf() {
+            let right = Node {
+                keys: self.keys.split_off(mid),
+                values: self.values.split_off(mid),
+                ..Node::leaf()
+            };
+            (right.keys[0].clone(), right)
+        } else {
+            // The middle pivot moves up instead of being copied.
+            let keys = self.keys.split_off(mid + 1);
+            let pivot = self.keys.pop().unwrap();
+            let right = Node {
+                keys,
+                values: Vec::new(),
+                children: self.children.split_off(mid + 1),
+                buffer: self.buffer.split_off(&pivot),
+            };
+            (pivot, right)
+        };
+
+        let mut siblings = self.split_excess(node_size);
+        let rest = right.split_excess(node_size);
+        siblings.push((pivot, right));
+        siblings.extend(rest);
+        siblings
+    }
+
+    fn collect(&self, range: &(Bound<K>, Bound<K>), out: &mut BTreeMap<K, V>) {
+        if self.is_leaf() {
+            for (key, value) in self.keys.iter().zip(&self.values) {
+                if range.contains(key) {
+                    out.insert(key.clone(), value.clone());
+                }
+            }
+            return;
+        }
+
+        for (i, child) in self.children.iter().enumerate() {
+            let below_start = self.keys.get(i).is_some_and(|high| match &range.0 {
+                Bound::Included(start) | Bound::Excluded(start) => high <= start,
+                Bound::Unbounded => false,
+            });
+            let past_end = i > 0 && {
+                let low = &self.keys[i - 1];
+                match &range.1 {
+                    Bound::Included(end) => low > end,
+                    Bound::Excluded(end) => low >= end,
+                    Bound::Unbounded => false,
+                }
+            };
+            if !below_start && !past_end {
+                child.collect(range, out);
+            }
+        }
+
+        for (key, message) in self.buffer.range(range.clone()) {
+            if let Some(value) = message.apply(out.remove(key)) {
+                out.insert(key.clone(), value);
+            }
+        }
+    }
+}
+
+pub struct BeTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug + 'static> {
+    root: Node<K, V>,
+    node_size: usize,
+    buffer_size: usize,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug + 'static> BeTree<K, V> {
+    // Nodes hold up to `node_size` keys and internal nodes buffer up to
+    // `buffer_size` messages.
+    pub fn new(node_size: usize, buffer_size: usize) -> BeTree<K, V> {
+        assert!(node_size >= 2, "a node must hold at least two keys");
+        assert!(buffer_size >= 1, "buffers must hold at least one message");
+
+        BeTree {
+            root: Node::leaf(),
+            node_size,
+            buffer_size,
+        }
+    }
+
+    // Writes are blind: they don't look up the old value, which is what lets
+    // them wait in buffers.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.send(key, Message::Insert(value));
+    }
+
+    pub fn remove(&mut self, key: K) {
+        self.send(key, Message::Delete);
+    }
+
+    pub fn upsert(&mut self, key: K, f: impl Fn(Option<&V>) -> V + 'static) {
+        self.send(key, Message::Upsert(Box::new(f)));
+    }
+
+    pub fn send(&mut self, key: K, message: Message<V>) {
+        self.root.apply(
+            BTreeMap::from([(key, message.into())]),
+            self.node_size,
+            self.buffer_size,
+        );
+
+        let siblings = self.root.split_excess(self.node_size);
+        if !siblings.is_empty() {
+            let old = std::mem::replace(&mut self.root, Node::leaf());
+            self.root.children.push(old);
+            for (pivot, sibling) in siblings {
+                self.root.keys.push(pivot);
+                self.root.children.push(sibling);
+            }
+        }
+
+        while self.root.children.len() == 1 && self.root.buffer.is_empty() {
+            self.root = self.root.children.pop().unwrap();
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        // Messages met on the way down, newest first.
+        let mut pending = Vec::new();
+        let mut node = &self.root;
+        while !node.is_leaf() {
+            pending.extend(node.buffer.get(key));
+            node = &node.children[node.child_index(key)];
+        }
+
+        let value = match node.keys.binary_search(key) {
+            Ok(i) => Some(node.values[i].clone()),
+            Err(_) => None,
+        };
+        pending
+            .into_iter()
+            .rev()
+            .fold(value, |value, message| message.apply(value))
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(K, V)> {
+        let mut out = BTreeMap::new();
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        self.root.collect(&range, &mut out);
+        out.into_iter().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.range(..).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::betree::BeTree;
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_betree_hot_key() {
+        // The key stays in the root's buffer, since other keys never fill
+        // it, so all of its upserts wait there together.
+        let mut tree = BeTree::<u64, u64>::new(8, 16);
+        for key in 0..1000 {
+            tree.insert(key, key);
+        }
+        for _ in 0..200_000 {
+            tree.upsert(1, |old| old.map_or(0, |old| old + 1));
+        }
+        assert_eq!(tree.get(&1), Some(200_001));
+        assert_eq!(tree.range(1..2), [(1, 200_001)]);
+    }
+
+    #[test]
+    fn test_betree() {
+        for (node_size, buffer_size) in [(2, 1), (4, 3), (8, 16)] {
+            let mut tree = BeTree::<u64, u64>::new(node_size, buffer_size);
+            let mut expected = BTreeMap::new();
+            let mut rng = Rng::new();
+
+            for step in 0..5000 {
+                let state = rng.next_u64();
+                let key = state % 400;
+
+                match state % 4 {
+                    0 => {
+                        tree.remove(key);
+                        expected.remove(&key);
+                    }
+                    1 => {
+                        tree.upsert(key, |old| old.map_or(1, |old| old + 1));
+                        *expected.entry(key).or_insert(0) += 1;
+                    }
+                    _ => {
+                        tree.insert(key, step);
+                        expected.insert(key, step);
+                    }
+                }
+                assert_eq!(tree.get(&key), expected.get(&key).copied());
+            }
+
+            let all: Vec<(u64, u64)> = expected.iter().map(|(k, v)| (*k, *v)).collect();
+            assert_eq!(tree.range(..), all);
+            let some: Vec<(u64, u64)> = expected.range(100..=250).map(|(k, v)| (*k, *v)).collect();
+            assert_eq!(tree.range(100..=250), some);
+            assert_eq!(tree.len(), expected.len());
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// A tree with a budget, for use as an ordered cache. Every entry has a
+// weight, one by default or e.g. its size in bytes, and once the entries
+// weigh more than the budget the policy picks which ones to evict.
+//
+// An insert may evict the entry it just added, e.g. a new smallest key under
+// Eviction::Smallest, or any entry heavier than the whole budget.
+
+use std::collections::BTreeMap;
+
+use crate::BTree;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eviction {
+    Smallest,
+    Largest,
+    // The entry least recently inserted or read.
+    LeastRecentlyUsed,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    fn last_key(&self) -> Option<&K> {
+        let mut node = &self.root;
+        while let Some(child) = node.children.last() {
+            node = child;
+        }
+        node.keys.last()
+    }
+}
+
+pub struct BoundedBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    // Each value with the tick it was last used at.
+    tree: BTree<K, (V, u64)>,
+    // Keys by the tick they were last used at.
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+    policy: Eviction,
+    budget: usize,
+    weight: usize,
+    weigh: fn(&K, &V) -> usize,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BoundedBTree<K, V> {
+    // Holds at most `capacity` entries.
+    pub fn new(node_size: usize, capacity: usize, policy: Eviction) -> BoundedBTree<K, V> {
+        BoundedBTree::with_weigher(node_size, capacity, policy, |_, _| 1)
+    }
+
+    // Holds entries weighing at most `budget` in total.
+    pub fn with_weigher(
+        node_size: usize,
+        budget: usize,
+        policy: Eviction,
+        weigh: fn(&K, &V) -> usize,
+    ) -> BoundedBTree<K, V> {
+        BoundedBTree {
+            tree: BTree::new(node_size),
+            recency: BTreeMap::new(),
+            clock: 0,
+            policy,
+            budget,
+            weight: 0,
+            weigh,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old = self.remove(&key);
+
+        self.weight += (self.weigh)(&key, &value);
+        let tick = self.tick();
+        self.recency.insert(tick, key.clone());
+        self.tree.add(key, (value, tick));
+
+        while self.weight > self.budget {
+            let victim = match self.policy {
+                Eviction::Smallest => self.tree.iter().next().map(|(key, _)| key),
+                Eviction::Largest => self.tree.last_key(),
+                Eviction::LeastRecentlyUsed => self.recency.values().next(),
+            };
+            let victim = victim.unwrap().clone();
+            self.remove(&victim);
+        }
+
+        old
+    }
+
+    // Reading an entry counts as using it.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let tick = self.tick();
+        let (value, used) = self.tree.get_mut(key)?;
+        let key = self.recency.remove(used).unwrap();
+        self.recency.insert(tick, key);
+        *used = tick;
+        Some(value)
+    }
+
+    // Looks at an entry without counting it as used.
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.tree.get(key).map(|(value, _)| value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, used) = self.tree.remove(key)?;
+        self.recency.remove(&used);
+        self.weight -= (self.weigh)(key, &value);
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.tree.iter().map(|(key, (value, _))| (key, value))
+    }
+
+    // Total weight of the entries held.
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn len(&self) -> usize {
+        self.recency.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recency.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bounded::{BoundedBTree, Eviction};
+
+    fn keys(tree: &BoundedBTree<u64, String>) -> Vec<u64> {
+        tree.iter().map(|(key, _)| *key).collect()
+    }
+
+    #[test]
+    fn test_bounded_btree() {
+        let mut smallest = BoundedBTree::new(3, 5, Eviction::Smallest);
+        let mut largest = BoundedBTree::new(3, 5, Eviction::Largest);
+        for key in [5, 1, 9, 3, 7, 2, 8, 4, 6, 0] {
+            smallest.insert(key, key.to_string());
+            largest.insert(key, key.to_string());
+        }
+        assert_eq!(keys(&smallest), [5, 6, 7, 8, 9]);
+        assert_eq!(keys(&largest), [0, 1, 2, 3, 4]);
+
+        let mut lru = BoundedBTree::new(3, 3, Eviction::LeastRecentlyUsed);
+        lru.insert(1, "a".to_string());
+        lru.insert(2, "b".to_string());
+        lru.insert(3, "c".to_string());
+        assert_eq!(lru.get(&1), Some(&"a".to_string()));
+        assert_eq!(lru.peek(&2), Some(&"b".to_string()));
+        lru.insert(4, "d".to_string());
+        assert_eq!(keys(&lru), [1, 3, 4]);
+        assert_eq!(lru.insert(3, "e".to_string()), Some("c".to_string()));
+        lru.insert(5, "f".to_string());
+        assert_eq!(keys(&lru), [3, 4, 5]);
+        assert_eq!(lru.remove(&4), Some("d".to_string()));
+        assert_eq!(lru.len(), 2);
+
+        // A byte budget counts the value sizes.
+        let mut bytes =
+            BoundedBTree::with_weigher(3, 10, Eviction::LeastRecentlyUsed, |_, v: &String| v.len());
+        bytes.insert(1, "aaaa".to_string());
+        bytes.insert(2, "bbbb".to_string());
+        bytes.insert(3, "cc".to_string());
+        assert_eq!(bytes.weight(), 10);
+        bytes.insert(4, "d".to_string());
+        assert_eq!(keys(&bytes), [2, 3, 4]);
+        assert_eq!(bytes.weight(), 7);
+        bytes.insert(5, "x".repeat(11));
+        assert!(bytes.is_empty());
+        assert_eq!(bytes.weight(), 0);
+    }
+}
@@ -0,0 +1,636 @@
+// A B+ tree. Internal nodes hold only separator keys, so more of them fit per
+// node, and every value sits in a leaf. Leaves are chained left to right, so
+// a range scan descends once and then walks the chain.
+//
+// Nodes live in an arena and refer to each other by index, which lets a leaf
+// point at its right neighbour without shared ownership.
+//
+// Every node also keeps its fence keys, the bounds its parent's separators
+// put on the keys below it. Scans use them to stop before touching a leaf that
+// starts past their end, and validate uses them to find separators that
+// disagree with what is stored below them.
+
+use std::ops::{Bound, RangeBounds};
+
+type NodeId = usize;
+
+struct Node<K, V> {
+    keys: Vec<K>,
+    // Empty in internal nodes.
+    values: Vec<V>,
+    // Empty in leaves. Child `i` holds the keys below `keys[i]`, and the last
+    // child those at or above the last key.
+    children: Vec<NodeId>,
+    // The next leaf to the right.
+    next: Option<NodeId>,
+    // Every key below this node is at least `low` and below `high`. None is
+    // unbounded.
+    low: Option<K>,
+    high: Option<K>,
+}
+
+impl<K, V> Node<K, V> {
+    fn leaf() -> Node<K, V> {
+        Node {
+            keys: Vec::new(),
+            values: Vec::new(),
+            children: Vec::new(),
+            next: None,
+            low: None,
+            high: None,
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    fn covers(&self, key: &K) -> bool
+    where
+        K: Ord,
+    {
+        self.low.as_ref().is_none_or(|low| key >= low)
+            && self.high.as_ref().is_none_or(|high| key < high)
+    }
+}
+
+// Where validate found a tree inconsistent, and how.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Corruption {
+    pub node: usize,
+    pub problem: &'static str,
+}
+
+pub struct BPlusTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    node_size: usize,
+    nodes: Vec<Node<K, V>>,
+    // Slots of merged-away nodes, reused before the arena grows.
+    free: Vec<NodeId>,
+    root: NodeId,
+    // The last leaf. Keys above every key in the tree are appended to it
+    // directly while it has room, so ascending inserts skip the descent.
+    rightmost: NodeId,
+    len: usize,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BPlusTree<K, V> {
+    // `node_size` is the most keys a node holds.
+    pub fn new(node_size: usize) -> BPlusTree<K, V> {
+        assert!(node_size >= 2, "a node must hold at least two keys");
+
+        BPlusTree {
+            node_size,
+            nodes: vec![Node::leaf()],
+            free: Vec::new(),
+            root: 0,
+            rightmost: 0,
+            len: 0,
+        }
+    }
+
+    fn min_keys(&self) -> usize {
+        self.node_size / 2
+    }
+
+    fn allocate(&mut self, node: Node<K, V>) -> NodeId {
+        match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, id: NodeId) {
+        self.nodes[id] = Node::leaf();
+        self.free.push(id);
+    }
+
+    // Mutable access to two distinct nodes at once.
+    fn pair(&mut self, a: NodeId, b: NodeId) -> (&mut Node<K, V>, &mut Node<K, V>) {
+        assert_ne!(a, b);
+        if a < b {
+            let (left, right) = self.nodes.split_at_mut(b);
+            (&mut left[a], &mut right[0])
+        } else {
+            let (left, right) = self.nodes.split_at_mut(a);
+            (&mut right[0], &mut left[b])
+        }
+    }
+
+    fn child_index(node: &Node<K, V>, key: &K) -> usize {
+        node.keys.partition_point(|k| k <= key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let mut node = &self.nodes[self.root];
+        while !node.is_leaf() {
+            node = &self.nodes[node.children[Self::child_index(node, key)]];
+        }
+
+        let i = node.keys.binary_search(key).ok()?;
+        Some(&node.values[i])
+    }
+
+    fn find_rightmost(&self) -> NodeId {
+        let mut id = self.root;
+        while let Some(&child) = self.nodes[id].children.last() {
+            id = child;
+        }
+        id
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let leaf = &mut self.nodes[self.rightmost];
+        if leaf.keys.len() < self.node_size && leaf.keys.last().is_some_and(|last| key > *last) {
+            leaf.keys.push(key);
+            leaf.values.push(value);
+            self.len += 1;
+            return None;
+        }
+
+        let (old, split) = self.insert_into(self.root, key, value);
+
+        if let Some((separator, right)) = split {
+            let root = Node {
+                keys: vec![separator],
+                values: Vec::new(),
+                children: vec![self.root, right],
+                next: None,
+                low: None,
+                high: None,
+            };
+            self.root = self.allocate(root);
+        }
+        self.rightmost = self.find_rightmost();
+        if old.is_none() {
+            self.len += 1;
+        }
+
+        old
+    }
+
+    // Returns the replaced value, and the separator and new right sibling if
+    // the node split.
+    fn insert_into(&mut self, id: NodeId, key: K, value: V) -> (Option<V>, Option<(K, NodeId)>) {
+        if self.nodes[id].is_leaf() {
+            let node = &mut self.nodes[id];
+            match node.keys.binary_search(&key) {
+                Ok(i) => return (Some(std::mem::replace(&mut node.values[i], value)), None),
+                Err(i) => {
+                    node.keys.insert(i, key);
+                    node.values.insert(i, value);
+                }
+            }
+            if node.keys.len() <= self.node_size {
+                return (None, None);
+            }
+
+            let mid = node.keys.len() / 2;
+            let keys = node.keys.split_off(mid);
+            let separator = keys[0].clone();
+            let right = Node {
+                keys,
+                values: node.values.split_off(mid),
+                children: Vec::new(),
+                next: node.next,
+                low: Some(separator.clone()),
+                high: node.high.replace(separator.clone()),
+            };
+            let right = self.allocate(right);
+            self.nodes[id].next = Some(right);
+            return (None, Some((separator, right)));
+        }
+
+        let i = Self::child_index(&self.nodes[id], &key);
+        let (old, split) = self.insert_into(self.nodes[id].children[i], key, value);
+        let Some((separator, child)) = split else {
+            return (old, None);
+        };
+
+        let node = &mut self.nodes[id];
+        node.keys.insert(i, separator);
+        node.children.insert(i + 1, child);
+        if node.keys.len() <= self.node_size {
+            return (old, None);
+        }
+
+        // The middle key moves up instead of being copied.
+        let mid = node.keys.len() / 2;
+        let keys = node.keys.split_off(mid + 1);
+        let separator = node.keys.pop().unwrap();
+        let right = Node {
+            keys,
+            values: Vec::new(),
+            children: node.children.split_off(mid + 1),
+            next: None,
+            low: Some(separator.clone()),
+            high: node.high.replace(separator.clone()),
+        };
+        (old, Some((separator, self.allocate(right))))
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.remove_from(self.root, key)?;
+        self.len -= 1;
+
+        let root = &self.nodes[self.root];
+        if root.keys.is_empty() && !root.is_leaf() {
+            let old = self.root;
+            self.root = root.children[0];
+            self.release(old);
+        }
+        self.rightmost = self.find_rightmost();
+
+        Some(value)
+    }
+
+    fn remove_from(&mut self, id: NodeId, key: &K) -> Option<V> {
+        let node = &mut self.nodes[id];
+        if node.is_leaf() {
+            let i = node.keys.binary_search(key).ok()?;
+            node.keys.remove(i);
+            return Some(node.values.remove(i));
+        }
+
+        let i = Self::child_index(node, key);
+        let child = node.children[i];
+        let value = self.remove_from(child, key)?;
+        if self.nodes[child].keys.len() < self.min_keys() {
+            self.fix_child(id, i);
+        }
+
+        Some(value)
+    }
+
+    // Refills the underfull child `i` of `parent` from a sibling, or merges it
+    // with one.
+    fn fix_child(&mut self, parent: NodeId, i: usize) {
+        let min_keys = self.min_keys();
+        let children = &self.nodes[parent].children;
+        let child = children[i];
+        let left = i.checked_sub(1).map(|j| children[j]);
+        let right = children.get(i + 1).copied();
+
+        if let Some(left) = left.filter(|&left| self.nodes[left].keys.len() > min_keys) {
+            let separator = self.nodes[parent].keys[i - 1].clone();
+            let (from, to) = self.pair(left, child);
+            let separator = if to.is_leaf() {
+                to.keys.insert(0, from.keys.pop().unwrap());
+                to.values.insert(0, from.values.pop().unwrap());
+                to.keys[0].clone()
+            } else {
+                to.keys.insert(0, separator);
+                to.children.insert(0, from.children.pop().unwrap());
+                from.keys.pop().unwrap()
+            };
+            from.high = Some(separator.clone());
+            to.low = Some(separator.clone());
+            self.nodes[parent].keys[i - 1] = separator;
+            return;
+        }
+
+        if let Some(right) = right.filter(|&right| self.nodes[right].keys.len() > min_keys) {
+            let separator = self.nodes[parent].keys[i].clone();
+            let (from, to) = self.pair(right, child);
+            let separator = if to.is_leaf() {
+                to.keys.push(from.keys.remove(0));
+                to.values.push(from.values.remove(0));
+                from.keys[0].clone()
+            } else {
+                to.keys.push(separator);
+                to.children.push(from.children.remove(0));
+                from.keys.remove(0)
+            };
+            to.high = Some(separator.clone());
+            from.low = Some(separator.clone());
+            self.nodes[parent].keys[i] = separator;
+            return;
+        }
+
+        // Merge the right one of the pair into the left one.
+        let (i, left, right) = match left {
+            Some(left) => (i - 1, left, child),
+            None => (i, child, right.expect("a non-root node has a sibling")),
+        };
+        let separator = self.nodes[parent].keys.remove(i);
+        self.nodes[parent].children.remove(i + 1);
+
+        let (into, from) = self.pair(left, right);
+        if into.is_leaf() {
+            into.keys.append(&mut from.keys);
+            into.values.append(&mut from.values);
+            into.next = from.next;
+        } else {
+            into.keys.push(separator);
+            into.keys.append(&mut from.keys);
+            into.children.append(&mut from.children);
+        }
+        into.high = from.high.take();
+        self.release(right);
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        let mut node = self.root;
+        while !self.nodes[node].is_leaf() {
+            let i = match range.start_bound() {
+                Bound::Included(key) | Bound::Excluded(key) => {
+                    Self::child_index(&self.nodes[node], key)
+                }
+                Bound::Unbounded => 0,
+            };
+            node = self.nodes[node].children[i];
+        }
+
+        let keys = &self.nodes[node].keys;
+        let index = match range.start_bound() {
+            Bound::Included(key) => keys.partition_point(|k| k < key),
+            Bound::Excluded(key) => keys.partition_point(|k| k <= key),
+            Bound::Unbounded => 0,
+        };
+
+        Range {
+            tree: self,
+            leaf: Some(node),
+            index,
+            end: range.end_bound().cloned(),
+        }
+    }
+
+    pub fn iter(&self) -> Range<'_, K, V> {
+        self.range::<(Bound<K>, Bound<K>)>((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    // Checks that every node agrees with the separators above it and that the
+    // leaf chain visits the leaves in order.
+    pub fn validate(&self) -> Result<(), Corruption> {
+        let mut leaves = Vec::new();
+        self.validate_node(self.root, None, None, &mut leaves)?;
+
+        for pair in leaves.windows(2) {
+            if self.nodes[pair[0]].next != Some(pair[1]) {
+                return Err(Corruption {
+                    node: pair[0],
+                    problem: "leaf chain skips a leaf",
+                });
+            }
+        }
+        match leaves.last() {
+            Some(&last) if self.nodes[last].next.is_some() => Err(Corruption {
+                node: last,
+                problem: "leaf chain runs past the last leaf",
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn validate_node(
+        &self,
+        id: NodeId,
+        low: Option<&K>,
+        high: Option<&K>,
+        leaves: &mut Vec<NodeId>,
+    ) -> Result<(), Corruption> {
+        let node = &self.nodes[id];
+        let fail = |problem| Err(Corruption { node: id, problem });
+
+        if node.low.as_ref() != low || node.high.as_ref() != high {
+            return fail("fences disagree with the parent's separators");
+        }
+        if !node.keys.windows(2).all(|pair| pair[0] < pair[1]) {
+            return fail("keys are out of order");
+        }
+        if !node.keys.iter().all(|key| node.covers(key)) {
+            return fail("key lies outside the node's fences");
+        }
+        if node.keys.len() > self.node_size
+            || (id != self.root && node.keys.len() < self.min_keys())
+        {
+            return fail("node holds the wrong number of keys");
+        }
+
+        if node.is_leaf() {
+            leaves.push(id);
+            return Ok(());
+        }
+        if node.children.len() != node.keys.len() + 1 {
+            return fail("node has the wrong number of children");
+        }
+        for (i, &child) in node.children.iter().enumerate() {
+            let low = if i == 0 { low } else { Some(&node.keys[i - 1]) };
+            let high = node.keys.get(i).or(high);
+            self.validate_node(child, low, high, leaves)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+// Walks the leaf chain from the first entry in the range.
+pub struct Range<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    tree: &'a BPlusTree<K, V>,
+    leaf: Option<NodeId>,
+    index: usize,
+    end: Bound<K>,
+}
+
+impl<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Iterator
+    for Range<'a, K, V>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let leaf = &self.tree.nodes[self.leaf?];
+            if self.index == leaf.keys.len() {
+                // A leaf whose keys all lie past the end needn't be read.
+                let next = leaf.next.map(|next| &self.tree.nodes[next]);
+                let past_end = match (&self.end, next.and_then(|next| next.low.as_ref())) {
+                    (Bound::Included(end), Some(low)) => low > end,
+                    (Bound::Excluded(end), Some(low)) => low >= end,
+                    _ => false,
+                };
+                debug_assert!(
+                    next.is_none_or(|next| next.low == leaf.high),
+                    "neighbouring leaves' fences don't meet"
+                );
+
+                self.leaf = if past_end { None } else { leaf.next };
+                self.index = 0;
+                continue;
+            }
+
+            let (key, value) = (&leaf.keys[self.index], &leaf.values[self.index]);
+            let past_end = match &self.end {
+                Bound::Included(end) => key > end,
+                Bound::Excluded(end) => key >= end,
+                Bound::Unbounded => false,
+            };
+            if past_end {
+                self.leaf = None;
+                return None;
+            }
+
+            self.index += 1;
+            return Some((key, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::bplus::{BPlusTree, NodeId};
+    use crate::testutil::Rng;
+
+    // Checks node sizes, key order against the separators and that every leaf
+    // is at the same depth, returning that depth.
+    fn check_node(
+        tree: &BPlusTree<u64, u64>,
+        id: NodeId,
+        low: Option<u64>,
+        high: Option<u64>,
+        leaves: &mut Vec<NodeId>,
+    ) -> usize {
+        let node = &tree.nodes[id];
+        assert_eq!((node.low, node.high), (low, high));
+        assert!(node.keys.len() <= tree.node_size);
+        if id != tree.root {
+            assert!(node.keys.len() >= tree.min_keys());
+        }
+        assert!(node.keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(node
+            .keys
+            .iter()
+            .all(|key| low.is_none_or(|low| *key >= low)));
+        assert!(node
+            .keys
+            .iter()
+            .all(|key| high.is_none_or(|high| *key < high)));
+
+        if node.is_leaf() {
+            assert_eq!(node.keys.len(), node.values.len());
+            leaves.push(id);
+            return 0;
+        }
+
+        assert_eq!(node.children.len(), node.keys.len() + 1);
+        let depths: Vec<usize> = (0..node.children.len())
+            .map(|i| {
+                let low = if i == 0 { low } else { Some(node.keys[i - 1]) };
+                let high = node.keys.get(i).copied().or(high);
+                check_node(tree, node.children[i], low, high, leaves)
+            })
+            .collect();
+        assert!(depths.windows(2).all(|pair| pair[0] == pair[1]));
+        depths[0] + 1
+    }
+
+    fn check_tree(tree: &BPlusTree<u64, u64>) {
+        let mut leaves = Vec::new();
+        check_node(tree, tree.root, None, None, &mut leaves);
+        assert_eq!(tree.validate(), Ok(()));
+
+        let chained: Vec<NodeId> =
+            std::iter::successors(Some(leaves[0]), |&leaf| tree.nodes[leaf].next).collect();
+        assert_eq!(chained, leaves);
+        assert_eq!(tree.rightmost, *leaves.last().unwrap());
+        assert_eq!(
+            tree.nodes.len() - tree.free.len(),
+            reachable(tree, tree.root)
+        );
+    }
+
+    fn reachable(tree: &BPlusTree<u64, u64>, id: NodeId) -> usize {
+        let children = &tree.nodes[id].children;
+        1 + children
+            .iter()
+            .map(|&child| reachable(tree, child))
+            .sum::<usize>()
+    }
+
+    #[test]
+    fn test_bplus_tree() {
+        for node_size in [2, 3, 4, 7] {
+            let mut tree = BPlusTree::<u64, u64>::new(node_size);
+            let mut expected = BTreeMap::new();
+            let mut rng = Rng::new();
+
+            for step in 0..4000 {
+                let state = rng.next_u64();
+                let key = state % 500;
+
+                if state.is_multiple_of(3) {
+                    assert_eq!(tree.remove(&key), expected.remove(&key));
+                } else {
+                    assert_eq!(tree.insert(key, step), expected.insert(key, step));
+                }
+                assert_eq!(tree.get(&key), expected.get(&key));
+                if step % 100 == 0 {
+                    check_tree(&tree);
+                }
+            }
+
+            check_tree(&tree);
+            assert_eq!(tree.len(), expected.len());
+            assert!(tree.iter().eq(expected.iter()));
+            assert!(tree.range(100..=300).eq(expected.range(100..=300)));
+            assert!(tree.range(250..).eq(expected.range(250..)));
+            assert!(tree.range(..0).eq(expected.range(..0)));
+
+            // Moving a separator leaves it disagreeing with the fences below.
+            let root = tree.root;
+            tree.nodes[root].keys[0] += 1;
+            let err = tree.validate().unwrap_err();
+            assert_ne!(err.node, root);
+            assert_eq!(err.problem, "fences disagree with the parent's separators");
+            tree.nodes[root].keys[0] -= 1;
+
+            for key in 0..500 {
+                assert_eq!(tree.remove(&key), expected.remove(&key));
+            }
+            check_tree(&tree);
+            assert!(tree.is_empty());
+            assert_eq!(tree.iter().next(), None);
+        }
+    }
+
+    #[test]
+    fn test_bplus_append() {
+        let mut tree = BPlusTree::<u64, u64>::new(4);
+        let mut expected = BTreeMap::new();
+
+        for key in 0..2000 {
+            assert_eq!(tree.insert(key * 2, key), None);
+            expected.insert(key * 2, key);
+            if key % 7 == 0 {
+                assert_eq!(tree.remove(&(key * 2)), expected.remove(&(key * 2)));
+            }
+            if key % 11 == 0 {
+                // Between existing keys, so not an append.
+                assert_eq!(tree.insert(key + 1, 0), expected.insert(key + 1, 0));
+            }
+            if key % 250 == 0 {
+                check_tree(&tree);
+            }
+        }
+
+        check_tree(&tree);
+        assert!(tree.iter().eq(expected.iter()));
+        assert_eq!(tree.insert(3998, 1), expected.insert(3998, 1));
+        assert_eq!(tree.get(&3998), Some(&1));
+    }
+}
@@ -0,0 +1,183 @@
+// B*-tree insertion. A node that overflows first shares its entries with a
+// sibling that has room. Only when both neighbours are full does it split,
+// and then two full nodes become three, so nodes stay about two thirds full
+// instead of half full and the tree stays lower for the same data.
+
+use std::sync::Arc;
+
+use crate::{BTree, BTreeNode};
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTreeNode<K, V> {
+    // Inserts below this node, leaving it with one key too many if it
+    // overflows, for the parent to fix.
+    fn add_dense_recursive(&mut self, key: K, value: V) {
+        let i = BTreeNode::<K, V>::find_it(&self.keys, &key) as usize;
+
+        if self.children.is_empty() {
+            self.keys.insert(i, key);
+            self.values.insert(i, value);
+            return;
+        }
+
+        Arc::make_mut(&mut self.children[i]).add_dense_recursive(key, value);
+        if self.children[i].keys.len() > self.node_size {
+            self.fix_overflow(i);
+        }
+    }
+
+    fn fix_overflow(&mut self, index: usize) {
+        let has_room = |i: usize| self.children[i].keys.len() < self.node_size;
+        let last = self.children.len() - 1;
+
+        if index > 0 && has_room(index - 1) {
+            self.redistribute(index - 1, 2, 2);
+        } else if index < last && has_room(index + 1) {
+            self.redistribute(index, 2, 2);
+        } else if index < last {
+            self.redistribute(index, 2, 3);
+        } else if index > 0 {
+            self.redistribute(index - 1, 2, 3);
+        } else {
+            self.redistribute(index, 1, 2);
+        }
+    }
+
+    // Replaces the `from` children starting at `first`, and the separators
+    // between them, with `to` children sharing the same entries evenly.
+    fn redistribute(&mut self, first: usize, from: usize, to: usize) {
+        let mut entries = Vec::new();
+        let mut grandchildren = Vec::new();
+
+        let children: Vec<_> = self.children.drain(first..first + from).collect();
+        let mut separators = self
+            .keys
+            .drain(first..first + from - 1)
+            .zip(self.values.drain(first..first + from - 1));
+        for child in children {
+            let child = Arc::unwrap_or_clone(child);
+            entries.extend(child.keys.into_iter().zip(child.values));
+            grandchildren.extend(child.children);
+            entries.extend(separators.next());
+        }
+        drop(separators);
+
+        let leaf = grandchildren.is_empty();
+        let total = entries.len() - (to - 1);
+        let mut entries = entries.into_iter();
+        let mut grandchildren = grandchildren.into_iter();
+
+        for j in 0..to {
+            let size = total / to + usize::from(j < total % to);
+
+            let mut node = BTreeNode::<K, V>::new(self.node_size);
+            for (key, value) in entries.by_ref().take(size) {
+                node.keys.push(key);
+                node.values.push(value);
+            }
+            if !leaf {
+                node.children.extend(grandchildren.by_ref().take(size + 1));
+            }
+            self.children.insert(first + j, Arc::new(node));
+
+            if j < to - 1 {
+                let (key, value) = entries.next().unwrap();
+                self.keys.insert(first + j, key);
+                self.values.insert(first + j, value);
+            }
+        }
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    // Like add, but splits the way a B*-tree does.
+    pub fn add_dense(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(slot) = self.root.get_mut(&key) {
+            return Some(std::mem::replace(slot, value));
+        }
+
+        self.root.add_dense_recursive(key, value);
+        if self.root.keys.len() > self.root.node_size {
+            // The root has no siblings, so it splits in two under a new root.
+            let root = BTreeNode::<K, V>::new(self.root.node_size);
+            let old = std::mem::replace(&mut self.root, root);
+            self.root.children.push(Arc::new(old));
+            self.root.redistribute(0, 1, 2);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::testutil::Rng;
+    use crate::{BTree, BTreeNode};
+
+    // Checks node sizes and key order, returning the number of nodes and the
+    // depth of the leaves, which must all be the same.
+    fn check(node: &BTreeNode<u64, u64>, root: bool) -> (usize, usize) {
+        assert!(node.keys.len() <= node.node_size);
+        assert!(root || node.keys.len() >= node.min_keys());
+        assert!(node.keys.windows(2).all(|pair| pair[0] < pair[1]));
+        if node.children.is_empty() {
+            return (1, 0);
+        }
+
+        assert_eq!(node.children.len(), node.keys.len() + 1);
+        let mut nodes = 1;
+        let mut depths = Vec::new();
+        for (i, child) in node.children.iter().enumerate() {
+            assert!(i == 0 || child.keys[0] > node.keys[i - 1]);
+            assert!(i == node.keys.len() || *child.keys.last().unwrap() < node.keys[i]);
+            let (count, depth) = check(child, false);
+            nodes += count;
+            depths.push(depth);
+        }
+        assert!(depths.windows(2).all(|pair| pair[0] == pair[1]));
+        (nodes, depths[0] + 1)
+    }
+
+    #[test]
+    fn test_add_dense() {
+        for node_size in [2, 3, 4, 9] {
+            let mut tree = BTree::<u64, u64>::new(node_size);
+            let mut expected = BTreeMap::new();
+            let mut rng = Rng::new();
+
+            for step in 0..3000 {
+                let state = rng.next_u64();
+                let key = state % 2000;
+
+                if state.is_multiple_of(5) {
+                    assert_eq!(tree.remove(&key), expected.remove(&key));
+                } else {
+                    assert_eq!(tree.add_dense(key, step), expected.insert(key, step));
+                }
+                if step % 100 == 0 {
+                    check(&tree.root, true);
+                }
+            }
+
+            check(&tree.root, true);
+            assert!(tree.iter().eq(expected.iter()));
+        }
+
+        // Ascending inserts leave plain splits with half-full nodes.
+        let mut plain = BTree::<u64, u64>::new(8);
+        let mut dense = BTree::<u64, u64>::new(8);
+        for key in 0..5000 {
+            plain.add(key, key);
+            dense.add_dense(key, key);
+        }
+        let (plain_nodes, _) = check(&plain.root, true);
+        let (dense_nodes, _) = check(&dense.root, true);
+        assert!(
+            dense_nodes * 4 < plain_nodes * 3,
+            "{} nodes, {} with plain splits",
+            dense_nodes,
+            plain_nodes
+        );
+    }
+}
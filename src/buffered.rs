@@ -0,0 +1,168 @@
+// A small sorted write buffer in front of a tree. Writes land in the buffer
+// and reach the tree in bulk once it fills, in key order, so consecutive
+// writes mostly touch nodes the previous one just did. Reads look at the
+// buffer first and then at the tree.
+
+use std::collections::{btree_map, BTreeMap};
+use std::iter::Peekable;
+use std::ops::{Bound, RangeBounds};
+
+use crate::{BTree, Range};
+
+pub struct BufferedBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    tree: BTree<K, V>,
+    // Pending value of every buffered key, None for a removal.
+    buffer: BTreeMap<K, Option<V>>,
+    capacity: usize,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BufferedBTree<K, V> {
+    // Buffers up to `capacity` keys before merging them into the tree.
+    pub fn new(node_size: usize, capacity: usize) -> BufferedBTree<K, V> {
+        BufferedBTree {
+            tree: BTree::new(node_size),
+            buffer: BTreeMap::new(),
+            capacity,
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old = self.get(&key).cloned();
+        self.write(key, Some(value));
+        old
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let old = self.get(key).cloned();
+        if old.is_some() {
+            self.write(key.clone(), None);
+        }
+        old
+    }
+
+    fn write(&mut self, key: K, write: Option<V>) {
+        self.buffer.insert(key, write);
+        if self.buffer.len() >= self.capacity {
+            self.flush();
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        match self.buffer.get(key) {
+            Some(write) => write.as_ref(),
+            None => self.tree.get(key),
+        }
+    }
+
+    // Merges the buffer into the tree.
+    pub fn flush(&mut self) {
+        for (key, write) in std::mem::take(&mut self.buffer) {
+            match write {
+                Some(value) => {
+                    self.tree.add(key, value);
+                }
+                None => {
+                    self.tree.remove(&key);
+                }
+            }
+        }
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Merge<'_, K, V> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        Merge {
+            buffer: self.buffer.range(bounds.clone()).peekable(),
+            tree: self.tree.range(bounds).peekable(),
+        }
+    }
+
+    pub fn iter(&self) -> Merge<'_, K, V> {
+        self.range::<(Bound<K>, Bound<K>)>((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+// Entries of the buffer and the tree in key order, with buffered writes
+// shadowing the tree's entries for the same key.
+pub struct Merge<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    buffer: Peekable<btree_map::Range<'a, K, Option<V>>>,
+    tree: Peekable<Range<'a, K, V>>,
+}
+
+impl<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Iterator
+    for Merge<'a, K, V>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let buffered = self.buffer.peek().map(|(key, _)| *key);
+            let stored = self.tree.peek().map(|(key, _)| *key);
+            let from_buffer = match (buffered, stored) {
+                (Some(buffered), Some(key)) => {
+                    if buffered == key {
+                        self.tree.next();
+                    }
+                    buffered <= key
+                }
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+
+            if !from_buffer {
+                return self.tree.next();
+            }
+            if let (key, Some(value)) = self.buffer.next().unwrap() {
+                return Some((key, value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::buffered::BufferedBTree;
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_buffered_btree() {
+        let mut tree = BufferedBTree::<u64, u64>::new(4, 16);
+        let mut expected = BTreeMap::new();
+        let mut rng = Rng::new();
+
+        for _ in 0..5000 {
+            let state = rng.next_u64();
+            let key = state % 300;
+
+            if state.is_multiple_of(3) {
+                assert_eq!(tree.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(tree.insert(key, state), expected.insert(key, state));
+            }
+            assert!(tree.buffered() < 16);
+            assert_eq!(tree.get(&key), expected.get(&key));
+        }
+
+        assert!(tree.iter().eq(expected.iter()));
+        assert!(tree.range(100..200).eq(expected.range(100..200)));
+        assert_eq!(tree.len(), expected.len());
+
+        tree.flush();
+        assert_eq!(tree.buffered(), 0);
+        assert!(tree.iter().eq(expected.iter()));
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::{self, Read, Write};
+
+pub trait Codec: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(bytes: &[u8]) -> io::Result<Self>;
+}
+
+macro_rules! int_codec {
+    ($($t:ty),*) => {
+        $(
+            impl Codec for $t {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> io::Result<Self> {
+                    match bytes.try_into() {
+                        Ok(bytes) => Ok(<$t>::from_le_bytes(bytes)),
+                        Err(_) => Err(invalid_data(concat!("bad length for ", stringify!($t)))),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+int_codec!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl Codec for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid_data("string is not valid utf-8"))
+    }
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+pub fn invalid_data<M: Into<String>>(message: M) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+pub fn write_item<W: Write, T: Codec>(out: &mut W, item: &T, buf: &mut Vec<u8>) -> io::Result<()> {
+    buf.clear();
+    item.encode(buf);
+
+    let len = u32::try_from(buf.len()).map_err(|_| invalid_data("item too large"))?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(buf)
+}
+
+pub fn read_item<R: Read, T: Codec>(input: &mut R, buf: &mut Vec<u8>) -> io::Result<T> {
+    let mut len = [0; 4];
+    input.read_exact(&mut len)?;
+
+    // The length comes from the input, so the item is read as it arrives
+    // rather than allocated up front.
+    let len = u32::from_le_bytes(len) as u64;
+    buf.clear();
+    if input.take(len).read_to_end(buf)? as u64 != len {
+        return Err(invalid_data("item is cut short"));
+    }
+
+    T::decode(buf)
+}
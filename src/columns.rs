@@ -0,0 +1,127 @@
+// Several named columns per key, each kept in a tree of its own. Writing one
+// column of a row touches only that column's tree, so a partial update
+// doesn't clone or rewrite the rest of the row, and a scan over one column
+// doesn't read the others.
+
+use std::collections::BTreeMap;
+use std::ops::RangeBounds;
+
+use crate::{BTree, Range};
+
+pub struct ColumnFamilies<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    node_size: usize,
+    // A column is created by its first write and kept until dropped, even
+    // once empty.
+    columns: BTreeMap<String, BTree<K, V>>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> ColumnFamilies<K, V> {
+    pub fn new(node_size: usize) -> ColumnFamilies<K, V> {
+        ColumnFamilies {
+            node_size,
+            columns: BTreeMap::new(),
+        }
+    }
+
+    pub fn put(&mut self, column: &str, key: K, value: V) -> Option<V> {
+        if !self.columns.contains_key(column) {
+            self.columns
+                .insert(column.to_string(), BTree::new(self.node_size));
+        }
+        self.columns.get_mut(column).unwrap().add(key, value)
+    }
+
+    pub fn get(&self, column: &str, key: &K) -> Option<&V> {
+        self.columns.get(column)?.get(key)
+    }
+
+    pub fn delete(&mut self, column: &str, key: &K) -> Option<V> {
+        self.columns.get_mut(column)?.remove(key)
+    }
+
+    // Every column `key` has a value in, by column name.
+    pub fn row(&self, key: &K) -> Vec<(&str, &V)> {
+        self.columns
+            .iter()
+            .filter_map(|(name, tree)| Some((name.as_str(), tree.get(key)?)))
+            .collect()
+    }
+
+    // Deletes `key` from every column, returning how many held it.
+    pub fn delete_row(&mut self, key: &K) -> usize {
+        self.columns
+            .values_mut()
+            .filter_map(|tree| tree.remove(key))
+            .count()
+    }
+
+    // One column's entries in `range`, or None for an unknown column.
+    pub fn scan<R: RangeBounds<K>>(&self, column: &str, range: R) -> Option<Range<'_, K, V>> {
+        Some(self.columns.get(column)?.range(range))
+    }
+
+    pub fn columns(&self) -> impl Iterator<Item = &str> {
+        self.columns.keys().map(String::as_str)
+    }
+
+    // Removes a column and everything in it.
+    pub fn drop_column(&mut self, column: &str) -> bool {
+        self.columns.remove(column).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::columns::ColumnFamilies;
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_column_families() {
+        let mut table = ColumnFamilies::<u64, u64>::new(3);
+        let mut expected = BTreeMap::new();
+        let names = ["balance", "email", "name"];
+        let mut rng = Rng::new();
+
+        for _ in 0..3000 {
+            let state = rng.next_u64();
+            let column = names[(state % 3) as usize];
+            let key = (state >> 8) % 200;
+            if state.is_multiple_of(5) {
+                assert_eq!(table.delete(column, &key), expected.remove(&(column, key)));
+            } else {
+                assert_eq!(
+                    table.put(column, key, state >> 32),
+                    expected.insert((column, key), state >> 32)
+                );
+            }
+        }
+
+        assert!(table.columns().eq(names));
+        for key in 0..200 {
+            let want: Vec<(&str, &u64)> = names
+                .iter()
+                .filter_map(|name| Some((*name, expected.get(&(*name, key))?)))
+                .collect();
+            assert_eq!(table.row(&key), want);
+        }
+        let email: Vec<(&u64, &u64)> = table.scan("email", 50..100).unwrap().collect();
+        let want: Vec<(&u64, &u64)> = expected
+            .iter()
+            .filter(|((name, key), _)| *name == "email" && (50..100).contains(key))
+            .map(|((_, key), value)| (key, value))
+            .collect();
+        assert_eq!(email, want);
+        assert!(table.scan("phone", ..).is_none());
+
+        let held = table.row(&7).len();
+        assert_eq!(table.delete_row(&7), held);
+        assert!(table.row(&7).is_empty());
+
+        assert!(table.drop_column("email"));
+        assert!(!table.drop_column("email"));
+        assert_eq!(table.get("email", &1), None);
+        assert!(table.columns().eq(["balance", "name"]));
+    }
+}
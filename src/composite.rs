@@ -0,0 +1,254 @@
+// Multi-column keys. A tuple key is stored as the concatenated encodings of
+// its components, each chosen so that byte order matches the component's own
+// order and no encoding is a prefix of another. Byte order then matches
+// tuple order, and all keys sharing their leading components sit next to
+// each other, which is what scan_prefix walks.
+
+use std::io;
+use std::marker::PhantomData;
+
+use crate::codec::invalid_data;
+use crate::BTree;
+
+pub trait KeyPart: Sized {
+    fn encode_part(&self, out: &mut Vec<u8>);
+    // Reads one part off the front of `input`.
+    fn decode_part(input: &mut &[u8]) -> io::Result<Self>;
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(invalid_data("key part is truncated"));
+    }
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+    Ok(bytes)
+}
+
+// Unsigned integers are big-endian. Signed ones have the sign bit flipped
+// first so negative numbers sort below positive ones.
+macro_rules! int_part {
+    ($($t:ty => $flip:expr),*) => {
+        $(
+            impl KeyPart for $t {
+                fn encode_part(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&(self ^ $flip).to_be_bytes());
+                }
+
+                fn decode_part(input: &mut &[u8]) -> io::Result<Self> {
+                    let bytes = take(input, std::mem::size_of::<$t>())?;
+                    Ok(<$t>::from_be_bytes(bytes.try_into().unwrap()) ^ $flip)
+                }
+            }
+        )*
+    };
+}
+
+int_part!(u8 => 0, u16 => 0, u32 => 0, u64 => 0, i8 => i8::MIN, i16 => i16::MIN, i32 => i32::MIN, i64 => i64::MIN);
+
+// Byte strings escape each zero byte as 0x00 0xff and end with 0x00 0x01, so
+// a string sorts before any longer string it is a prefix of.
+impl KeyPart for Vec<u8> {
+    fn encode_part(&self, out: &mut Vec<u8>) {
+        for &byte in self {
+            out.push(byte);
+            if byte == 0 {
+                out.push(0xff);
+            }
+        }
+        out.extend_from_slice(&[0, 1]);
+    }
+
+    fn decode_part(input: &mut &[u8]) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        loop {
+            match take(input, 1)?[0] {
+                0 => match take(input, 1)?[0] {
+                    0xff => bytes.push(0),
+                    1 => return Ok(bytes),
+                    _ => return Err(invalid_data("bad escape in key part")),
+                },
+                byte => bytes.push(byte),
+            }
+        }
+    }
+}
+
+impl KeyPart for String {
+    fn encode_part(&self, out: &mut Vec<u8>) {
+        self.as_bytes().to_vec().encode_part(out);
+    }
+
+    fn decode_part(input: &mut &[u8]) -> io::Result<Self> {
+        String::from_utf8(Vec::decode_part(input)?)
+            .map_err(|_| invalid_data("string is not valid utf-8"))
+    }
+}
+
+pub trait CompositeKey: Sized {
+    fn encode_key(&self) -> Vec<u8>;
+    fn decode_key(bytes: &[u8]) -> io::Result<Self>;
+}
+
+macro_rules! tuple_key {
+    ($($name:ident),+) => {
+        impl<$($name: KeyPart),+> CompositeKey for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self) -> Vec<u8> {
+                let ($($name,)+) = self;
+                let mut out = Vec::new();
+                $($name.encode_part(&mut out);)+
+                out
+            }
+
+            fn decode_key(mut bytes: &[u8]) -> io::Result<Self> {
+                let key = ($($name::decode_part(&mut bytes)?,)+);
+                if !bytes.is_empty() {
+                    return Err(invalid_data("trailing bytes after key"));
+                }
+                Ok(key)
+            }
+        }
+    };
+}
+
+tuple_key!(A);
+tuple_key!(A, B);
+tuple_key!(A, B, C);
+tuple_key!(A, B, C, D);
+
+// Tuples made of the leading components of K, which scan_prefix accepts.
+pub trait PrefixOf<K>: CompositeKey {}
+
+impl<A: KeyPart> PrefixOf<(A,)> for (A,) {}
+impl<A: KeyPart, B: KeyPart> PrefixOf<(A, B)> for (A,) {}
+impl<A: KeyPart, B: KeyPart> PrefixOf<(A, B)> for (A, B) {}
+impl<A: KeyPart, B: KeyPart, C: KeyPart> PrefixOf<(A, B, C)> for (A,) {}
+impl<A: KeyPart, B: KeyPart, C: KeyPart> PrefixOf<(A, B, C)> for (A, B) {}
+impl<A: KeyPart, B: KeyPart, C: KeyPart> PrefixOf<(A, B, C)> for (A, B, C) {}
+impl<A: KeyPart, B: KeyPart, C: KeyPart, D: KeyPart> PrefixOf<(A, B, C, D)> for (A,) {}
+impl<A: KeyPart, B: KeyPart, C: KeyPart, D: KeyPart> PrefixOf<(A, B, C, D)> for (A, B) {}
+impl<A: KeyPart, B: KeyPart, C: KeyPart, D: KeyPart> PrefixOf<(A, B, C, D)> for (A, B, C) {}
+impl<A: KeyPart, B: KeyPart, C: KeyPart, D: KeyPart> PrefixOf<(A, B, C, D)> for (A, B, C, D) {}
+
+// A tree keyed by tuples, usable as a multi-column index.
+pub struct CompositeIndex<K: CompositeKey, V: Ord + Clone + std::fmt::Debug> {
+    tree: BTree<Vec<u8>, V>,
+    keys: PhantomData<K>,
+}
+
+impl<K: CompositeKey, V: Ord + Clone + std::fmt::Debug> CompositeIndex<K, V> {
+    pub fn new(node_size: usize) -> CompositeIndex<K, V> {
+        CompositeIndex {
+            tree: BTree::new(node_size),
+            keys: PhantomData,
+        }
+    }
+
+    pub fn insert(&mut self, key: &K, value: V) -> Option<V> {
+        self.tree.add(key.encode_key(), value)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.tree.get(&key.encode_key())
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.tree.remove(&key.encode_key())
+    }
+
+    // Entries whose keys start with `prefix`, in key order.
+    pub fn scan_prefix<P: PrefixOf<K>>(&self, prefix: &P) -> impl Iterator<Item = (K, &V)> + '_ {
+        self.tree
+            .scan_prefix(&prefix.encode_key())
+            .map(|(key, value)| {
+                (
+                    K::decode_key(key).expect("the index encoded this key"),
+                    value,
+                )
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::composite::{CompositeIndex, CompositeKey};
+
+    #[test]
+    fn test_composite_index() {
+        let names = ["", "a", "a\0", "a\0b", "ab", "b", "\u{e9}"];
+        let mut index = CompositeIndex::<(i64, String, u32), usize>::new(4);
+        let mut expected = BTreeMap::new();
+
+        for (i, user) in [-300i64, -1, 0, 7, 1 << 40].into_iter().enumerate() {
+            for (j, name) in names.iter().enumerate() {
+                for seq in [0u32, 1, 256, u32::MAX] {
+                    let key = (user, name.to_string(), seq);
+                    let value = i * 100 + j * 10 + seq as usize % 10;
+                    assert_eq!(index.insert(&key, value), None);
+                    expected.insert(key, value);
+                }
+            }
+        }
+
+        // Byte order of the encoding is tuple order.
+        let ordered: Vec<(i64, String, u32)> = index
+            .tree
+            .iter()
+            .map(|(key, _)| CompositeKey::decode_key(key).unwrap())
+            .collect();
+        assert!(ordered.iter().eq(expected.keys()));
+
+        let all: Vec<_> = index
+            .scan_prefix(&(-300i64,))
+            .chain(index.scan_prefix(&(-1i64,)))
+            .collect();
+        let want: Vec<_> = expected
+            .iter()
+            .filter(|((user, _, _), _)| *user < 0)
+            .map(|(k, v)| (k.clone(), v))
+            .collect();
+        assert_eq!(all, want);
+
+        let rows: Vec<_> = index
+            .scan_prefix(&(7i64, "a".to_string()))
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(
+            rows,
+            [0, 1, 256, u32::MAX].map(|seq| (7, "a".to_string(), seq))
+        );
+        assert_eq!(index.scan_prefix(&(7i64, "c".to_string())).count(), 0);
+        assert_eq!(
+            index.scan_prefix(&(0i64, "ab".to_string(), 256u32)).count(),
+            1
+        );
+
+        assert_eq!(
+            index.get(&(0, "a\0b".to_string(), 1)),
+            expected.get(&(0, "a\0b".to_string(), 1))
+        );
+        assert_eq!(
+            index.remove(&(0, "a\0b".to_string(), 1)),
+            expected.remove(&(0, "a\0b".to_string(), 1))
+        );
+        assert_eq!(index.len(), expected.len());
+
+        let key = (u64::MAX, b"\0\0\xff".to_vec());
+        assert_eq!(
+            <(u64, Vec<u8>)>::decode_key(&key.encode_key()).unwrap(),
+            key
+        );
+        assert!(<(u64, Vec<u8>)>::decode_key(&[1, 2, 3]).is_err());
+    }
+}
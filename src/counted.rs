@@ -0,0 +1,226 @@
+// A B-tree whose nodes also know how many entries are below them. Finding
+// the entry at a given position then only needs one descent, which is what
+// quantiles, sampling and partitioning are built on. The counts are an
+// AggregateBTree's Count summaries.
+
+use std::collections::BTreeSet;
+use std::ops::Bound;
+
+use crate::aggregate::{AggregateBTree, Count};
+
+pub struct CountedBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    tree: AggregateBTree<K, V, Count>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> CountedBTree<K, V> {
+    pub fn new(node_size: usize) -> CountedBTree<K, V> {
+        CountedBTree {
+            tree: AggregateBTree::new(node_size),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.tree.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.tree.remove(key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.tree.get(key)
+    }
+
+    // The entry with `index` smaller keys before it.
+    pub fn nth(&self, mut index: usize) -> Option<(&K, &V)> {
+        let mut node = self.tree.root();
+        'descend: loop {
+            for (i, child) in node.children.iter().enumerate() {
+                if index < child.summary.0 {
+                    node = child;
+                    continue 'descend;
+                }
+                index -= child.summary.0;
+                if i == node.keys.len() {
+                    return None;
+                }
+                if index == 0 {
+                    return Some((&node.keys[i], &node.values[i]));
+                }
+                index -= 1;
+            }
+            return node.keys.get(index).zip(node.values.get(index));
+        }
+    }
+
+    // How many keys are smaller than `key`.
+    pub fn rank(&self, key: &K) -> usize {
+        let Count(rank) = self
+            .tree
+            .aggregate_range::<(Bound<&K>, Bound<&K>)>((Bound::Unbounded, Bound::Excluded(key)));
+        rank
+    }
+
+    // The key at fraction `q` of the way through the keys, rounded to the
+    // nearest one, so 0.0 is the smallest key, 0.5 the median and 1.0 the
+    // largest.
+    pub fn quantile(&self, q: f64) -> Option<&K> {
+        assert!((0.0..=1.0).contains(&q), "quantile must be between 0 and 1");
+
+        let last = self.len().checked_sub(1)?;
+        let index = (q * last as f64).round() as usize;
+        self.nth(index).map(|(key, _)| key)
+    }
+
+    // `n` distinct keys chosen uniformly at random, or every key if there
+    // aren't that many, in key order. `rng` returns uniformly random numbers.
+    pub fn sample(&self, n: usize, rng: &mut impl FnMut() -> u64) -> Vec<&K> {
+        let len = self.len();
+        let n = n.min(len);
+
+        // Floyd's algorithm picks the positions without a pass over the keys.
+        let mut picked = BTreeSet::new();
+        for j in len - n..len {
+            let position = (rng() % (j as u64 + 1)) as usize;
+            if !picked.insert(position) {
+                picked.insert(j);
+            }
+        }
+
+        picked
+            .into_iter()
+            .map(|position| self.nth(position).unwrap().0)
+            .collect()
+    }
+
+    // Splits the key space into `n` ranges holding about as many entries
+    // each, in key order, or into one per entry if there are fewer. The
+    // first and last are unbounded, so together they also cover keys
+    // inserted later.
+    pub fn partition(&self, n: usize) -> Vec<(Bound<K>, Bound<K>)> {
+        assert!(n >= 1, "there must be at least one partition");
+
+        let n = n.min(self.len()).max(1);
+        let mut start = Bound::Unbounded;
+        let mut ranges = Vec::with_capacity(n);
+        for i in 1..n {
+            let (cut, _) = self.nth(i * self.len() / n).unwrap();
+            ranges.push((start, Bound::Excluded(cut.clone())));
+            start = Bound::Included(cut.clone());
+        }
+        ranges.push((start, Bound::Unbounded));
+        ranges
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::ops::{Bound, RangeBounds};
+
+    use crate::aggregate::{Count, Node};
+    use crate::counted::CountedBTree;
+    use crate::testutil::Rng;
+
+    // Checks counts and node sizes, returning the depth of the leaves.
+    fn check(node: &Node<u64, u64, Count>, node_size: usize, root: bool) -> usize {
+        assert!(node.keys.len() <= node_size);
+        assert!(root || node.keys.len() >= node_size / 2);
+
+        let below: usize = node.children.iter().map(|child| child.summary.0).sum();
+        assert_eq!(node.summary, Count(node.keys.len() + below));
+
+        let depths: Vec<usize> = node
+            .children
+            .iter()
+            .map(|child| check(child, node_size, false))
+            .collect();
+        assert!(depths.windows(2).all(|pair| pair[0] == pair[1]));
+        depths.first().map_or(0, |depth| depth + 1)
+    }
+
+    #[test]
+    fn test_counted_btree() {
+        for node_size in [2, 3, 6] {
+            let mut tree = CountedBTree::<u64, u64>::new(node_size);
+            let mut expected = BTreeMap::new();
+            let mut rng = Rng::new();
+
+            for step in 0..3000 {
+                let key = rng.next_u64() % 1000;
+                if key.is_multiple_of(3) {
+                    assert_eq!(tree.remove(&key), expected.remove(&key));
+                } else {
+                    assert_eq!(tree.insert(key, step), expected.insert(key, step));
+                }
+                assert_eq!(tree.get(&key), expected.get(&key));
+
+                if step % 100 == 0 {
+                    check(tree.tree.root(), node_size, true);
+                    let keys: Vec<&u64> = expected.keys().collect();
+                    let index = rng.next_u64() as usize % (keys.len() + 1);
+                    assert_eq!(
+                        tree.nth(index).map(|(key, _)| key),
+                        keys.get(index).copied()
+                    );
+                    assert_eq!(tree.rank(&key), expected.range(..key).count());
+                }
+            }
+
+            assert_eq!(tree.len(), expected.len());
+            let keys: Vec<&u64> = expected.keys().collect();
+            for (i, key) in keys.iter().enumerate() {
+                assert_eq!(tree.nth(i), Some((*key, &expected[key])));
+                assert_eq!(tree.rank(key), i);
+            }
+            assert_eq!(tree.nth(keys.len()), None);
+
+            assert_eq!(tree.quantile(0.0), keys.first().copied());
+            assert_eq!(tree.quantile(1.0), keys.last().copied());
+            let median = ((keys.len() - 1) as f64 * 0.5).round() as usize;
+            assert_eq!(tree.quantile(0.5), Some(keys[median]));
+
+            let sample = tree.sample(50, &mut || rng.next_u64());
+            assert_eq!(sample.len(), 50);
+            assert!(sample.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(sample.iter().all(|key| expected.contains_key(key)));
+            assert_eq!(tree.sample(keys.len() + 10, &mut || rng.next_u64()), keys);
+        }
+
+        let mut tree = CountedBTree::<u64, u64>::new(4);
+        for key in 0..1000 {
+            tree.insert(key * 3, key);
+        }
+        for n in [1, 3, 7, 1000, 5000] {
+            let ranges = tree.partition(n);
+            assert_eq!(ranges.len(), n.min(1000));
+            let sizes: Vec<usize> = ranges
+                .iter()
+                .map(|range| {
+                    (0..1000u64)
+                        .filter(|key| range.contains(&(key * 3)))
+                        .count()
+                })
+                .collect();
+            assert_eq!(sizes.iter().sum::<usize>(), 1000);
+            assert!(sizes
+                .iter()
+                .all(|size| size.abs_diff(1000 / ranges.len()) <= 1));
+        }
+        assert!(tree.partition(4)[0].contains(&0));
+        assert!(tree.partition(4)[3].contains(&u64::MAX));
+
+        let empty = CountedBTree::<u64, u64>::new(4);
+        assert_eq!(empty.partition(4), [(Bound::Unbounded, Bound::Unbounded)]);
+        assert_eq!(empty.quantile(0.5), None);
+        assert!(empty.sample(3, &mut || 7).is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+// Moving entries in and out as CSV, for spreadsheets and data pipelines.
+// Keys and values are written with Display and read back with FromStr,
+// under a key,value header. Fields holding a comma, a quote or a line break
+// are quoted, with quotes doubled, as RFC 4180 describes.
+
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use crate::codec::invalid_data;
+use crate::BTree;
+
+const HEADER: [&str; 2] = ["key", "value"];
+
+// Writes entries as they come, holding only the current row.
+pub struct CsvWriter<W: Write> {
+    out: W,
+    row: String,
+}
+
+impl<W: Write> CsvWriter<W> {
+    // Writes the header row.
+    pub fn new(out: W) -> io::Result<CsvWriter<W>> {
+        let mut writer = CsvWriter {
+            out,
+            row: String::new(),
+        };
+        writer.write(&HEADER[0], &HEADER[1])?;
+        Ok(writer)
+    }
+
+    fn push_field(&mut self, field: &str) {
+        if field.contains([',', '"', '\n', '\r']) {
+            self.row.push('"');
+            self.row.push_str(&field.replace('"', "\"\""));
+            self.row.push('"');
+        } else {
+            self.row.push_str(field);
+        }
+    }
+
+    pub fn write<K: Display, V: Display>(&mut self, key: &K, value: &V) -> io::Result<()> {
+        self.row.clear();
+        self.push_field(&key.to_string());
+        self.row.push(',');
+        self.push_field(&value.to_string());
+        self.row.push_str("\r\n");
+        self.out.write_all(self.row.as_bytes())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// Splits one record into fields, or returns None if it ends inside a quoted
+// field and so continues on the next line.
+fn parse_record(text: &str) -> io::Result<Option<Vec<String>>> {
+    let mut fields = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    None => return Ok(None),
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                }
+            }
+            if !matches!(chars.peek(), None | Some(',')) {
+                return Err(invalid_data("characters after a quoted CSV field"));
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ',' {
+                    break;
+                }
+                if c == '"' {
+                    return Err(invalid_data("quote inside an unquoted CSV field"));
+                }
+                field.push(c);
+                chars.next();
+            }
+        }
+        fields.push(field);
+
+        if chars.next().is_none() {
+            return Ok(Some(fields));
+        }
+    }
+}
+
+// Reads the next record, which may span lines, or None at the end. Blank
+// lines between records are skipped; a written record is never empty, since
+// it always has the comma between its two fields.
+fn read_record<R: BufRead>(input: &mut R, text: &mut String) -> io::Result<Option<Vec<String>>> {
+    text.clear();
+    loop {
+        if input.read_line(text)? == 0 {
+            if text.is_empty() {
+                return Ok(None);
+            }
+            return Err(invalid_data("unterminated quoted CSV field"));
+        }
+
+        let record = text.strip_suffix('\n').unwrap_or(text);
+        let record = record.strip_suffix('\r').unwrap_or(record);
+        if record.is_empty() {
+            text.clear();
+            continue;
+        }
+        if let Some(fields) = parse_record(record)? {
+            return Ok(Some(fields));
+        }
+    }
+}
+
+fn parse_field<T: FromStr>(field: &str, what: &str) -> io::Result<T> {
+    field
+        .parse()
+        .map_err(|_| invalid_data(format!("can't parse {} {:?}", what, field)))
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    pub fn export_csv<W: Write>(&self, out: W) -> io::Result<()>
+    where
+        K: Display,
+        V: Display,
+    {
+        let mut writer = CsvWriter::new(out)?;
+        for (key, value) in self.iter() {
+            writer.write(key, value)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    // Inserts every row read, in any order, replacing existing values. A
+    // key,value header is skipped if present. Returns how many entries were
+    // read; on an error, those before the bad record stay inserted.
+    pub fn import_csv<R: BufRead>(&mut self, mut input: R) -> io::Result<usize>
+    where
+        K: FromStr,
+        V: FromStr,
+    {
+        let mut text = String::new();
+        let mut count = 0;
+        for number in 1.. {
+            let with_number = |err: io::Error| invalid_data(format!("record {}: {}", number, err));
+            let Some(fields) = read_record(&mut input, &mut text).map_err(with_number)? else {
+                break;
+            };
+            if number == 1 && fields == HEADER {
+                continue;
+            }
+
+            let (key, value) = match fields.as_slice() {
+                [key, value] => (
+                    parse_field(key, "key").map_err(with_number)?,
+                    parse_field(value, "value").map_err(with_number)?,
+                ),
+                _ => {
+                    return Err(with_number(invalid_data(format!(
+                        "expected 2 fields, found {}",
+                        fields.len()
+                    ))))
+                }
+            };
+            self.add(key, value);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BTree;
+
+    #[test]
+    fn test_csv() {
+        let mut tree = BTree::<String, i64>::new(3);
+        let names = ["plain", "with, comma", "\"quoted\"", "two\r\nlines", ""];
+        for (i, name) in names.iter().enumerate() {
+            tree.add(name.to_string(), i as i64 - 2);
+        }
+
+        let mut out = Vec::new();
+        tree.export_csv(&mut out).unwrap();
+        let text = String::from_utf8(out.clone()).unwrap();
+        assert!(text.starts_with("key,value\r\n,2\r\n"), "{}", text);
+        assert!(text.contains("\r\n\"\"\"quoted\"\"\",0\r\n"));
+        assert!(text.contains("\r\n\"with, comma\",-1\r\n"));
+
+        let mut copy = BTree::<String, i64>::new(4);
+        assert_eq!(copy.import_csv(out.as_slice()).unwrap(), names.len());
+        assert!(copy.iter().eq(tree.iter()));
+
+        // No header, bare newlines, a line break inside quotes.
+        let input = "7,\"seven\"\n8,eight\n9,\"ni\nne\"";
+        let mut numbers = BTree::<u64, String>::new(3);
+        assert_eq!(numbers.import_csv(input.as_bytes()).unwrap(), 3);
+        assert_eq!(numbers.get(&9), Some(&"ni\nne".to_string()));
+        assert_eq!(numbers.get(&7), Some(&"seven".to_string()));
+
+        // Blank lines, including a trailing one, hold no records; inside
+        // quotes they are part of the field.
+        let input = "key,value\n1,2\n\n";
+        let mut blank = BTree::<u64, String>::new(3);
+        assert_eq!(blank.import_csv(input.as_bytes()).unwrap(), 1);
+        let input = "\r\n3,\"a\n\nb\"\r\n\r\n\n4,c\n";
+        assert_eq!(blank.import_csv(input.as_bytes()).unwrap(), 2);
+        assert_eq!(blank.get(&3), Some(&"a\n\nb".to_string()));
+        assert_eq!(blank.len(), 3);
+
+        for bad in ["x,1", "1", "1,a,b", "1,\"open", "1,\"a\"b", "1,a\"b"] {
+            let input = format!("key,value\n2,two\n{}\n", bad);
+            let err = numbers.import_csv(input.as_bytes()).unwrap_err();
+            assert!(
+                err.to_string().starts_with("record 3: "),
+                "{}: {}",
+                bad,
+                err
+            );
+        }
+        assert_eq!(numbers.get(&2), Some(&"two".to_string()));
+    }
+}
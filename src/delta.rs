@@ -0,0 +1,252 @@
+// Experimental Bw-tree style tree. Leaves live in a mapping table of logical
+// pages. An update does not rewrite its leaf. Instead it appends a small delta
+// record to the page's chain, and the chain is folded into a new base only
+// once it grows past CONSOLIDATE_AFTER records. Pages split during
+// consolidation and are never merged.
+
+use std::ops::RangeBounds;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+
+const CONSOLIDATE_AFTER: usize = 8;
+
+type PageId = usize;
+
+enum Delta<K, V> {
+    Insert(K, V),
+    Remove(K),
+}
+
+struct Page<K, V> {
+    // Sorted entries as of the last consolidation.
+    base: Vec<(K, V)>,
+    // Updates since then, oldest first.
+    deltas: Vec<Delta<K, V>>,
+}
+
+impl<K: Ord, V> Page<K, V> {
+    fn lookup(&self, key: &K) -> Option<&V> {
+        for delta in self.deltas.iter().rev() {
+            match delta {
+                Delta::Insert(k, value) if k == key => return Some(value),
+                Delta::Remove(k) if k == key => return None,
+                _ => {}
+            }
+        }
+
+        let index = self.base.binary_search_by(|(k, _)| k.cmp(key)).ok()?;
+        Some(&self.base[index].1)
+    }
+
+    fn consolidate(&mut self) {
+        for delta in self.deltas.drain(..) {
+            match delta {
+                Delta::Insert(key, value) => match self.base.binary_search_by(|(k, _)| k.cmp(&key))
+                {
+                    Ok(index) => self.base[index].1 = value,
+                    Err(index) => self.base.insert(index, (key, value)),
+                },
+                Delta::Remove(key) => {
+                    if let Ok(index) = self.base.binary_search_by(|(k, _)| k.cmp(&key)) {
+                        self.base.remove(index);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct MappingTable<K, V> {
+    pages: Vec<Mutex<Page<K, V>>>,
+    // Pages in key order. `routes[i + 1]` holds the keys from `fences[i]` up
+    // to the next fence.
+    routes: Vec<PageId>,
+    fences: Vec<K>,
+}
+
+impl<K: Ord, V> MappingTable<K, V> {
+    fn route(&self, key: &K) -> PageId {
+        self.routes[self.fences.partition_point(|fence| fence <= key)]
+    }
+}
+
+pub struct DeltaBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    page_size: usize,
+    // Read-locked by single-page operations, write-locked to split a page.
+    table: RwLock<MappingTable<K, V>>,
+    len: AtomicUsize,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> DeltaBTree<K, V> {
+    // `page_size` is the most entries a consolidated page holds before it splits.
+    pub fn new(page_size: usize) -> DeltaBTree<K, V> {
+        assert!(page_size >= 2, "page size must be at least 2");
+
+        DeltaBTree {
+            page_size,
+            table: RwLock::new(MappingTable {
+                pages: vec![Mutex::new(Page {
+                    base: Vec::new(),
+                    deltas: Vec::new(),
+                })],
+                routes: vec![0],
+                fences: Vec::new(),
+            }),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let table = self.table.read().unwrap();
+        let id = table.route(&key);
+        let mut page = table.pages[id].lock().unwrap();
+
+        let old = page.lookup(&key).cloned();
+        page.deltas.push(Delta::Insert(key, value));
+        if old.is_none() {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut split = false;
+        if page.deltas.len() > CONSOLIDATE_AFTER {
+            page.consolidate();
+            split = page.base.len() > self.page_size;
+        }
+        drop(page);
+        drop(table);
+
+        if split {
+            self.split(id);
+        }
+
+        old
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let table = self.table.read().unwrap();
+        let page = table.pages[table.route(key)].lock().unwrap();
+
+        page.lookup(key).cloned()
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let table = self.table.read().unwrap();
+        let mut page = table.pages[table.route(key)].lock().unwrap();
+
+        let old = page.lookup(key).cloned()?;
+        page.deltas.push(Delta::Remove(key.clone()));
+        self.len.fetch_sub(1, Ordering::Relaxed);
+
+        if page.deltas.len() > CONSOLIDATE_AFTER {
+            page.consolidate();
+        }
+
+        Some(old)
+    }
+
+    // Moves the upper half of page `id` to a new page, unless another thread
+    // already split it.
+    fn split(&self, id: PageId) {
+        let mut table = self.table.write().unwrap();
+
+        let page = table.pages[id].get_mut().unwrap();
+        page.consolidate();
+        if page.base.len() <= self.page_size {
+            return;
+        }
+        let right = page.base.split_off(page.base.len() / 2);
+        let fence = right[0].0.clone();
+
+        let new_id = table.pages.len();
+        table.pages.push(Mutex::new(Page {
+            base: right,
+            deltas: Vec::new(),
+        }));
+        let position = table.fences.partition_point(|key| *key < fence);
+        table.fences.insert(position, fence);
+        table.routes.insert(position + 1, new_id);
+    }
+
+    // Copies the entries in `range` out. Every page is latched, in key order,
+    // before any of them is read, so the result is consistent.
+    pub fn range_snapshot<R: RangeBounds<K>>(&self, range: R) -> Vec<(K, V)> {
+        let table = self.table.read().unwrap();
+        let mut pages: Vec<_> = table
+            .routes
+            .iter()
+            .map(|&id| table.pages[id].lock().unwrap())
+            .collect();
+
+        let mut entries = Vec::new();
+        for page in pages.iter_mut() {
+            page.consolidate();
+            entries.extend(
+                page.base
+                    .iter()
+                    .filter(|(key, _)| range.contains(key))
+                    .cloned(),
+            );
+        }
+
+        entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::delta::DeltaBTree;
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_delta_btree() {
+        let tree = DeltaBTree::<u64, u64>::new(16);
+
+        std::thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let tree = &tree;
+                scope.spawn(move || {
+                    for key in (thread..4000).step_by(4) {
+                        assert_eq!(tree.insert(key, key), None);
+                    }
+                });
+            }
+        });
+        assert_eq!(tree.len(), 4000);
+        assert!(tree.table.read().unwrap().pages.len() > 4000 / 16);
+
+        let mut expected: BTreeMap<u64, u64> = tree.range_snapshot(..).into_iter().collect();
+        assert_eq!(expected.len(), 4000);
+
+        let mut rng = Rng::with_seed(88172645463325252);
+        for _ in 0..5000 {
+            let state = rng.next_u64();
+            let key = state % 5000;
+
+            if state.is_multiple_of(3) {
+                assert_eq!(tree.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(tree.insert(key, state), expected.insert(key, state));
+            }
+            assert_eq!(tree.get(&key), expected.get(&key).copied());
+        }
+
+        assert_eq!(tree.len(), expected.len());
+        assert_eq!(
+            tree.range_snapshot(100..200),
+            expected
+                .range(100..200)
+                .map(|(k, v)| (*k, *v))
+                .collect::<Vec<_>>()
+        );
+    }
+}
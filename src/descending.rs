@@ -0,0 +1,99 @@
+// A tree that keeps its keys largest first, so iteration and ranges over
+// timestamps or sequence numbers start with the newest entry. Keys are stored
+// as Reverse<K>, but the API takes and returns plain keys.
+
+use std::cmp::Reverse;
+use std::ops::{Bound, RangeBounds};
+
+use crate::BTree;
+
+fn reverse<K: Clone>(bound: Bound<&K>) -> Bound<Reverse<K>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(Reverse(key.clone())),
+        Bound::Excluded(key) => Bound::Excluded(Reverse(key.clone())),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+pub struct DescendingBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    tree: BTree<Reverse<K>, V>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> DescendingBTree<K, V> {
+    pub fn new(node_size: usize) -> DescendingBTree<K, V> {
+        DescendingBTree {
+            tree: BTree::new(node_size),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.tree.add(Reverse(key), value)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.tree.get(&Reverse(key.clone()))
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.tree.remove(&Reverse(key.clone()))
+    }
+
+    // The entry with the largest key.
+    pub fn first(&self) -> Option<(&K, &V)> {
+        self.iter().next()
+    }
+
+    // All entries, largest key first.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.tree.iter().map(|(key, value)| (&key.0, value))
+    }
+
+    // The entries in `range`, largest key first. The range is given in the
+    // keys' own order, so `10..20` yields 19 down to 10.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (&K, &V)> + '_ {
+        let bounds = (reverse(range.end_bound()), reverse(range.start_bound()));
+        self.tree.range(bounds).map(|(key, value)| (&key.0, value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.first().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::descending::DescendingBTree;
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_descending_btree() {
+        let mut tree = DescendingBTree::<u64, u64>::new(3);
+        let mut expected = BTreeMap::new();
+        let mut rng = Rng::new();
+
+        for step in 0..2000 {
+            let state = rng.next_u64();
+            let key = state % 500;
+
+            if state.is_multiple_of(3) {
+                assert_eq!(tree.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(tree.insert(key, step), expected.insert(key, step));
+            }
+            assert_eq!(tree.get(&key), expected.get(&key));
+        }
+
+        assert!(tree.iter().eq(expected.iter().rev()));
+        assert_eq!(tree.first(), expected.iter().next_back());
+        assert!(tree.range(100..200).eq(expected.range(100..200).rev()));
+        assert!(tree.range(..=50).eq(expected.range(..=50).rev()));
+        assert!(tree.range(450..).eq(expected.range(450..).rev()));
+        assert_eq!(tree.len(), expected.len());
+    }
+}
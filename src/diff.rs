@@ -0,0 +1,270 @@
+// Differences between two trees, found by walking both in key order at once.
+// A tree and its copies share every node neither has changed since, so when
+// both walks reach the same shared subtree at the same time it is skipped
+// without being read, and comparing a tree with a slightly edited copy only
+// opens the nodes on the edited paths.
+
+use std::sync::Arc;
+
+use crate::batch::WriteBatch;
+use crate::{BTree, BTreeNode};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffEntry<K, V> {
+    // Only in the other tree.
+    Added(K, V),
+    // Only in this tree.
+    Removed(K, V),
+    // In both, with this tree's value first.
+    Modified(K, V, V),
+}
+
+enum Token<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    Subtree(&'a Arc<BTreeNode<K, V>>),
+    Entry(&'a K, &'a V),
+}
+
+// Walks a tree in order without opening subtrees until asked to. Internal
+// nodes are read as child, entry, child, ..., child, so a position counts
+// both.
+struct Walk<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    stack: Vec<(&'a BTreeNode<K, V>, usize)>,
+}
+
+impl<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Walk<'a, K, V> {
+    fn peek(&mut self) -> Option<Token<'a, K, V>> {
+        loop {
+            let &(node, position) = self.stack.last()?;
+
+            if node.children.is_empty() {
+                if position < node.keys.len() {
+                    return Some(Token::Entry(&node.keys[position], &node.values[position]));
+                }
+            } else if position <= 2 * node.keys.len() {
+                let i = position / 2;
+                return Some(match position % 2 {
+                    0 => Token::Subtree(&node.children[i]),
+                    _ => Token::Entry(&node.keys[i], &node.values[i]),
+                });
+            }
+
+            self.stack.pop();
+        }
+    }
+
+    // Moves past what peek returned.
+    fn skip(&mut self) {
+        self.stack.last_mut().unwrap().1 += 1;
+    }
+
+    // Moves into the subtree peek returned.
+    fn open(&mut self, node: &'a BTreeNode<K, V>) {
+        self.skip();
+        self.stack.push((node, 0));
+    }
+}
+
+pub struct Diff<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    old: Walk<'a, K, V>,
+    new: Walk<'a, K, V>,
+    // Subtrees opened so far. Shared ones are never opened.
+    opened: usize,
+}
+
+impl<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Iterator
+    for Diff<'a, K, V>
+{
+    type Item = DiffEntry<K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match (self.old.peek(), self.new.peek()) {
+                (Some(Token::Subtree(a)), Some(Token::Subtree(b))) if Arc::ptr_eq(a, b) => {
+                    self.old.skip();
+                    self.new.skip();
+                }
+                (Some(Token::Subtree(a)), _) => {
+                    self.old.open(a);
+                    self.opened += 1;
+                }
+                (_, Some(Token::Subtree(b))) => {
+                    self.new.open(b);
+                    self.opened += 1;
+                }
+                (Some(Token::Entry(key, value)), None) => {
+                    self.old.skip();
+                    return Some(DiffEntry::Removed(key.clone(), value.clone()));
+                }
+                (None, Some(Token::Entry(key, value))) => {
+                    self.new.skip();
+                    return Some(DiffEntry::Added(key.clone(), value.clone()));
+                }
+                (Some(Token::Entry(a, old)), Some(Token::Entry(b, new))) => {
+                    if a < b {
+                        self.old.skip();
+                        return Some(DiffEntry::Removed(a.clone(), old.clone()));
+                    }
+                    if b < a {
+                        self.new.skip();
+                        return Some(DiffEntry::Added(b.clone(), new.clone()));
+                    }
+
+                    self.old.skip();
+                    self.new.skip();
+                    if old != new {
+                        return Some(DiffEntry::Modified(a.clone(), old.clone(), new.clone()));
+                    }
+                }
+                (None, None) => return None,
+            }
+        }
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    // The changes that turn this tree into `other`, in key order.
+    pub fn diff<'a>(&'a self, other: &'a BTree<K, V>) -> Diff<'a, K, V> {
+        Diff {
+            old: Walk {
+                stack: vec![(&self.root, 0)],
+            },
+            new: Walk {
+                stack: vec![(&other.root, 0)],
+            },
+            opened: 0,
+        }
+    }
+
+    // The writes that bring `snapshot`, an earlier copy of this tree, up to
+    // date. Encoded with Codec, they can be sent to a replica holding the
+    // same snapshot and applied there with apply_changes.
+    pub fn changes_since(&self, snapshot: &BTree<K, V>) -> WriteBatch<K, V> {
+        let mut changes = WriteBatch::new();
+        for entry in snapshot.diff(self) {
+            match entry {
+                DiffEntry::Added(key, value) | DiffEntry::Modified(key, _, value) => {
+                    changes.insert(key, value);
+                }
+                DiffEntry::Removed(key, _) => {
+                    changes.remove(key);
+                }
+            }
+        }
+        changes
+    }
+
+    pub fn apply_changes(&mut self, changes: WriteBatch<K, V>) {
+        self.write(changes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::batch::WriteBatch;
+    use crate::codec::Codec;
+    use crate::diff::DiffEntry;
+    use crate::testutil::Rng;
+    use crate::BTree;
+
+    #[test]
+    fn test_diff() {
+        let mut tree = BTree::<u64, u64>::new(4);
+        for key in 0..5000 {
+            tree.add(key, key);
+        }
+
+        // An edited copy shares everything off the edited paths.
+        let mut copy = BTree {
+            root: tree.root.clone(),
+        };
+        copy.add(10, 11);
+        copy.remove(&2500);
+        copy.add(9999, 0);
+
+        let mut diff = tree.diff(&copy);
+        let changes: Vec<_> = diff.by_ref().collect();
+        assert_eq!(
+            changes,
+            [
+                DiffEntry::Modified(10, 10, 11),
+                DiffEntry::Removed(2500, 2500),
+                DiffEntry::Added(9999, 0),
+            ]
+        );
+        assert!(diff.opened < 100, "opened {} subtrees", diff.opened);
+        assert_eq!(tree.diff(&tree).count(), 0);
+
+        // Trees built separately share nothing but still compare entry by
+        // entry.
+        for node_size in [2, 3, 7] {
+            let mut a = BTree::<u64, u64>::new(node_size);
+            let mut b = BTree::<u64, u64>::new(5);
+            let mut expected_a = BTreeMap::new();
+            let mut expected_b = BTreeMap::new();
+            let mut rng = Rng::new();
+
+            for _ in 0..2000 {
+                let state = rng.next_u64();
+                let key = state % 300;
+                let value = (state >> 20) % 3;
+                if state.is_multiple_of(2) {
+                    a.add(key, value);
+                    expected_a.insert(key, value);
+                } else {
+                    b.add(key, value);
+                    expected_b.insert(key, value);
+                }
+            }
+
+            let mut want = Vec::new();
+            for key in 0..300 {
+                match (expected_a.get(&key), expected_b.get(&key)) {
+                    (Some(&x), None) => want.push(DiffEntry::Removed(key, x)),
+                    (None, Some(&y)) => want.push(DiffEntry::Added(key, y)),
+                    (Some(&x), Some(&y)) if x != y => want.push(DiffEntry::Modified(key, x, y)),
+                    _ => {}
+                }
+            }
+            assert_eq!(a.diff(&b).collect::<Vec<_>>(), want);
+        }
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let mut tree = BTree::<u64, String>::new(4);
+        for key in 0..2000 {
+            tree.add(key, key.to_string());
+        }
+        let mut replica = BTree::<u64, String>::new(3);
+        for (key, value) in tree.iter() {
+            replica.add(*key, value.clone());
+        }
+        let snapshot = BTree {
+            root: tree.root.clone(),
+        };
+
+        let mut rng = Rng::new();
+        for _ in 0..300 {
+            let state = rng.next_u64();
+            let key = state % 2500;
+            if state.is_multiple_of(3) {
+                tree.remove(&key);
+            } else {
+                tree.add(key, (state >> 32).to_string());
+            }
+        }
+
+        let changes = tree.changes_since(&snapshot);
+        assert!(changes.len() <= 300);
+        let mut bytes = Vec::new();
+        changes.encode(&mut bytes);
+        let changes = WriteBatch::<u64, String>::decode(&bytes).unwrap();
+        replica.apply_changes(changes);
+        assert!(replica.iter().eq(tree.iter()));
+
+        assert!(tree.changes_since(&tree).is_empty());
+        assert!(WriteBatch::<u64, String>::decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+// Building an sstable from more entries than fit in memory. Entries are
+// sorted in memory a run at a time, each full run is spilled to its own file,
+// and finish merges the run files into the sstable, reading each from front
+// to back. Memory use is bounded by the run size, not the input.
+//
+// Run files are a count followed by length-prefixed keys and values.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use crate::codec::{read_item, write_item, Codec};
+use crate::runs::MergeRuns;
+use crate::sstable::{self, SSTable};
+
+pub struct ExternalBuilder<K: Codec + Ord, V: Codec> {
+    // Where run files go. Nothing else should write there.
+    dir: PathBuf,
+    run_size: usize,
+    // Later pushes of a key replace earlier ones.
+    buffer: BTreeMap<K, V>,
+    runs: Vec<PathBuf>,
+}
+
+// Reads a run file back. A read error ends the run early and is left in
+// `error` for finish to return.
+struct RunReader<'a, K, V> {
+    file: BufReader<File>,
+    remaining: u64,
+    buf: Vec<u8>,
+    error: &'a Cell<Option<io::Error>>,
+    entries: PhantomData<(K, V)>,
+}
+
+impl<'a, K: Codec, V: Codec> RunReader<'a, K, V> {
+    fn open(path: &Path, error: &'a Cell<Option<io::Error>>) -> io::Result<RunReader<'a, K, V>> {
+        let mut file = BufReader::new(File::open(path)?);
+        let remaining = read_item(&mut file, &mut Vec::new())?;
+        Ok(RunReader {
+            file,
+            remaining,
+            buf: Vec::new(),
+            error,
+            entries: PhantomData,
+        })
+    }
+
+    fn read_entry(&mut self) -> io::Result<(K, V)> {
+        let key = read_item(&mut self.file, &mut self.buf)?;
+        let value = read_item(&mut self.file, &mut self.buf)?;
+        Ok((key, value))
+    }
+}
+
+impl<'a, K: Codec, V: Codec> Iterator for RunReader<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        match self.read_entry() {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                self.remaining = 0;
+                self.error.set(Some(err));
+                None
+            }
+        }
+    }
+}
+
+impl<K: Codec + Ord, V: Codec> ExternalBuilder<K, V> {
+    // Spills a run to `dir` every `run_size` distinct keys.
+    pub fn new<P: AsRef<Path>>(dir: P, run_size: usize) -> ExternalBuilder<K, V> {
+        assert!(run_size >= 1, "a run must hold at least one entry");
+
+        ExternalBuilder {
+            dir: dir.as_ref().to_path_buf(),
+            run_size,
+            buffer: BTreeMap::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, key: K, value: V) -> io::Result<()> {
+        self.buffer.insert(key, value);
+        if self.buffer.len() >= self.run_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    pub fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, entries: I) -> io::Result<()> {
+        entries
+            .into_iter()
+            .try_for_each(|(key, value)| self.push(key, value))
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let path = self.dir.join(format!("run-{}", self.runs.len()));
+        let mut file = BufWriter::new(File::create(&path)?);
+        // Recorded before it is written, so a failed spill is cleaned up too.
+        self.runs.push(path);
+        let mut buf = Vec::new();
+
+        write_item(&mut file, &(self.buffer.len() as u64), &mut buf)?;
+        for (key, value) in std::mem::take(&mut self.buffer) {
+            write_item(&mut file, &key, &mut buf)?;
+            write_item(&mut file, &value, &mut buf)?;
+        }
+        file.flush()?;
+
+        Ok(())
+    }
+
+    // Pushed runs so far spilled to disk.
+    pub fn spilled(&self) -> usize {
+        self.runs.len()
+    }
+
+    // Merges everything pushed into a new sstable at `path` and removes the
+    // run files. If a run can't be read back, the sstable is removed too,
+    // since it would be missing entries.
+    pub fn finish<P: AsRef<Path>>(mut self, path: P) -> io::Result<SSTable<K, V>> {
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+
+        let error = Cell::new(None);
+        let readers = self
+            .runs
+            .iter()
+            .map(|run| RunReader::<K, V>::open(run, &error))
+            .collect::<io::Result<Vec<_>>>()?;
+        sstable::write_owned(&path, MergeRuns::new(readers))?;
+        if let Some(err) = error.take() {
+            let _ = fs::remove_file(&path);
+            return Err(err);
+        }
+
+        while let Some(run) = self.runs.pop() {
+            fs::remove_file(run)?;
+        }
+        SSTable::open(path)
+    }
+}
+
+// Removes the run files left when the builder is dropped unfinished or
+// finish fails.
+impl<K: Codec + Ord, V: Codec> Drop for ExternalBuilder<K, V> {
+    fn drop(&mut self) {
+        for run in &self.runs {
+            let _ = fs::remove_file(run);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::external::ExternalBuilder;
+    use crate::testutil::{Rng, TempDir};
+
+    #[test]
+    fn test_external_builder() {
+        let dir = TempDir::new("external");
+        let mut builder = ExternalBuilder::<u64, String>::new(dir.path(), 100);
+        let mut expected = BTreeMap::new();
+        let mut rng = Rng::new();
+        for step in 0..2000 {
+            let state = rng.next_u64();
+            let key = state % 1500;
+            builder.push(key, format!("{} {}", key, step)).unwrap();
+            expected.insert(key, format!("{} {}", key, step));
+        }
+        assert!(builder.spilled() >= 19);
+
+        let path = dir.join("table");
+        let mut table = builder.finish(&path).unwrap();
+        assert_eq!(table.len() as usize, expected.len());
+        let entries: Vec<(u64, String)> = expected.into_iter().collect();
+        assert_eq!(table.entries().unwrap(), entries);
+        assert_eq!(
+            table.get(&entries[10].0).unwrap(),
+            Some(entries[10].1.clone())
+        );
+
+        // Only the table is left behind.
+        let left: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(left.len(), 1);
+
+        let empty_dir = TempDir::new("external-empty");
+        let builder = ExternalBuilder::<u64, u64>::new(empty_dir.path(), 10);
+        let table = builder.finish(empty_dir.join("table")).unwrap();
+        assert!(table.is_empty());
+
+        // A run that can't be read back leaves neither a short table nor the
+        // run files behind.
+        let broken_dir = TempDir::new("external-broken");
+        let mut builder = ExternalBuilder::<u64, u64>::new(broken_dir.path(), 10);
+        builder.extend((0..25).map(|key| (key, key))).unwrap();
+        let run = broken_dir.join("run-0");
+        let run_len = std::fs::metadata(&run).unwrap().len();
+        std::fs::File::options()
+            .write(true)
+            .open(&run)
+            .unwrap()
+            .set_len(run_len / 2)
+            .unwrap();
+        let path = broken_dir.join("table");
+        assert!(builder.finish(&path).is_err());
+        assert_eq!(std::fs::read_dir(broken_dir.path()).unwrap().count(), 0);
+
+        // So does a builder dropped before it finishes.
+        let mut builder = ExternalBuilder::<u64, u64>::new(broken_dir.path(), 10);
+        builder.extend((0..25).map(|key| (key, key))).unwrap();
+        assert_eq!(builder.spilled(), 2);
+        drop(builder);
+        assert_eq!(std::fs::read_dir(broken_dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,330 @@
+// C interface to a tree of byte string keys and values, declared in
+// include/ctree.h. Every function returns one of the CTREE_* codes and hands
+// results back through out pointers.
+//
+// Handles are pointers this side allocated, valid until closed or freed.
+// Byte arguments are a pointer and a length; the pointer may be null only if
+// the length is 0. Bytes returned by ctree_get belong to the caller, who
+// frees them with ctree_free. Entries returned by ctree_iter_next stay valid
+// until the next call on the same iterator. A tree handle must not be used
+// from two threads at once, but an iterator walks a snapshot and may be used
+// while the tree changes.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, c_int, CStr};
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::ptr;
+
+use crate::{BTree, SnapshotRange};
+
+pub const CTREE_OK: c_int = 0;
+pub const CTREE_NOT_FOUND: c_int = 1;
+// The iterator has no entries left.
+pub const CTREE_DONE: c_int = 2;
+pub const CTREE_INVALID_ARGUMENT: c_int = -1;
+pub const CTREE_IO_ERROR: c_int = -2;
+
+// Used for files that don't exist yet.
+const NODE_SIZE: usize = 32;
+
+pub struct CTree {
+    tree: BTree<Vec<u8>, Vec<u8>>,
+    path: PathBuf,
+}
+
+pub struct CTreeIter {
+    range: SnapshotRange<Vec<u8>, Vec<u8>>,
+    entry: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> Option<&'a [u8]> {
+    if len == 0 {
+        Some(&[])
+    } else if ptr.is_null() {
+        None
+    } else {
+        Some(std::slice::from_raw_parts(ptr, len))
+    }
+}
+
+// Opens the tree saved at `path`, or an empty tree that ctree_save will
+// create the file for.
+#[no_mangle]
+pub unsafe extern "C" fn ctree_open(path: *const c_char, out: *mut *mut CTree) -> c_int {
+    if path.is_null() || out.is_null() {
+        return CTREE_INVALID_ARGUMENT;
+    }
+    let Ok(path) = CStr::from_ptr(path).to_str() else {
+        return CTREE_INVALID_ARGUMENT;
+    };
+
+    let tree = match BTree::load(path) {
+        Ok(tree) => tree,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTree::new(NODE_SIZE),
+        Err(_) => return CTREE_IO_ERROR,
+    };
+    *out = Box::into_raw(Box::new(CTree {
+        tree,
+        path: PathBuf::from(path),
+    }));
+    CTREE_OK
+}
+
+// Writes the tree to the file it was opened from.
+#[no_mangle]
+pub unsafe extern "C" fn ctree_save(tree: *const CTree) -> c_int {
+    let Some(tree) = tree.as_ref() else {
+        return CTREE_INVALID_ARGUMENT;
+    };
+    match tree.tree.save(&tree.path) {
+        Ok(()) => CTREE_OK,
+        Err(_) => CTREE_IO_ERROR,
+    }
+}
+
+// Frees the tree without saving it. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn ctree_close(tree: *mut CTree) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ctree_put(
+    tree: *mut CTree,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int {
+    let (Some(tree), Some(key), Some(value)) =
+        (tree.as_mut(), bytes(key, key_len), bytes(value, value_len))
+    else {
+        return CTREE_INVALID_ARGUMENT;
+    };
+    tree.tree.add(key.to_vec(), value.to_vec());
+    CTREE_OK
+}
+
+// On CTREE_OK, stores a copy of the value for ctree_free.
+#[no_mangle]
+pub unsafe extern "C" fn ctree_get(
+    tree: *const CTree,
+    key: *const u8,
+    key_len: usize,
+    value: *mut *mut u8,
+    value_len: *mut usize,
+) -> c_int {
+    let (Some(tree), Some(key)) = (tree.as_ref(), bytes(key, key_len)) else {
+        return CTREE_INVALID_ARGUMENT;
+    };
+    if value.is_null() || value_len.is_null() {
+        return CTREE_INVALID_ARGUMENT;
+    }
+
+    match tree.tree.get(&key.to_vec()) {
+        Some(found) => {
+            *value_len = found.len();
+            *value = Box::into_raw(found.clone().into_boxed_slice()).cast();
+            CTREE_OK
+        }
+        None => CTREE_NOT_FOUND,
+    }
+}
+
+// Frees bytes returned by ctree_get. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn ctree_free(bytes: *mut u8, len: usize) {
+    if !bytes.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(bytes, len)));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ctree_delete(tree: *mut CTree, key: *const u8, key_len: usize) -> c_int {
+    let (Some(tree), Some(key)) = (tree.as_mut(), bytes(key, key_len)) else {
+        return CTREE_INVALID_ARGUMENT;
+    };
+    match tree.tree.remove(&key.to_vec()) {
+        Some(_) => CTREE_OK,
+        None => CTREE_NOT_FOUND,
+    }
+}
+
+// Iterates over keys from `start` up to but not including `end`, in order.
+// A null bound leaves that side open; to start at the empty key, pass a
+// non-null pointer with length 0.
+#[no_mangle]
+pub unsafe extern "C" fn ctree_iter_range(
+    tree: *const CTree,
+    start: *const u8,
+    start_len: usize,
+    end: *const u8,
+    end_len: usize,
+    out: *mut *mut CTreeIter,
+) -> c_int {
+    let Some(tree) = tree.as_ref() else {
+        return CTREE_INVALID_ARGUMENT;
+    };
+    if out.is_null() {
+        return CTREE_INVALID_ARGUMENT;
+    }
+    let bound = |ptr: *const u8, len, bound: fn(Vec<u8>) -> Bound<Vec<u8>>| {
+        if ptr.is_null() && len == 0 {
+            Some(Bound::Unbounded)
+        } else {
+            bytes(ptr, len).map(|bytes| bound(bytes.to_vec()))
+        }
+    };
+    let (Some(start), Some(end)) = (
+        bound(start, start_len, Bound::Included),
+        bound(end, end_len, Bound::Excluded),
+    ) else {
+        return CTREE_INVALID_ARGUMENT;
+    };
+
+    *out = Box::into_raw(Box::new(CTreeIter {
+        range: tree.tree.snapshot_range((start, end)),
+        entry: None,
+    }));
+    CTREE_OK
+}
+
+// Moves to the next entry and points `key` and `value` at it, or returns
+// CTREE_DONE.
+#[no_mangle]
+pub unsafe extern "C" fn ctree_iter_next(
+    iter: *mut CTreeIter,
+    key: *mut *const u8,
+    key_len: *mut usize,
+    value: *mut *const u8,
+    value_len: *mut usize,
+) -> c_int {
+    let Some(iter) = iter.as_mut() else {
+        return CTREE_INVALID_ARGUMENT;
+    };
+    if key.is_null() || key_len.is_null() || value.is_null() || value_len.is_null() {
+        return CTREE_INVALID_ARGUMENT;
+    }
+
+    iter.entry = iter.range.next();
+    match &iter.entry {
+        Some((k, v)) => {
+            (*key, *key_len) = (k.as_ptr(), k.len());
+            (*value, *value_len) = (v.as_ptr(), v.len());
+            CTREE_OK
+        }
+        None => CTREE_DONE,
+    }
+}
+
+// Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn ctree_iter_free(iter: *mut CTreeIter) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use crate::ffi::*;
+    use crate::testutil::TempDir;
+
+    unsafe fn get(tree: *const CTree, key: &[u8]) -> Option<Vec<u8>> {
+        let mut value = ptr::null_mut();
+        let mut len = 0;
+        match ctree_get(tree, key.as_ptr(), key.len(), &mut value, &mut len) {
+            CTREE_OK => {
+                let copy = std::slice::from_raw_parts(value, len).to_vec();
+                ctree_free(value, len);
+                Some(copy)
+            }
+            code => {
+                assert_eq!(code, CTREE_NOT_FOUND);
+                None
+            }
+        }
+    }
+
+    unsafe fn collect(iter: *mut CTreeIter) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let (mut key, mut key_len) = (ptr::null(), 0);
+        let (mut value, mut value_len) = (ptr::null(), 0);
+        let mut entries = Vec::new();
+        while ctree_iter_next(iter, &mut key, &mut key_len, &mut value, &mut value_len) == CTREE_OK
+        {
+            entries.push((
+                std::slice::from_raw_parts(key, key_len).to_vec(),
+                std::slice::from_raw_parts(value, value_len).to_vec(),
+            ));
+        }
+        ctree_iter_free(iter);
+        entries
+    }
+
+    #[test]
+    fn test_ffi() {
+        let dir = TempDir::new("ffi");
+        let path = dir.join("tree");
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+
+        unsafe {
+            let mut tree = ptr::null_mut();
+            assert_eq!(ctree_open(c_path.as_ptr(), &mut tree), CTREE_OK);
+            for i in 0..100u32 {
+                let key = format!("key{:03}", i);
+                let value = i.to_le_bytes();
+                assert_eq!(
+                    ctree_put(tree, key.as_ptr(), key.len(), value.as_ptr(), value.len()),
+                    CTREE_OK
+                );
+            }
+            assert_eq!(ctree_put(tree, ptr::null(), 0, ptr::null(), 0), CTREE_OK);
+            assert_eq!(get(tree, b""), Some(Vec::new()));
+            assert_eq!(get(tree, b"key042"), Some(42u32.to_le_bytes().to_vec()));
+            assert_eq!(get(tree, b"nope"), None);
+            assert_eq!(ctree_delete(tree, b"key042".as_ptr(), 6), CTREE_OK);
+            assert_eq!(ctree_delete(tree, b"key042".as_ptr(), 6), CTREE_NOT_FOUND);
+            assert_eq!(
+                ctree_put(tree, ptr::null(), 3, ptr::null(), 0),
+                CTREE_INVALID_ARGUMENT
+            );
+
+            // The iterator sees the tree as it was when created.
+            let mut iter = ptr::null_mut();
+            let (start, end) = (b"key040", b"key045");
+            assert_eq!(
+                ctree_iter_range(tree, start.as_ptr(), 6, end.as_ptr(), 6, &mut iter),
+                CTREE_OK
+            );
+            assert_eq!(ctree_delete(tree, b"key041".as_ptr(), 6), CTREE_OK);
+            let keys: Vec<Vec<u8>> = collect(iter).into_iter().map(|(k, _)| k).collect();
+            assert_eq!(keys, [b"key040", b"key041", b"key043", b"key044"]);
+
+            assert_eq!(ctree_save(tree), CTREE_OK);
+            ctree_close(tree);
+
+            let mut reopened = ptr::null_mut();
+            assert_eq!(ctree_open(c_path.as_ptr(), &mut reopened), CTREE_OK);
+            let mut iter = ptr::null_mut();
+            assert_eq!(
+                ctree_iter_range(reopened, ptr::null(), 0, ptr::null(), 0, &mut iter),
+                CTREE_OK
+            );
+            let entries = collect(iter);
+            assert_eq!(entries.len(), 99);
+            assert_eq!(entries[0], (Vec::new(), Vec::new()));
+            assert_eq!(
+                entries[1],
+                (b"key000".to_vec(), 0u32.to_le_bytes().to_vec())
+            );
+            ctree_close(reopened);
+            ctree_close(ptr::null_mut());
+        }
+    }
+}
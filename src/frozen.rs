@@ -0,0 +1,239 @@
+// A read-only copy of a tree, packed for datasets built once and queried
+// many times. Keys are encoded back to back and front-coded: each stores only
+// what follows the part it shares with the previous key, except every
+// RESTART_INTERVAL-th key, which is stored whole so a lookup can binary
+// search those and decode at most one run of the rest. Values sit in one
+// exactly sized slice, and there are no nodes and no spare capacity.
+
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+use crate::codec::Codec;
+use crate::BTree;
+
+const RESTART_INTERVAL: usize = 16;
+
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(bytes: &[u8], offset: &mut usize) -> usize {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*offset];
+        *offset += 1;
+        n |= ((byte & 0x7f) as usize) << shift;
+        if byte < 0x80 {
+            return n;
+        }
+        shift += 7;
+    }
+}
+
+pub struct FrozenBTree<K: Codec, V> {
+    // Each key as the length it shares with the previous one, the length of
+    // the rest and the rest.
+    keys: Box<[u8]>,
+    // Offset in `keys` of every key stored whole.
+    restarts: Box<[usize]>,
+    values: Box<[V]>,
+    marker: PhantomData<K>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug + Codec, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    pub fn frozen(&self) -> FrozenBTree<K, V> {
+        let mut keys = Vec::new();
+        let mut restarts = Vec::new();
+        let mut values = Vec::new();
+        let mut last = Vec::new();
+        let mut key = Vec::new();
+
+        for (i, (k, value)) in self.iter().enumerate() {
+            key.clear();
+            k.encode(&mut key);
+
+            let shared = if i % RESTART_INTERVAL == 0 {
+                restarts.push(keys.len());
+                0
+            } else {
+                last.iter().zip(&key).take_while(|(a, b)| a == b).count()
+            };
+            write_varint(&mut keys, shared);
+            write_varint(&mut keys, key.len() - shared);
+            keys.extend_from_slice(&key[shared..]);
+
+            values.push(value.clone());
+            std::mem::swap(&mut last, &mut key);
+        }
+
+        FrozenBTree {
+            keys: keys.into_boxed_slice(),
+            restarts: restarts.into_boxed_slice(),
+            values: values.into_boxed_slice(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<K: Codec, V> FrozenBTree<K, V> {
+    // Decodes the key at `offset` onto the previous one in `key`, returning
+    // the offset of the next.
+    fn read_key(&self, mut offset: usize, key: &mut Vec<u8>) -> usize {
+        let shared = read_varint(&self.keys, &mut offset);
+        let len = read_varint(&self.keys, &mut offset);
+        key.truncate(shared);
+        key.extend_from_slice(&self.keys[offset..offset + len]);
+        offset + len
+    }
+
+    fn decode(bytes: &[u8]) -> K {
+        K::decode(bytes).expect("the tree encoded this key")
+    }
+}
+
+impl<K: Ord + Clone + Codec, V> FrozenBTree<K, V> {
+    // The run of keys that `key` would be in: the last one starting at or
+    // before it, or the first.
+    fn run_of(&self, key: &K) -> usize {
+        let mut buf = Vec::new();
+        let after = self.restarts.partition_point(|&offset| {
+            self.read_key(offset, &mut buf);
+            Self::decode(&buf) <= *key
+        });
+        after.saturating_sub(1)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.range((Bound::Included(key), Bound::Included(key)))
+            .next()
+            .map(|(_, value)| value)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            tree: self,
+            index: 0,
+            offset: 0,
+            key: Vec::new(),
+        }
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (K, &V)> + '_ {
+        let mut iter = self.iter();
+        if let Bound::Included(start) | Bound::Excluded(start) = range.start_bound() {
+            let run = self.run_of(start);
+            if let Some(&offset) = self.restarts.get(run) {
+                iter.index = run * RESTART_INTERVAL;
+                iter.offset = offset;
+            }
+        }
+
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        iter.skip_while(move |(key, _)| match &start {
+            Bound::Included(start) => key < start,
+            Bound::Excluded(start) => key <= start,
+            Bound::Unbounded => false,
+        })
+        .take_while(move |(key, _)| match &end {
+            Bound::Included(end) => key <= end,
+            Bound::Excluded(end) => key < end,
+            Bound::Unbounded => true,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    // Bytes taken by the packed keys.
+    pub fn key_bytes(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+pub struct Iter<'a, K: Codec, V> {
+    tree: &'a FrozenBTree<K, V>,
+    index: usize,
+    offset: usize,
+    // The encoded key last decoded.
+    key: Vec<u8>,
+}
+
+impl<'a, K: Codec, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = self.tree.values.get(self.index)?;
+        self.offset = self.tree.read_key(self.offset, &mut self.key);
+        self.index += 1;
+        Some((FrozenBTree::<K, V>::decode(&self.key), value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BTree;
+
+    #[test]
+    fn test_frozen() {
+        let mut tree = BTree::<String, u64>::new(4);
+        for i in 0..1000u64 {
+            tree.add(
+                format!("https://example.com/users/{}/posts/{}", i % 37, i),
+                i,
+            );
+        }
+
+        let frozen = tree.frozen();
+        assert_eq!(frozen.len(), tree.len());
+        assert!(frozen
+            .iter()
+            .map(|(k, v)| (k, *v))
+            .eq(tree.iter().map(|(k, v)| (k.clone(), *v))));
+        for (key, value) in tree.iter() {
+            assert_eq!(frozen.get(key), Some(value));
+        }
+        assert_eq!(frozen.get(&"https://example.com/users/1".to_string()), None);
+        assert_eq!(frozen.get(&"zzz".to_string()), None);
+        assert_eq!(frozen.get(&String::new()), None);
+
+        let start = "https://example.com/users/12".to_string();
+        let end = "https://example.com/users/20".to_string();
+        assert!(frozen
+            .range(start.clone()..end.clone())
+            .map(|(k, v)| (k, *v))
+            .eq(tree.range(start..end).map(|(k, v)| (k.clone(), *v))));
+
+        // Front coding keeps well under half the key bytes.
+        let key_bytes: usize = tree.iter().map(|(key, _)| key.len()).sum();
+        assert!(
+            frozen.key_bytes() * 2 < key_bytes,
+            "{} of {} bytes",
+            frozen.key_bytes(),
+            key_bytes
+        );
+
+        // Keys whose encodings don't sort like the keys still look up fine.
+        let numbers = BTree::from_sorted(3, (0..300u64).map(|i| (i * 7, i)).collect());
+        let frozen = numbers.frozen();
+        for i in 0..300u64 {
+            assert_eq!(frozen.get(&(i * 7)), Some(&i));
+            assert_eq!(frozen.get(&(i * 7 + 1)), None);
+        }
+        assert!(frozen
+            .range(500..=700)
+            .map(|(k, _)| k)
+            .eq(numbers.range(500..=700).map(|(k, _)| *k)));
+        assert!(BTree::<u64, u64>::new(3).frozen().is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+// Leader/follower group commit. Threads that finished their writes call
+// commit to make them durable. The first one becomes the leader and flushes
+// the pager once on behalf of everyone who arrived before its flush started.
+// Those who arrive during the flush wait for the next leader, so the number
+// of fsyncs grows with the flush rate rather than the commit rate.
+
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::pager::Pager;
+use crate::store::PageStore;
+
+#[derive(Default)]
+struct State {
+    // Tickets handed to committers, and the highest ticket made durable.
+    issued: u64,
+    durable: u64,
+    flushing: bool,
+    flushes: u64,
+}
+
+pub struct GroupCommit<S: PageStore> {
+    pager: Arc<Mutex<Pager<S>>>,
+    state: Mutex<State>,
+    flushed: Condvar,
+}
+
+impl<S: PageStore> GroupCommit<S> {
+    pub fn new(pager: Arc<Mutex<Pager<S>>>) -> GroupCommit<S> {
+        GroupCommit {
+            pager,
+            state: Mutex::new(State::default()),
+            flushed: Condvar::new(),
+        }
+    }
+
+    pub fn pager(&self) -> &Arc<Mutex<Pager<S>>> {
+        &self.pager
+    }
+
+    // Returns once every page written before the call is durable.
+    pub fn commit(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.issued += 1;
+        let ticket = state.issued;
+
+        while state.durable < ticket {
+            if state.flushing {
+                state = self.flushed.wait(state).unwrap();
+                continue;
+            }
+
+            state.flushing = true;
+            let covered = state.issued;
+            drop(state);
+
+            let result = self.pager.lock().unwrap().flush();
+
+            state = self.state.lock().unwrap();
+            state.flushing = false;
+            state.flushes += 1;
+            if result.is_ok() {
+                state.durable = covered;
+            }
+            self.flushed.notify_all();
+            result?;
+        }
+
+        Ok(())
+    }
+
+    // Number of flushes leaders have run.
+    pub fn flushes(&self) -> u64 {
+        self.state.lock().unwrap().flushes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::group_commit::GroupCommit;
+    use crate::pager::Pager;
+    use crate::store::{MemoryStore, PageStore};
+
+    // A store whose fsync takes a while, like a real disk.
+    struct SlowSync(MemoryStore);
+
+    impl PageStore for SlowSync {
+        fn size(&mut self) -> io::Result<u64> {
+            self.0.size()
+        }
+
+        fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+            self.0.read_at(offset, buf)
+        }
+
+        fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+            self.0.write_at(offset, buf)
+        }
+
+        fn set_size(&mut self, size: u64) -> io::Result<()> {
+            self.0.set_size(size)
+        }
+
+        fn sync(&mut self) -> io::Result<()> {
+            std::thread::sleep(Duration::from_millis(5));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let pager = Pager::with_store(SlowSync(MemoryStore::new()), 512, 3, 64).unwrap();
+        let group = GroupCommit::new(Arc::new(Mutex::new(pager)));
+
+        std::thread::scope(|scope| {
+            for thread in 0..8u8 {
+                let group = &group;
+                scope.spawn(move || {
+                    for _ in 0..5 {
+                        {
+                            let mut pager = group.pager().lock().unwrap();
+                            let id = pager.allocate().unwrap();
+                            pager.page_mut(id).fill(thread);
+                            pager.unpin(id, true).unwrap();
+                        }
+                        group.commit().unwrap();
+                    }
+                });
+            }
+        });
+
+        let pager = group.pager().lock().unwrap();
+        assert_eq!(pager.num_pages(), 1 + 40);
+        assert_eq!(pager.store().0.bytes().len(), 41 * 512);
+        assert!(group.flushes() < 40, "{} flushes", group.flushes());
+    }
+}
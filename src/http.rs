@@ -0,0 +1,404 @@
+// A small HTTP/1.1 key-value service over a tree of strings saved in a file,
+// for prototypes. Connections are handled one at a time and closed after
+// each response. Every change is written back to the file before it is
+// acknowledged.
+//
+//   GET    /keys/<key>                        the entry, or 404
+//   PUT    /keys/<key>                        sets the value to the body
+//   DELETE /keys/<key>                        removes the entry, or 404
+//   GET    /range?start=<a>&end=<b>&limit=<n> entries from a up to but not
+//                                             including b, all optional
+//
+// Keys in paths and queries are percent-encoded. Entries come back as JSON
+// objects, {"key":...,"value":...}, and ranges as arrays of them.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::json::Json;
+use crate::BTree;
+
+// Used when the file doesn't exist yet.
+const NODE_SIZE: usize = 32;
+
+// The largest request body accepted.
+const MAX_BODY: usize = 16 << 20;
+
+// The longest request or header line accepted, and the most headers.
+const MAX_LINE: usize = 8 << 10;
+const MAX_HEADERS: usize = 100;
+
+// How long a client may stall before its connection is dropped, since
+// connections are handled one at a time.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn error(status: u16, message: &str) -> Response {
+        let mut body = String::from("{\"error\":");
+        message.to_string().write_json(&mut body);
+        body.push('}');
+        Response { status, body }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+fn entry_json(out: &mut String, key: &String, value: &String) {
+    out.push_str("{\"key\":");
+    key.write_json(out);
+    out.push_str(",\"value\":");
+    value.write_json(out);
+    out.push('}');
+}
+
+fn entry(key: &String, value: &String) -> Response {
+    let mut body = String::new();
+    entry_json(&mut body, key, value);
+    Response { status: 200, body }
+}
+
+fn hex(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+// Decodes %XX escapes, and `+` as a space if `plus` is set as in queries.
+fn percent_decode(text: &str, plus: bool) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'%' => bytes.push(hex(input.next()?)? << 4 | hex(input.next()?)?),
+            b'+' if plus => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+pub struct Server {
+    tree: BTree<String, String>,
+    path: PathBuf,
+}
+
+impl Server {
+    // Serves the tree saved at `path`, or an empty tree whose file is
+    // created by the first change.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Server> {
+        let path = path.as_ref().to_path_buf();
+        let tree = match BTree::load(&path) {
+            Ok(tree) => tree,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTree::new(NODE_SIZE),
+            Err(err) => return Err(err),
+        };
+        Ok(Server { tree, path })
+    }
+
+    // Writes to a temporary file and renames it over the old one, so the
+    // file holds either the old tree or the new one.
+    fn save(&self) -> io::Result<()> {
+        let mut temp = self.path.as_os_str().to_owned();
+        temp.push(".tmp");
+        self.tree.save(&temp)?;
+        fs::rename(&temp, &self.path)
+    }
+
+    fn range(&self, query: &str) -> Response {
+        let (mut start, mut end, mut limit) = (None, None, usize::MAX);
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let Some(value) = percent_decode(value, true) else {
+                return Response::error(400, "bad percent-encoding in query");
+            };
+            match name {
+                "start" => start = Some(value),
+                "end" => end = Some(value),
+                "limit" => match value.parse() {
+                    Ok(n) => limit = n,
+                    Err(_) => return Response::error(400, "limit must be a number"),
+                },
+                _ => return Response::error(400, &format!("unknown parameter {}", name)),
+            }
+        }
+
+        let start = start.as_ref().map_or(Bound::Unbounded, Bound::Included);
+        let end = end.as_ref().map_or(Bound::Unbounded, Bound::Excluded);
+        if let (Bound::Included(start), Bound::Excluded(end)) = (start, end) {
+            if start > end {
+                return Response::error(400, "start is after end");
+            }
+        }
+
+        let mut body = String::from("[");
+        for (i, (key, value)) in self.tree.range((start, end)).take(limit).enumerate() {
+            if i > 0 {
+                body.push(',');
+            }
+            entry_json(&mut body, key, value);
+        }
+        body.push(']');
+        Response { status: 200, body }
+    }
+
+    // Answers one request. Fails only if a change can't be saved, in which
+    // case the tree may hold a change the file doesn't.
+    pub fn handle(&mut self, method: &str, target: &str, body: &[u8]) -> io::Result<Response> {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        if path == "/range" {
+            return Ok(match method {
+                "GET" => self.range(query),
+                _ => Response::error(405, "range only supports GET"),
+            });
+        }
+        let Some(key) = path.strip_prefix("/keys/") else {
+            return Ok(Response::error(404, "no such endpoint"));
+        };
+        let Some(key) = percent_decode(key, false) else {
+            return Ok(Response::error(400, "bad percent-encoding in key"));
+        };
+
+        Ok(match method {
+            "GET" => match self.tree.get(&key) {
+                Some(value) => entry(&key, value),
+                None => Response::error(404, "not found"),
+            },
+            "PUT" => {
+                let Ok(value) = String::from_utf8(body.to_vec()) else {
+                    return Ok(Response::error(400, "value must be utf-8"));
+                };
+                let response = entry(&key, &value);
+                self.tree.add(key, value);
+                self.save()?;
+                response
+            }
+            "DELETE" => match self.tree.remove(&key) {
+                Some(value) => {
+                    self.save()?;
+                    entry(&key, &value)
+                }
+                None => Response::error(404, "not found"),
+            },
+            _ => Response::error(405, "keys support GET, PUT and DELETE"),
+        })
+    }
+
+    // Answers one request on the stream. A request that isn't valid HTTP gets
+    // a 400, or a 431 if its headers are too long or too many, and a client
+    // that stalls or hangs up loses only its own request.
+    // Fails only if a change can't be saved, after answering with a 500.
+    fn connection(&mut self, stream: TcpStream) -> io::Result<()> {
+        let _ = stream.set_read_timeout(Some(TIMEOUT));
+        let mut reader = BufReader::new(&stream);
+        let Ok(request) = read_request(&mut reader) else {
+            return Ok(());
+        };
+        let (response, result) = match request {
+            Ok((method, target, body)) => match self.handle(&method, &target, &body) {
+                Ok(response) => (response, Ok(())),
+                Err(err) => (Response::error(500, &err.to_string()), Err(err)),
+            },
+            Err(response) => (response, Ok(())),
+        };
+        let _ = write_response(&stream, &response);
+        result
+    }
+
+    // Handles connections one at a time until accepting one or saving a
+    // change fails.
+    pub fn serve(&mut self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            self.connection(stream?)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_response(mut out: &TcpStream, response: &Response) -> io::Result<()> {
+    write!(
+        out,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        response.status,
+        response.reason(),
+        response.body.len(),
+        response.body
+    )?;
+    out.flush()
+}
+
+type Request = (String, String, Vec<u8>);
+
+// Reads one line into `line`, or returns None if it is longer than
+// MAX_LINE, so a client can't make a line grow without end.
+fn read_line<R: BufRead>(input: &mut R, line: &mut String) -> io::Result<Option<usize>> {
+    line.clear();
+    let n = input.by_ref().take(MAX_LINE as u64).read_line(line)?;
+    if n == MAX_LINE && !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(Some(n))
+}
+
+// The method, target and body of a request, or the response to send back
+// if it is malformed.
+fn read_request<R: BufRead>(input: &mut R) -> io::Result<Result<Request, Response>> {
+    let mut line = String::new();
+    if read_line(input, &mut line)?.is_none() {
+        return Ok(Err(Response::error(400, "request line too long")));
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Ok(Err(Response::error(400, "bad request line")));
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+
+    let mut length = 0;
+    let mut headers = 0;
+    loop {
+        match read_line(input, &mut line)? {
+            None => return Ok(Err(Response::error(431, "header line too long"))),
+            Some(0) => return Ok(Err(Response::error(400, "headers end early"))),
+            Some(_) => {}
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Ok(Err(Response::error(431, "too many headers")));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                match value.trim().parse() {
+                    Ok(n) => length = n,
+                    Err(_) => return Ok(Err(Response::error(400, "bad Content-Length"))),
+                }
+            }
+        }
+    }
+
+    if length > MAX_BODY {
+        return Ok(Err(Response::error(413, "body too large")));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Ok((method, target, body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    use crate::http::{read_request, Server, MAX_HEADERS, MAX_LINE};
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_http() {
+        let dir = TempDir::new("http");
+        let path = dir.join("tree");
+        let mut server = Server::open(&path).unwrap();
+        let mut call = |method: &str, target: &str, body: &str| {
+            let response = server.handle(method, target, body.as_bytes()).unwrap();
+            (response.status, response.body)
+        };
+
+        for key in ["b", "a", "c d", "e/f"] {
+            let target = format!("/keys/{}", key.replace(' ', "%20").replace('/', "%2F"));
+            let status = call("PUT", &target, &format!("value of {}", key)).0;
+            assert_eq!(status, 200);
+        }
+        assert_eq!(
+            call("GET", "/keys/c%20d", ""),
+            (
+                200,
+                "{\"key\":\"c d\",\"value\":\"value of c d\"}".to_string()
+            )
+        );
+        assert_eq!(call("GET", "/keys/zz", "").0, 404);
+        assert_eq!(call("DELETE", "/keys/b", "").0, 200);
+        assert_eq!(call("DELETE", "/keys/b", "").0, 404);
+
+        let keys = |body: String| -> Vec<String> {
+            body.split("{\"key\":\"")
+                .skip(1)
+                .map(|entry| entry[..entry.find('"').unwrap()].to_string())
+                .collect()
+        };
+        assert_eq!(keys(call("GET", "/range", "").1), ["a", "c d", "e/f"]);
+        assert_eq!(keys(call("GET", "/range?start=b&end=e", "").1), ["c d"]);
+        assert_eq!(keys(call("GET", "/range?start=c+d&limit=1", "").1), ["c d"]);
+        assert_eq!(call("GET", "/range?end=a", "").1, "[]");
+        assert_eq!(call("GET", "/range?limit=x", "").0, 400);
+        assert_eq!(call("GET", "/range?start=z&end=a", "").0, 400);
+        assert_eq!(call("POST", "/keys/a", "").0, 405);
+        assert_eq!(call("GET", "/keys/%zz", "").0, 400);
+        assert_eq!(call("GET", "/other", "").0, 404);
+
+        // Changes were saved as they were made.
+        let mut reopened = Server::open(&path).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let (stream, _) = listener.accept().unwrap();
+                reopened.connection(stream).unwrap();
+            });
+
+            let mut client = TcpStream::connect(addr).unwrap();
+            client
+                .write_all(b"PUT /keys/new HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello")
+                .unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(response.ends_with("\r\n\r\n{\"key\":\"new\",\"value\":\"hello\"}"));
+        });
+        assert_eq!(reopened.tree.len(), 4);
+    }
+
+    #[test]
+    fn test_read_request() {
+        let status = |request: &[u8]| match read_request(&mut &request[..]).unwrap() {
+            Ok(_) => 200,
+            Err(response) => response.status,
+        };
+
+        assert_eq!(status(b"GET /keys/a HTTP/1.1\r\nHost: x\r\n\r\n"), 200);
+        assert_eq!(status(b"GET /keys/a\r\n\r\n"), 400);
+        assert_eq!(status(b"GET /keys/a HTTP/1.1\r\nHost: x\r\n"), 400);
+
+        // Lines stop being read at the limit rather than growing.
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(status(long.as_bytes()), 400);
+        let long = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(status(long.as_bytes()), 431);
+        let endless = [b"GET / HTTP/1.1\r\nX: ".as_slice(), &[b'a'; 1 << 20]].concat();
+        assert_eq!(status(&endless), 431);
+
+        let headers = |n: usize| format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(n));
+        assert_eq!(status(headers(MAX_HEADERS).as_bytes()), 200);
+        assert_eq!(status(headers(MAX_HEADERS + 1).as_bytes()), 431);
+    }
+}
@@ -0,0 +1,191 @@
+// Half-open intervals [start, end), ordered by start and then end, in a
+// B-tree whose nodes also know the largest end below them, as an
+// AggregateBTree's MaxEnd summaries. A query skips any subtree whose
+// intervals all end before it begins, and stops once the intervals start
+// after it ends.
+
+use std::ops::{Bound, Range};
+
+use crate::aggregate::{AggregateBTree, Monoid, Node};
+
+// Largest end among the intervals summarised, None over none.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct MaxEnd<K>(Option<K>);
+
+impl<K: Ord + Clone, V> Monoid<(K, K), V> for MaxEnd<K> {
+    fn empty() -> MaxEnd<K> {
+        MaxEnd(None)
+    }
+
+    fn of((_, end): &(K, K), _: &V) -> MaxEnd<K> {
+        MaxEnd(Some(end.clone()))
+    }
+
+    fn combine(&self, right: &MaxEnd<K>) -> MaxEnd<K> {
+        MaxEnd(self.0.clone().max(right.0.clone()))
+    }
+}
+
+// Collects the intervals in `node` that end after `after` and start before
+// `upto`.
+fn query<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug>(
+    node: &'a Node<(K, K), V, MaxEnd<K>>,
+    after: &K,
+    upto: Bound<&K>,
+    out: &mut Vec<(&'a (K, K), &'a V)>,
+) {
+    match &node.summary.0 {
+        Some(max_end) if max_end > after => {}
+        _ => return,
+    }
+
+    for i in 0..=node.keys.len() {
+        if let Some(child) = node.children.get(i) {
+            query(child, after, upto, out);
+        }
+        let Some(key) = node.keys.get(i) else {
+            break;
+        };
+
+        let started = match upto {
+            Bound::Included(upto) => key.0 <= *upto,
+            Bound::Excluded(upto) => key.0 < *upto,
+            Bound::Unbounded => true,
+        };
+        if !started {
+            break;
+        }
+        if key.1 > *after {
+            out.push((key, &node.values[i]));
+        }
+    }
+}
+
+pub struct IntervalTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    tree: AggregateBTree<(K, K), V, MaxEnd<K>>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> IntervalTree<K, V> {
+    pub fn new(node_size: usize) -> IntervalTree<K, V> {
+        IntervalTree {
+            tree: AggregateBTree::new(node_size),
+        }
+    }
+
+    // Returns the previous value stored for exactly this interval.
+    pub fn insert(&mut self, interval: Range<K>, value: V) -> Option<V> {
+        assert!(
+            interval.start <= interval.end,
+            "interval ends before it starts"
+        );
+
+        self.tree.insert((interval.start, interval.end), value)
+    }
+
+    pub fn remove(&mut self, interval: &Range<K>) -> Option<V> {
+        self.tree
+            .remove(&(interval.start.clone(), interval.end.clone()))
+    }
+
+    // Intervals containing `point`, ordered by start.
+    pub fn stab(&self, point: &K) -> Vec<(&(K, K), &V)> {
+        let mut out = Vec::new();
+        // start <= point < end
+        query(self.tree.root(), point, Bound::Included(point), &mut out);
+        out
+    }
+
+    // Intervals sharing at least one point with `range`, ordered by start.
+    pub fn overlapping(&self, range: Range<K>) -> Vec<(&(K, K), &V)> {
+        let mut out = Vec::new();
+        if range.start < range.end {
+            query(
+                self.tree.root(),
+                &range.start,
+                Bound::Excluded(&range.end),
+                &mut out,
+            );
+        }
+        out
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::aggregate::Node;
+    use crate::interval::{IntervalTree, MaxEnd};
+    use crate::testutil::Rng;
+
+    // Checks the largest ends and node sizes, returning the depth of the
+    // leaves.
+    fn check(node: &Node<(u64, u64), u64, MaxEnd<u64>>, node_size: usize, root: bool) -> usize {
+        assert!(node.keys.len() <= node_size);
+        assert!(root || node.keys.len() >= node_size / 2);
+
+        let ends = node.keys.iter().map(|(_, end)| *end);
+        let below = node.children.iter().filter_map(|child| child.summary.0);
+        assert_eq!(node.summary, MaxEnd(ends.chain(below).max()));
+
+        let depths: Vec<usize> = node
+            .children
+            .iter()
+            .map(|child| check(child, node_size, false))
+            .collect();
+        assert!(depths.windows(2).all(|pair| pair[0] == pair[1]));
+        depths.first().map_or(0, |depth| depth + 1)
+    }
+
+    #[test]
+    fn test_interval_tree() {
+        for node_size in [2, 3, 6] {
+            let mut tree = IntervalTree::<u64, u64>::new(node_size);
+            let mut expected = BTreeMap::new();
+            let mut rng = Rng::new();
+
+            for step in 0..3000 {
+                let state = rng.next_u64();
+                let start = state % 1000;
+                let end = start + (state >> 20) % 60;
+
+                if state.is_multiple_of(3) {
+                    assert_eq!(tree.remove(&(start..end)), expected.remove(&(start, end)));
+                } else {
+                    assert_eq!(
+                        tree.insert(start..end, step),
+                        expected.insert((start, end), step)
+                    );
+                }
+
+                if step % 50 == 0 {
+                    check(tree.tree.root(), node_size, true);
+
+                    let point = (state >> 32) % 1000;
+                    let stabbed: Vec<_> = expected
+                        .iter()
+                        .filter(|((start, end), _)| *start <= point && point < *end)
+                        .collect();
+                    assert_eq!(tree.stab(&point), stabbed);
+
+                    let range = point..point + 25;
+                    let overlapping: Vec<_> = expected
+                        .iter()
+                        .filter(|((start, end), _)| *start < range.end && range.start < *end)
+                        .collect();
+                    assert_eq!(tree.overlapping(range), overlapping);
+                }
+            }
+
+            assert_eq!(tree.len(), expected.len());
+        }
+    }
+}
@@ -0,0 +1,295 @@
+// Moving entries in and out as newline-delimited JSON, one object per entry
+// in key order:
+//
+//   {"key":"alice","value":42}
+//
+// This is the form jq reads and writes with -c, and both directions stream a
+// line at a time.
+
+use std::io::{self, BufRead, Write};
+
+use crate::codec::invalid_data;
+use crate::BTree;
+
+// A type that can stand as a key or value in the JSON form.
+pub trait Json: Sized {
+    fn write_json(&self, out: &mut String);
+    fn read_json(parser: &mut Parser) -> io::Result<Self>;
+}
+
+macro_rules! int_json {
+    ($($t:ty),*) => {
+        $(
+            impl Json for $t {
+                fn write_json(&self, out: &mut String) {
+                    out.push_str(&self.to_string());
+                }
+
+                fn read_json(parser: &mut Parser) -> io::Result<Self> {
+                    parser
+                        .token()
+                        .parse()
+                        .map_err(|_| invalid_data(concat!("expected a JSON ", stringify!($t))))
+                }
+            }
+        )*
+    };
+}
+
+int_json!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl Json for String {
+    fn write_json(&self, out: &mut String) {
+        out.push('"');
+        for c in self.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+
+    fn read_json(parser: &mut Parser) -> io::Result<Self> {
+        parser.string()
+    }
+}
+
+// Reads JSON from one line.
+pub struct Parser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.offset..];
+        self.offset += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.input[self.offset..].chars().next()
+    }
+
+    fn next_char(&mut self) -> io::Result<char> {
+        let c = self.input[self.offset..]
+            .chars()
+            .next()
+            .ok_or_else(|| invalid_data("unexpected end of JSON"))?;
+        self.offset += c.len_utf8();
+        Ok(c)
+    }
+
+    fn expect(&mut self, want: char) -> io::Result<()> {
+        self.skip_whitespace();
+        match self.next_char()? {
+            c if c == want => Ok(()),
+            c => Err(invalid_data(format!(
+                "expected '{}' in JSON, found '{}'",
+                want, c
+            ))),
+        }
+    }
+
+    // A bare number or literal, up to the next delimiter.
+    pub fn token(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let rest = &self.input[self.offset..];
+        let len = rest
+            .find(|c: char| c == ',' || c == '}' || c == ']' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        self.offset += len;
+        &rest[..len]
+    }
+
+    fn hex4(&mut self) -> io::Result<u32> {
+        let digits = self
+            .input
+            .get(self.offset..self.offset + 4)
+            .ok_or_else(|| invalid_data("short \\u escape in JSON"))?;
+        self.offset += 4;
+        // from_str_radix would also take a leading +.
+        if !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(invalid_data("bad \\u escape in JSON"));
+        }
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    pub fn string(&mut self) -> io::Result<String> {
+        self.expect('"')?;
+        let mut out = String::new();
+        loop {
+            match self.next_char()? {
+                '"' => return Ok(out),
+                '\\' => {
+                    let c = match self.next_char()? {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // Characters outside the basic plane come as a
+                            // surrogate pair.
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.input[self.offset..].starts_with("\\u")
+                            {
+                                self.offset += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(invalid_data("bad surrogate pair in JSON"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code)
+                                .ok_or_else(|| invalid_data("bad \\u escape in JSON"))?
+                        }
+                        c => return Err(invalid_data(format!("bad escape '\\{}' in JSON", c))),
+                    };
+                    out.push(c);
+                }
+                c => out.push(c),
+            }
+        }
+    }
+}
+
+// Parses one line into a key and value.
+fn read_entry<K: Json, V: Json>(line: &str) -> io::Result<(K, V)> {
+    let mut parser = Parser {
+        input: line,
+        offset: 0,
+    };
+    let mut key = None;
+    let mut value = None;
+
+    parser.expect('{')?;
+    if parser.peek() != Some('}') {
+        loop {
+            match parser.string()?.as_str() {
+                "key" if key.is_none() => {
+                    parser.expect(':')?;
+                    key = Some(K::read_json(&mut parser)?);
+                }
+                "value" if value.is_none() => {
+                    parser.expect(':')?;
+                    value = Some(V::read_json(&mut parser)?);
+                }
+                field => return Err(invalid_data(format!("unexpected field \"{}\"", field))),
+            }
+            if parser.peek() != Some(',') {
+                break;
+            }
+            parser.expect(',')?;
+        }
+    }
+    parser.expect('}')?;
+    if parser.peek().is_some() {
+        return Err(invalid_data("trailing characters after JSON object"));
+    }
+
+    match (key, value) {
+        (Some(key), Some(value)) => Ok((key, value)),
+        _ => Err(invalid_data("JSON object needs both \"key\" and \"value\"")),
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug + Json, V: Ord + Clone + std::fmt::Debug + Json> BTree<K, V> {
+    pub fn export_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut line = String::new();
+        for (key, value) in self.iter() {
+            line.clear();
+            line.push_str("{\"key\":");
+            key.write_json(&mut line);
+            line.push_str(",\"value\":");
+            value.write_json(&mut line);
+            line.push_str("}\n");
+            out.write_all(line.as_bytes())?;
+        }
+        out.flush()
+    }
+
+    // Inserts every entry read, in any order, replacing existing values.
+    // Blank lines are skipped. Returns how many entries were read; on an
+    // error, those before the bad line stay inserted.
+    pub fn import_json<R: BufRead>(&mut self, input: R) -> io::Result<usize> {
+        let mut count = 0;
+        for (number, line) in input.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (key, value) = read_entry(&line)
+                .map_err(|err| invalid_data(format!("line {}: {}", number + 1, err)))?;
+            self.add(key, value);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BTree;
+
+    #[test]
+    fn test_json() {
+        let mut tree = BTree::<String, i64>::new(3);
+        let names = [
+            "plain",
+            "quote \" and \\ slash",
+            "tab\tnew\nline",
+            "\u{1}",
+            "caf\u{e9} \u{1f600}",
+        ];
+        for (i, name) in names.iter().enumerate() {
+            tree.add(name.to_string(), i as i64 * -7);
+        }
+
+        let mut out = Vec::new();
+        tree.export_json(&mut out).unwrap();
+        let text = String::from_utf8(out.clone()).unwrap();
+        assert_eq!(text.lines().count(), names.len());
+        assert!(text.contains("{\"key\":\"plain\",\"value\":0}\n"));
+        assert!(text.contains("\"tab\\tnew\\nline\""));
+        assert!(text.contains("\"\\u0001\""));
+
+        let mut copy = BTree::<String, i64>::new(4);
+        assert_eq!(copy.import_json(out.as_slice()).unwrap(), names.len());
+        assert!(copy.iter().eq(tree.iter()));
+
+        // What other tools write: reordered fields, spacing, escapes this
+        // side never produces.
+        let input = "{ \"value\" : 5 , \"key\" : \"a\\/b\" }\n\n{\"key\":\"\\ud83d\\ude00\",\"value\":-1}\n";
+        let mut other = BTree::<String, i64>::new(3);
+        assert_eq!(other.import_json(input.as_bytes()).unwrap(), 2);
+        assert_eq!(other.get(&"a/b".to_string()), Some(&5));
+        assert_eq!(other.get(&"\u{1f600}".to_string()), Some(&-1));
+
+        for bad in [
+            "{\"key\":\"a\"}",
+            "{\"key\":\"a\",\"value\":1,\"extra\":2}",
+            "{\"key\":\"a\",\"value\":\"1\"}",
+            "{\"key\":\"a\",\"value\":1} x",
+            "{\"key\":\"a",
+            "{\"key\":\"\\u+041\",\"value\":1}",
+            "[\"a\",1]",
+        ] {
+            let input = format!("{{\"key\":\"ok\",\"value\":1}}\n{}\n", bad);
+            let err = other.import_json(input.as_bytes()).unwrap_err();
+            assert!(err.to_string().starts_with("line 2: "), "{}: {}", bad, err);
+        }
+        assert_eq!(other.get(&"ok".to_string()), Some(&1));
+    }
+}
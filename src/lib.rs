@@ -0,0 +1,1040 @@
+pub mod aggregate;
+pub mod backend;
+pub mod batch;
+pub mod betree;
+pub mod bounded;
+pub mod bplus;
+pub mod bstar;
+pub mod buffered;
+pub mod checksum;
+pub mod codec;
+pub mod columns;
+pub mod composite;
+pub mod counted;
+pub mod csv;
+pub mod delta;
+pub mod descending;
+pub mod diff;
+pub mod external;
+pub mod ffi;
+pub mod frozen;
+pub mod group_commit;
+pub mod http;
+pub mod interval;
+pub mod json;
+pub mod locks;
+pub mod merge;
+pub mod merkle;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mvcc;
+pub mod oplog;
+pub mod overflow;
+pub mod pager;
+pub mod persistent;
+pub mod prefix;
+pub mod query;
+pub mod runs;
+pub mod scan;
+pub mod sharded;
+pub mod shared;
+pub mod slotted;
+pub mod snapshot;
+pub mod sstable;
+pub mod store;
+#[cfg(test)]
+mod testutil;
+pub mod transaction;
+pub mod ttl;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::codec::{invalid_data, read_item, write_item, Codec};
+
+// Node sizes read_from accepts. A node needs room for two keys to split, and
+// the size comes from the input, where a huge one would allocate that much
+// for every node.
+const MIN_NODE_SIZE: u64 = 2;
+const MAX_NODE_SIZE: u64 = 1 << 16;
+
+pub(crate) fn check_node_size(node_size: u64) -> io::Result<usize> {
+    if !(MIN_NODE_SIZE..=MAX_NODE_SIZE).contains(&node_size) {
+        return Err(invalid_data(format!(
+            "node size {} is not between {} and {}",
+            node_size, MIN_NODE_SIZE, MAX_NODE_SIZE
+        )));
+    }
+    Ok(node_size as usize)
+}
+
+// Children are shared with snapshots and copied on write through Arc::make_mut.
+#[derive(Clone)]
+struct BTreeNode<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    node_size: usize,
+    keys: Vec<K>,
+    values: Vec<V>,
+    children: Vec<Arc<BTreeNode<K, V>>>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTreeNode<K, V> {
+    fn new(node_size: usize) -> BTreeNode<K, V> {
+        BTreeNode {
+            node_size,
+            keys: Vec::with_capacity(node_size + 1),
+            values: Vec::with_capacity(node_size + 1),
+            children: Vec::with_capacity(node_size + 1),
+        }
+    }
+
+    fn generate_find_path(&self, key: &K) -> Vec<usize> {
+        let mut stack = Vec::<usize>::new();
+        let mut current_node = self;
+
+        loop {
+            let i = BTreeNode::<K, V>::find_it(&current_node.keys, key);
+            if i < 0 {
+                stack.push(-(i + 1) as usize);
+                break;
+            } else if (i as usize) < current_node.children.len() {
+                current_node = &current_node.children[i as usize];
+                stack.push(i as usize);
+            } else {
+                stack.clear();
+                break;
+            }
+        }
+
+        stack.reverse();
+
+        stack
+    }
+
+    fn find_it(keys: &[K], key: &K) -> i32 {
+        let mut low = 0;
+        let mut high = keys.len() as i32;
+
+        while high != low {
+            let mid = (high + low) / 2;
+
+            if key < &keys[mid as usize] {
+                high = mid;
+            } else if key > &keys[mid as usize] {
+                low = mid + 1;
+            } else {
+                // Return early, exact key found
+                return -mid - 1;
+            }
+        }
+
+        low
+    }
+
+    fn find(&self, key: &K) -> Option<V> {
+        let mut current_node = self;
+        let mut path = self.generate_find_path(key);
+        let mut key_index = 0;
+
+        while let Some(index) = path.pop() {
+            if path.is_empty() {
+                // Last part of path is leaf node. Value is the index of k.
+                key_index = index;
+                break;
+            }
+
+            current_node = &current_node.children[index];
+        }
+
+        if current_node.keys.get(key_index) == Some(key) {
+            return Some(current_node.values[key_index].clone());
+        }
+
+        None
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        let mut node = self;
+
+        loop {
+            let i = BTreeNode::<K, V>::find_it(&node.keys, key);
+            if i < 0 {
+                return Some(&node.values[-(i + 1) as usize]);
+            }
+            node = node.children.get(i as usize)?;
+        }
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let mut node = self;
+
+        loop {
+            let i = BTreeNode::<K, V>::find_it(&node.keys, key);
+            if i < 0 {
+                return Some(&mut node.values[-(i + 1) as usize]);
+            }
+            node = Arc::make_mut(node.children.get_mut(i as usize)?);
+        }
+    }
+
+    // Smallest number of keys a node other than the root may hold, which is
+    // what the smaller half of a split ends up with.
+    fn min_keys(&self) -> usize {
+        self.node_size / 2
+    }
+
+    fn remove_recursive(&mut self, key: &K) -> Option<(K, V)> {
+        let i = BTreeNode::<K, V>::find_it(&self.keys, key);
+
+        if i < 0 {
+            let index = -(i + 1) as usize;
+            if self.children.is_empty() {
+                return Some((self.keys.remove(index), self.values.remove(index)));
+            }
+
+            // Replace the entry with its predecessor from the left subtree.
+            let (key, value) = Arc::make_mut(&mut self.children[index]).pop_max();
+            let key = std::mem::replace(&mut self.keys[index], key);
+            let value = std::mem::replace(&mut self.values[index], value);
+            self.fix_child(index);
+
+            return Some((key, value));
+        }
+
+        let index = i as usize;
+        let removed = Arc::make_mut(self.children.get_mut(index)?).remove_recursive(key);
+        if removed.is_some() {
+            self.fix_child(index);
+        }
+
+        removed
+    }
+
+    fn pop_max(&mut self) -> (K, V) {
+        if self.children.is_empty() {
+            return (self.keys.pop().unwrap(), self.values.pop().unwrap());
+        }
+
+        let last = self.children.len() - 1;
+        let entry = Arc::make_mut(&mut self.children[last]).pop_max();
+        self.fix_child(last);
+
+        entry
+    }
+
+    // Refills children[index] after a removal left it with too few keys, by
+    // borrowing from a sibling or merging with one.
+    fn fix_child(&mut self, index: usize) {
+        let min = self.min_keys();
+        if self.children[index].keys.len() >= min {
+            return;
+        }
+
+        if index > 0 && self.children[index - 1].keys.len() > min {
+            let (left, right) = self.children.split_at_mut(index);
+            let (left, child) = (Arc::make_mut(&mut left[index - 1]), Arc::make_mut(&mut right[0]));
+
+            let key = std::mem::replace(&mut self.keys[index - 1], left.keys.pop().unwrap());
+            let value = std::mem::replace(&mut self.values[index - 1], left.values.pop().unwrap());
+            child.keys.insert(0, key);
+            child.values.insert(0, value);
+            if let Some(grandchild) = left.children.pop() {
+                child.children.insert(0, grandchild);
+            }
+        } else if index + 1 < self.children.len() && self.children[index + 1].keys.len() > min {
+            let (left, right) = self.children.split_at_mut(index + 1);
+            let (child, right) = (Arc::make_mut(&mut left[index]), Arc::make_mut(&mut right[0]));
+
+            let key = std::mem::replace(&mut self.keys[index], right.keys.remove(0));
+            let value = std::mem::replace(&mut self.values[index], right.values.remove(0));
+            child.keys.push(key);
+            child.values.push(value);
+            if !right.children.is_empty() {
+                child.children.push(right.children.remove(0));
+            }
+        } else if index > 0 {
+            self.merge_children(index - 1);
+        } else if self.children.len() > 1 {
+            self.merge_children(index);
+        }
+    }
+
+    // Merges children[index + 1] and the key separating them into children[index].
+    fn merge_children(&mut self, index: usize) {
+        #[cfg(feature = "metrics")]
+        metrics::METRICS.merges.inc();
+        let right = Arc::unwrap_or_clone(self.children.remove(index + 1));
+        let key = self.keys.remove(index);
+        let value = self.values.remove(index);
+
+        let left = Arc::make_mut(&mut self.children[index]);
+        left.keys.push(key);
+        left.values.push(value);
+        left.keys.extend(right.keys);
+        left.values.extend(right.values);
+        left.children.extend(right.children);
+    }
+
+    fn split(&mut self) -> BTreeNode<K, V> {
+        #[cfg(feature = "metrics")]
+        metrics::METRICS.splits.inc();
+        let mid = self.keys.len() / 2;
+
+        let mut new_node = BTreeNode::<K, V>::new(self.node_size);
+        new_node.keys = self.keys.drain(mid..).collect();
+        new_node.values = self.values.drain(mid..).collect();
+        if !self.children.is_empty() {
+            new_node.children = self.children.drain(mid + 1..).collect();
+        }
+
+        new_node
+    }
+
+    fn add_recursive(&mut self, key: K, value: V) -> Added<K, V> {
+        let i = BTreeNode::<K, V>::find_it(&self.keys, &key);
+        if i < 0 {
+            // The key is already here, in a leaf or as a separator.
+            let index = -(i + 1) as usize;
+            return Added::Replaced(std::mem::replace(&mut self.values[index], value));
+        }
+
+        let index = i as usize;
+        if self.children.is_empty() {
+            // Add directly to leaf node
+            self.keys.insert(index, key);
+            self.values.insert(index, value);
+        } else {
+            let children = &mut self.children;
+
+            assert!(index <= children.len() + 1);
+
+            match Arc::make_mut(&mut children[index]).add_recursive(key, value) {
+                Added::Replaced(old) => return Added::Replaced(old),
+                Added::Inserted(Some(mut new_node)) => {
+                    let new_key = new_node.keys.remove(0);
+                    let new_value = new_node.values.remove(0);
+
+                    children.insert(index + 1, Arc::new(new_node));
+                    self.keys.insert(index, new_key);
+                    self.values.insert(index, new_value);
+                }
+                Added::Inserted(None) => {}
+            }
+        }
+
+        if self.keys.len() == self.node_size + 1 {
+            return Added::Inserted(Some(self.split()));
+        }
+
+        Added::Inserted(None)
+    }
+
+    // Builds a subtree bottom-up from sorted entries. `children` is either empty,
+    // for the leaf level, or holds one more node than there are entries.
+    fn build(
+        node_size: usize,
+        mut entries: Vec<(K, V)>,
+        mut children: Vec<Arc<BTreeNode<K, V>>>,
+    ) -> BTreeNode<K, V> {
+        let n = entries.len();
+
+        if n <= node_size {
+            let mut node = BTreeNode::<K, V>::new(node_size);
+            for (key, value) in entries {
+                node.keys.push(key);
+                node.values.push(value);
+            }
+            node.children = children;
+            return node;
+        }
+
+        // Every node but the last gives up one entry as separator to the level above.
+        let count = (n + 1).div_ceil(node_size + 1);
+        let per_node = (n - (count - 1)) / count;
+        let extra = (n - (count - 1)) % count;
+
+        let leaf = children.is_empty();
+        let mut entries = entries.drain(..);
+        let mut children = children.drain(..);
+        let mut level = Vec::with_capacity(count);
+        let mut separators = Vec::with_capacity(count - 1);
+
+        for j in 0..count {
+            let size = per_node + usize::from(j < extra);
+
+            let mut node = BTreeNode::<K, V>::new(node_size);
+            for (key, value) in entries.by_ref().take(size) {
+                node.keys.push(key);
+                node.values.push(value);
+            }
+            if !leaf {
+                node.children.extend(children.by_ref().take(size + 1));
+            }
+            level.push(Arc::new(node));
+
+            if j < count - 1 {
+                separators.push(entries.next().unwrap());
+            }
+        }
+
+        BTreeNode::<K, V>::build(node_size, separators, level)
+    }
+
+    fn into_sorted(self, out: &mut Vec<(K, V)>) {
+        let mut children = self.children.into_iter();
+        for (key, value) in self.keys.into_iter().zip(self.values) {
+            if let Some(child) = children.next() {
+                Arc::unwrap_or_clone(child).into_sorted(out);
+            }
+            out.push((key, value));
+        }
+        if let Some(child) = children.next() {
+            Arc::unwrap_or_clone(child).into_sorted(out);
+        }
+    }
+
+    fn display(&self, depth: usize) {
+        println!("{}Node with {:?} keys and {} children", " ".repeat(depth * 2), self.keys, self.children.len());
+
+        for child in self.children.iter() {
+            child.display(depth + 1);
+        }
+    }
+}
+
+// What add_recursive did with an entry: replaced the value of a key already
+// in the subtree, or inserted a new key, splitting off a node if that left
+// the subtree's root with too many keys.
+enum Added<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    Replaced(V),
+    Inserted(Option<BTreeNode<K, V>>),
+}
+
+pub struct BTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    root: BTreeNode<K, V>,
+}
+
+pub struct Iter<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    stack: Vec<(&'a BTreeNode<K, V>, usize)>,
+}
+
+impl<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Iter<'a, K, V> {
+    fn new(root: &'a BTreeNode<K, V>) -> Iter<'a, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.descend(root);
+        iter
+    }
+
+    // Positions the iterator on the first entry inside `start`.
+    fn seek(root: &'a BTreeNode<K, V>, start: Bound<&K>) -> Iter<'a, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        let mut node = root;
+
+        loop {
+            let i = match start {
+                Bound::Included(key) => node.keys.partition_point(|k| k < key),
+                Bound::Excluded(key) => node.keys.partition_point(|k| k <= key),
+                Bound::Unbounded => 0,
+            };
+            iter.stack.push((node, i));
+
+            match node.children.get(i) {
+                Some(child) => node = &**child,
+                None => break,
+            }
+        }
+
+        iter
+    }
+
+    fn descend(&mut self, mut node: &'a BTreeNode<K, V>) {
+        loop {
+            self.stack.push((node, 0));
+            match node.children.first() {
+                Some(child) => node = &**child,
+                None => break,
+            }
+        }
+    }
+}
+
+impl<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, index) = self.stack.last_mut()?;
+            let node: &'a BTreeNode<K, V> = node;
+
+            if *index < node.keys.len() {
+                let i = *index;
+                *index += 1;
+                if !node.children.is_empty() {
+                    self.descend(&node.children[i + 1]);
+                }
+                return Some((&node.keys[i], &node.values[i]));
+            }
+
+            self.stack.pop();
+        }
+    }
+}
+
+pub struct Range<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    iter: Iter<'a, K, V>,
+    end: Bound<K>,
+}
+
+impl<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.iter.next()?;
+
+        let past_end = match &self.end {
+            Bound::Included(end) => key > end,
+            Bound::Excluded(end) => key >= end,
+            Bound::Unbounded => false,
+        };
+        if past_end {
+            self.iter.stack.clear();
+            return None;
+        }
+
+        Some((key, value))
+    }
+}
+
+// Owns the nodes it walks, so it can outlive the borrow of the tree and move
+// to another thread. The tree copies any node a live iterator still shares
+// before changing it, so the iterator keeps seeing the entries as they were
+// when it was created.
+pub struct SnapshotIter<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    stack: Vec<(Arc<BTreeNode<K, V>>, usize)>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> SnapshotIter<K, V> {
+    // Positions the iterator on the first entry inside `start`.
+    fn seek(root: Arc<BTreeNode<K, V>>, start: Bound<&K>) -> SnapshotIter<K, V> {
+        let mut iter = SnapshotIter { stack: Vec::new() };
+        let mut node = root;
+
+        loop {
+            let i = match start {
+                Bound::Included(key) => node.keys.partition_point(|k| k < key),
+                Bound::Excluded(key) => node.keys.partition_point(|k| k <= key),
+                Bound::Unbounded => 0,
+            };
+            let child = node.children.get(i).cloned();
+            iter.stack.push((node, i));
+
+            match child {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+
+        iter
+    }
+
+    fn descend(&mut self, mut node: Arc<BTreeNode<K, V>>) {
+        loop {
+            let child = node.children.first().cloned();
+            self.stack.push((node, 0));
+            match child {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Iterator for SnapshotIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, index) = self.stack.last_mut()?;
+
+            if *index < node.keys.len() {
+                let i = *index;
+                *index += 1;
+                let entry = (node.keys[i].clone(), node.values[i].clone());
+                if let Some(child) = node.children.get(i + 1).cloned() {
+                    self.descend(child);
+                }
+                return Some(entry);
+            }
+
+            self.stack.pop();
+        }
+    }
+}
+
+// A range over a snapshot, owned like SnapshotIter.
+pub struct SnapshotRange<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    iter: SnapshotIter<K, V>,
+    end: Bound<K>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Iterator for SnapshotRange<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.iter.next()?;
+
+        let past_end = match &self.end {
+            Bound::Included(end) => key > *end,
+            Bound::Excluded(end) => key >= *end,
+            Bound::Unbounded => false,
+        };
+        if past_end {
+            self.iter.stack.clear();
+            return None;
+        }
+
+        Some((key, value))
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    pub fn new(node_size: usize) -> BTree<K, V> {
+        BTree {
+            root: BTreeNode::new(node_size),
+        }
+    }
+
+    pub fn from_sorted(node_size: usize, entries: Vec<(K, V)>) -> BTree<K, V> {
+        BTree {
+            root: BTreeNode::build(node_size, entries, Vec::new()),
+        }
+    }
+
+    pub fn find(&self, k: K) -> Option<V> {
+        self.root.find(&k)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        #[cfg(feature = "metrics")]
+        metrics::METRICS.lookups.inc();
+        self.root.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.root.get_mut(key)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (_, value) = self.root.remove_recursive(key)?;
+        #[cfg(feature = "metrics")]
+        metrics::METRICS.removes.inc();
+
+        if self.root.keys.is_empty() {
+            if let Some(child) = self.root.children.pop() {
+                self.root = Arc::unwrap_or_clone(child);
+            }
+        }
+
+        Some(value)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter::new(&self.root)
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        Range {
+            iter: Iter::seek(&self.root, range.start_bound()),
+            end: range.end_bound().cloned(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.keys.is_empty()
+    }
+
+    pub fn node_size(&self) -> usize {
+        self.root.node_size
+    }
+
+    // Levels of nodes, counting the root. All leaves are at the same depth.
+    pub fn height(&self) -> usize {
+        let mut height = 1;
+        let mut node = &self.root;
+        while let Some(child) = node.children.first() {
+            height += 1;
+            node = child;
+        }
+        height
+    }
+
+    // Iterates over the entries as of now. Only the root is copied up front.
+    pub fn snapshot_iter(&self) -> SnapshotIter<K, V> {
+        let mut iter = SnapshotIter { stack: Vec::new() };
+        iter.descend(Arc::new(self.root.clone()));
+        iter
+    }
+
+    pub fn snapshot_range<R: RangeBounds<K>>(&self, range: R) -> SnapshotRange<K, V> {
+        SnapshotRange {
+            iter: SnapshotIter::seek(Arc::new(self.root.clone()), range.start_bound()),
+            end: range.end_bound().cloned(),
+        }
+    }
+
+    // Prints the node structure, one node per line indented by depth.
+    pub fn display(&self) {
+        self.root.display(0);
+    }
+
+    pub fn into_sorted(self) -> Vec<(K, V)> {
+        let mut entries = Vec::new();
+        self.root.into_sorted(&mut entries);
+        entries
+    }
+
+    // Inserts the entry, returning the previous value if the key was present.
+    pub fn add(&mut self, key: K, value: V) -> Option<V> {
+        #[cfg(feature = "metrics")]
+        let _timer = metrics::METRICS.insert_seconds.start();
+        #[cfg(feature = "metrics")]
+        metrics::METRICS.inserts.inc();
+
+        let overflow = match self.root.add_recursive(key, value) {
+            Added::Replaced(old) => return Some(old),
+            Added::Inserted(overflow) => overflow,
+        };
+        if let Some(mut overflow) = overflow {
+            let newroot = BTreeNode::<K, V>::new(self.root.node_size);
+
+            let overflow_key = overflow.keys.remove(0);
+            let overflow_value = overflow.values.remove(0);
+
+            let oldroot = std::mem::replace(&mut self.root, newroot);
+
+            self.root.children.push(Arc::new(oldroot));
+
+            self.root.keys.push(overflow_key);
+            assert!(self.root.keys.len() == 1);
+
+            self.root.values.push(overflow_value);
+            assert!(self.root.values.len() == 1);
+
+            self.root.children.push(Arc::new(overflow));
+            assert!(self.root.children.len() == 2);
+        };
+
+        None
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug + Codec, V: Ord + Clone + std::fmt::Debug + Codec> BTree<K, V> {
+    // Layout: node size and entry count as u64, then every entry in key order as
+    // a u32 length-prefixed key followed by a u32 length-prefixed value.
+    // Entries are written as they are visited, so the output can be a socket
+    // or a pipe as well as a file. Writes are not buffered here.
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        out.write_all(&(self.root.node_size as u64).to_le_bytes())?;
+        out.write_all(&(self.len() as u64).to_le_bytes())?;
+
+        let mut buf = Vec::new();
+        for (key, value) in self.iter() {
+            write_item(&mut out, key, &mut buf)?;
+            write_item(&mut out, value, &mut buf)?;
+        }
+
+        out.flush()
+    }
+
+    // Reads exactly what write_to wrote, leaving anything after it unread.
+    // Reads are not buffered here.
+    pub fn read_from<R: Read>(mut input: R) -> io::Result<BTree<K, V>> {
+        let mut word = [0; 8];
+        input.read_exact(&mut word)?;
+        let node_size = check_node_size(u64::from_le_bytes(word))?;
+        input.read_exact(&mut word)?;
+        let len = u64::from_le_bytes(word);
+
+        let mut buf = Vec::new();
+        // The count comes from the input, so it only caps the reservation.
+        let mut entries = Vec::with_capacity(len.min(1 << 16) as usize);
+        for _ in 0..len {
+            let key: K = read_item(&mut input, &mut buf)?;
+            let value: V = read_item(&mut input, &mut buf)?;
+
+            if let Some((last, _)) = entries.last() {
+                if *last >= key {
+                    return Err(invalid_data("keys are not in ascending order"));
+                }
+            }
+            entries.push((key, value));
+        }
+
+        Ok(BTree::from_sorted(node_size, entries))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<BTree<K, V>> {
+        BTree::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::ops::Bound;
+
+    use crate::testutil::{Rng, TempDir};
+    use crate::{BTree, BTreeNode};
+
+    #[test]
+    fn test_btree() {
+        let mut tree = BTree::<u64, String>::new(12);
+
+        let data = vec![
+            (1, "a"),
+            (2, "b"),
+            (3, "c"),
+            (4, "d"),
+            (5, "e"),
+            (6, "f"),
+            (7, "g"),
+            (8, "h"),
+            (9, "i"),
+            (10, "j"),
+            (11, "k"),
+            (20, "t"),
+            (21, "u"),
+            (22, "v"),
+            (23, "w"),
+            (24, "x"),
+            (25, "y"),
+            (26, "zz"),
+            (27, "zzz"),
+            (28, "zzzz"),
+            (29, "zzzzz"),
+            (30, "zsa"),
+            (31, "zasd"),
+            (32, "zsff"),
+        ];
+
+        for (key, value) in data.iter() {
+            tree.add(*key, value.to_string());
+
+            assert!(tree.find(*key).is_some());
+        }
+
+        tree.root.display(0);
+
+        for (key, _) in data.iter() {
+            assert!(tree.find(*key).is_some());
+        }
+    }
+
+    #[test]
+    fn test_add_replaces() {
+        let mut tree = BTree::<u64, u64>::new(3);
+        for key in 0..100 {
+            assert_eq!(tree.add(key, key), None);
+        }
+        let snapshot = tree.snapshot_iter();
+
+        // Every key, whether it sits in a leaf or separates inner children.
+        for key in 0..100 {
+            assert_eq!(tree.add(key, key + 1000), Some(key));
+        }
+        assert_eq!(tree.len(), 100);
+        check_node(&tree.root, true);
+        assert!(tree.iter().all(|(key, value)| *value == key + 1000));
+        assert!(snapshot.enumerate().all(|(i, (key, value))| key == i as u64 && value == key));
+    }
+
+    // Checks key counts and that all leaves are at the same depth, returning it.
+    fn check_node<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug>(
+        node: &BTreeNode<K, V>,
+        root: bool,
+    ) -> usize {
+        assert!(node.keys.len() <= node.node_size);
+        assert!(root || node.keys.len() >= node.min_keys());
+        assert_eq!(node.keys.len(), node.values.len());
+
+        if node.children.is_empty() {
+            return 0;
+        }
+
+        assert_eq!(node.children.len(), node.keys.len() + 1);
+        let depth = check_node(&node.children[0], false);
+        for child in node.children.iter() {
+            assert_eq!(check_node(child, false), depth);
+        }
+
+        depth + 1
+    }
+
+    #[test]
+    fn test_remove() {
+        for node_size in [2, 3, 4, 7] {
+            let mut tree = BTree::<u64, u64>::new(node_size);
+            let mut expected = std::collections::BTreeMap::new();
+
+            let mut rng = Rng::new();
+            for _ in 0..5000 {
+                let state = rng.next_u64();
+
+                let key = state % 500;
+                if state.is_multiple_of(3) {
+                    assert_eq!(tree.remove(&key), expected.remove(&key));
+                } else {
+                    assert_eq!(tree.add(key, state), expected.insert(key, state));
+                }
+            }
+
+            assert!(tree.iter().map(|(k, v)| (*k, *v)).eq(expected.into_iter()));
+            assert_eq!(tree.remove(&1000), None);
+            check_node(&tree.root, true);
+        }
+
+        let mut tree = BTree::<u64, String>::new(3);
+        for key in 0..100 {
+            tree.add(key, key.to_string());
+        }
+        for key in 0..100 {
+            assert_eq!(tree.remove(&key), Some(key.to_string()));
+            assert_eq!(tree.find(key), None);
+        }
+        assert_eq!(tree.len(), 0);
+        assert!(tree.root.children.is_empty());
+    }
+
+    #[test]
+    fn test_range() {
+        let mut tree = BTree::<u64, u64>::new(3);
+        for key in 0..200 {
+            tree.add(key * 3, key);
+        }
+
+        let keys = |range: Vec<(&u64, &u64)>| range.into_iter().map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(keys(tree.range(30..40).collect()), vec![30, 33, 36, 39]);
+        assert_eq!(keys(tree.range(31..=39).collect()), vec![33, 36, 39]);
+        assert_eq!(keys(tree.range((Bound::Excluded(30), Bound::Excluded(39))).collect()), vec![33, 36]);
+        assert_eq!(keys(tree.range(590..).collect()), vec![591, 594, 597]);
+        assert_eq!(tree.range(..).count(), 200);
+        assert_eq!(tree.range(1000..).count(), 0);
+        assert!(tree.range(..=9).map(|(k, _)| *k).eq([0, 3, 6, 9]));
+    }
+
+    #[test]
+    fn test_snapshot_iter() {
+        let mut tree = BTree::<u64, String>::new(4);
+        for key in 0..1000 {
+            tree.add(key, key.to_string());
+        }
+
+        let snapshot = tree.snapshot_iter();
+        for key in 0..500 {
+            tree.remove(&key);
+        }
+        for key in 1000..1500 {
+            tree.add(key, key.to_string());
+        }
+        tree.add(700, "changed".to_string());
+
+        let entries = std::thread::spawn(move || snapshot.collect::<Vec<_>>()).join().unwrap();
+        assert_eq!(entries.len(), 1000);
+        assert!(entries.iter().enumerate().all(|(i, (key, value))| *key == i as u64 && *value == key.to_string()));
+
+        assert_eq!(tree.len(), 1000);
+        assert_eq!(tree.get(&700), Some(&"changed".to_string()));
+        assert_eq!(tree.snapshot_iter().next(), Some((500, "500".to_string())));
+
+        // A range cursor can be parked and resumed on another thread while the
+        // tree keeps changing.
+        let mut cursor = tree.snapshot_range(990..1010);
+        assert_eq!(cursor.next(), Some((990, "990".to_string())));
+        tree.remove(&995);
+        let rest = std::thread::spawn(move || cursor.map(|(key, _)| key).collect::<Vec<_>>()).join().unwrap();
+        assert_eq!(rest, (991..1010).collect::<Vec<_>>());
+        assert_eq!(tree.snapshot_range(..=502).count(), 3);
+        assert_eq!(tree.snapshot_range((Bound::Excluded(1498), Bound::Unbounded)).count(), 1);
+        check_node(&tree.root, true);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let dir = TempDir::new("save");
+        let path = dir.join("tree");
+
+        for count in [0, 1, 3, 4, 17, 100, 1000] {
+            let mut tree = BTree::<u64, String>::new(4);
+            for key in 0..count {
+                tree.add(key * 2, format!("value {}", key));
+            }
+
+            tree.save(&path).unwrap();
+            let loaded = BTree::<u64, String>::load(&path).unwrap();
+
+            assert_eq!(loaded.len(), count as usize);
+            check_node(&loaded.root, true);
+            assert!(loaded.iter().eq(tree.iter()));
+            for key in 0..count {
+                assert_eq!(loaded.find(key * 2), Some(format!("value {}", key)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_to_read_from() {
+        let mut tree = BTree::<String, u64>::new(3);
+        for key in 0..500 {
+            tree.add(format!("key {}", key), key);
+        }
+        let small = BTree::from_sorted(5, vec![(1u64, 2u64)]);
+
+        // Several trees back to back in one stream, then other data.
+        let mut stream = Vec::new();
+        tree.write_to(&mut stream).unwrap();
+        small.write_to(&mut stream).unwrap();
+        stream.extend_from_slice(b"trailer");
+
+        let mut input = stream.as_slice();
+        let read = BTree::<String, u64>::read_from(&mut input).unwrap();
+        assert!(read.iter().eq(tree.iter()));
+        check_node(&read.root, true);
+        let read = BTree::<u64, u64>::read_from(&mut input).unwrap();
+        assert!(read.iter().eq(small.iter()));
+        assert_eq!(input, b"trailer");
+
+        let mut truncated = &stream[..100];
+        assert!(BTree::<String, u64>::read_from(&mut truncated).is_err());
+
+        // The node size is checked before anything is allocated for it.
+        for node_size in [0u64, 1, (1 << 16) + 1, 1 << 40] {
+            let mut bad = node_size.to_le_bytes().to_vec();
+            bad.extend_from_slice(&1u64.to_le_bytes());
+            bad.extend_from_slice(&[8, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+            bad.extend_from_slice(&[8, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+            let err = BTree::<u64, u64>::read_from(bad.as_slice()).err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+        // So is an item length, which here claims 4 GiB that isn't there.
+        let mut bad = 4u64.to_le_bytes().to_vec();
+        bad.extend_from_slice(&1u64.to_le_bytes());
+        bad.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 1, 2]);
+        let err = BTree::<u64, u64>::read_from(bad.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let mut smallest = Vec::new();
+        BTree::from_sorted(2, (0..100u64).map(|key| (key, key)).collect()).write_to(&mut smallest).unwrap();
+        let read = BTree::<u64, u64>::read_from(smallest.as_slice()).unwrap();
+        assert_eq!(read.len(), 100);
+        check_node(&read.root, true);
+    }
+}
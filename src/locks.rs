@@ -0,0 +1,134 @@
+// Exclusive key locks for transactions that share a tree. A transaction asking
+// for a held key waits for its owner. If the owner is itself waiting, directly
+// or through others, for the asking transaction, that wait would never end.
+// The asker is then refused with Deadlock instead, and should release its
+// locks and retry.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Condvar, Mutex};
+
+pub type TxnId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deadlock {
+    pub victim: TxnId,
+}
+
+struct LockState<K> {
+    owners: BTreeMap<K, TxnId>,
+    // The transaction each waiting transaction waits for.
+    waits_for: HashMap<TxnId, TxnId>,
+}
+
+impl<K> LockState<K> {
+    // Whether `from` is, or transitively waits for, `to`.
+    fn reaches(&self, from: TxnId, to: TxnId) -> bool {
+        let mut txn = from;
+        for _ in 0..=self.waits_for.len() {
+            if txn == to {
+                return true;
+            }
+            match self.waits_for.get(&txn) {
+                Some(&next) => txn = next,
+                None => return false,
+            }
+        }
+
+        false
+    }
+}
+
+pub struct LockManager<K: Ord> {
+    state: Mutex<LockState<K>>,
+    released: Condvar,
+}
+
+impl<K: Ord> LockManager<K> {
+    pub fn new() -> LockManager<K> {
+        LockManager {
+            state: Mutex::new(LockState {
+                owners: BTreeMap::new(),
+                waits_for: HashMap::new(),
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    // Blocks until `txn` holds `key`, or fails if waiting would deadlock.
+    pub fn lock(&self, txn: TxnId, key: K) -> Result<(), Deadlock> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            let owner = match state.owners.get(&key) {
+                Some(&owner) if owner == txn => return Ok(()),
+                Some(&owner) => owner,
+                None => break,
+            };
+
+            if state.reaches(owner, txn) {
+                state.waits_for.remove(&txn);
+                return Err(Deadlock { victim: txn });
+            }
+            state.waits_for.insert(txn, owner);
+            state = self.released.wait(state).unwrap();
+        }
+
+        state.waits_for.remove(&txn);
+        state.owners.insert(key, txn);
+        Ok(())
+    }
+
+    pub fn release_all(&self, txn: TxnId) {
+        let mut state = self.state.lock().unwrap();
+        state.owners.retain(|_, owner| *owner != txn);
+        state.waits_for.remove(&txn);
+        drop(state);
+
+        self.released.notify_all();
+    }
+}
+
+impl<K: Ord> Default for LockManager<K> {
+    fn default() -> LockManager<K> {
+        LockManager::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+
+    use crate::locks::{Deadlock, LockManager};
+
+    #[test]
+    fn test_deadlock_detection() {
+        let locks = LockManager::<&str>::new();
+        locks.lock(1, "a").unwrap();
+        locks.lock(1, "a").unwrap();
+        locks.release_all(1);
+
+        // Two transactions take their first key, then each other's. Exactly
+        // one of them must be refused, and the other then gets both keys.
+        let barrier = Barrier::new(2);
+        let results = std::thread::scope(|scope| {
+            let run = |txn, first, second| {
+                let (locks, barrier) = (&locks, &barrier);
+                scope.spawn(move || {
+                    locks.lock(txn, first).unwrap();
+                    barrier.wait();
+                    let result = locks.lock(txn, second);
+                    locks.release_all(txn);
+                    result
+                })
+            };
+            let one = run(1, "a", "b");
+            let two = run(2, "b", "a");
+            [one.join().unwrap(), two.join().unwrap()]
+        });
+
+        match results {
+            [Ok(()), Err(Deadlock { victim: 2 })] | [Err(Deadlock { victim: 1 }), Ok(())] => {}
+            other => panic!("unexpected results {:?}", other),
+        }
+    }
+}
@@ -1,266 +1,322 @@
-struct BTreeNode<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
-    node_size: usize,
-    keys: Vec<K>,
-    values: Vec<V>,
-    children: Vec<BTreeNode<K, V>>,
+// Command line access to a tree saved in a file, for shell scripts and for
+// looking inside database files. Keys and values are strings. Every command
+// loads the whole file, and put and del write it back; shell keeps it loaded
+// between commands, and serve answers HTTP requests for it.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+use std::ops::Bound;
+use std::path::Path;
+use std::process::ExitCode;
+
+use c_tree::http::Server;
+use c_tree::BTree;
+
+// The library's test fixtures aren't compiled into it for the binary's
+// tests, so they build their own copy. Not every fixture is used here.
+#[cfg(test)]
+#[path = "testutil.rs"]
+#[allow(dead_code)]
+mod testutil;
+
+// Used when put creates a new file.
+const NODE_SIZE: usize = 32;
+
+const USAGE: &str = "usage:
+    ctree put <file> <key> <value>
+    ctree get <file> <key>
+    ctree del <file> <key>
+    ctree scan <file> [<start> [<end>]]
+    ctree stats <file>
+    ctree shell <file>
+    ctree serve --http <addr> <file>";
+
+const SHELL_HELP: &str = "commands:
+    put <key> <value>
+    get <key>
+    delete <key>
+    range [<start> [<end>]]
+    stats
+    help
+    quit
+a command name cut short and followed by tab lists the commands it could be";
+
+// Command names the shell completes.
+const COMMANDS: [&str; 7] = ["put", "get", "delete", "range", "stats", "help", "quit"];
+
+const PROMPT: &str = "ctree> ";
+
+enum Error {
+    Usage,
+    // The key looked up or deleted isn't there.
+    Missing,
+    Io(io::Error),
 }
 
-impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTreeNode<K, V> {
-    fn new(node_size: usize) -> BTreeNode<K, V> {
-        BTreeNode {
-            node_size,
-            keys: Vec::with_capacity(node_size + 1),
-            values: Vec::with_capacity(node_size + 1),
-            children: Vec::with_capacity(node_size + 1),
-        }
-    }
-
-    fn generate_find_path(&self, key: &K) -> Vec<usize> {
-        let mut stack = Vec::<usize>::new();
-        let mut current_node = self;
-
-        loop {
-            let i = BTreeNode::<K, V>::find_it(&current_node.keys, key);
-            if i < 0 {
-                stack.push(-(i + 1) as usize);
-                break;
-            } else if (i as usize) < current_node.children.len() {
-                current_node = &current_node.children[i as usize];
-                stack.push(i as usize);
-            } else {
-                stack.clear();
-                break;
-            }
-        }
-
-        stack.reverse();
-
-        stack
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
     }
+}
 
-    fn find_it(keys: &Vec<K>, key: &K) -> i32 {
-        let mut low = 0;
-        let mut high = keys.len() as i32;
+fn load(path: &Path) -> io::Result<BTree<String, String>> {
+    BTree::load(path)
+}
 
-        while high != low {
-            let mid = (high + low) / 2;
+// Writes to a temporary file and renames it over the old one, so the file
+// holds either the old tree or the new one if this is interrupted.
+fn save(tree: &BTree<String, String>, path: &Path) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    tree.save(&temp)?;
+    fs::rename(&temp, path)
+}
 
-            if key < &keys[mid as usize] {
-                high = mid;
-            } else if key > &keys[mid as usize] {
-                low = mid + 1;
-            } else {
-                // Return early, exact key found
-                return -mid - 1;
-            }
+// Runs one command against a loaded tree, returning whether it changed the
+// tree. The shell's names for commands work here too.
+fn execute(
+    tree: &mut BTree<String, String>,
+    path: &Path,
+    command: &str,
+    args: &[String],
+    out: &mut impl Write,
+) -> Result<bool, Error> {
+    match (command, args) {
+        ("put", [key, value]) => {
+            tree.add(key.clone(), value.clone());
+            return Ok(true);
         }
-
-        return low;
-    }
-
-    fn find(&self, key: &K) -> Option<V> {
-        let mut current_node = self;
-        let mut path = self.generate_find_path(key);
-        let mut key_index = 0;
-
-        while let Some(index) = path.pop() {
-            if path.len() == 0 {
-                // Last part of path is leaf node. Value is the index of k.
-                key_index = index;
-                break;
+        ("get", [key]) => {
+            writeln!(out, "{}", tree.get(key).ok_or(Error::Missing)?)?;
+        }
+        ("del" | "delete", [key]) => {
+            tree.remove(key).ok_or(Error::Missing)?;
+            return Ok(true);
+        }
+        ("scan" | "range", bounds) if bounds.len() <= 2 => {
+            let start = bounds.first().map_or(Bound::Unbounded, Bound::Included);
+            let end = bounds.get(1).map_or(Bound::Unbounded, Bound::Excluded);
+            for (key, value) in tree.range::<(Bound<&String>, Bound<&String>)>((start, end)) {
+                writeln!(out, "{}\t{}", key, value)?;
             }
-
-            current_node = &current_node.children[index];
         }
-
-        if current_node.keys[key_index] == *key {
-            return Some(current_node.values[key_index].clone());
+        ("stats", []) => {
+            writeln!(out, "entries: {}", tree.len())?;
+            writeln!(out, "node size: {}", tree.node_size())?;
+            writeln!(out, "height: {}", tree.height())?;
+            let bytes = match fs::metadata(path) {
+                Ok(metadata) => metadata.len(),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+                Err(err) => return Err(err.into()),
+            };
+            writeln!(out, "file bytes: {}", bytes)?;
         }
-
-
-        return None;
+        _ => return Err(Error::Usage),
     }
+    Ok(false)
+}
 
-    fn split(&mut self) -> BTreeNode<K, V> {
-        let mid = self.keys.len() / 2;
+// Loads the tree at `path`, or an empty one if there is no file yet.
+fn load_or_new(path: &Path) -> io::Result<BTree<String, String>> {
+    match load(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(BTree::new(NODE_SIZE)),
+        loaded => loaded,
+    }
+}
 
-        let mut new_node = BTreeNode::<K, V>::new(self.node_size);
-        new_node.keys = self.keys.drain(mid..).collect();
-        new_node.values = self.values.drain(mid..).collect();
-        if self.children.len() > 0 {
-            new_node.children = self.children.drain(mid + 1..).collect();
-        }
+// The shell commands starting with `prefix`.
+fn complete(prefix: &str) -> Vec<&'static str> {
+    COMMANDS
+        .into_iter()
+        .filter(|command| command.starts_with(prefix))
+        .collect()
+}
 
-        new_node
+// Splits a shell line into a command and its arguments. A put's value is
+// the rest of the line after the key and the one character separating
+// them, exactly as typed.
+fn words(line: &str) -> Vec<String> {
+    let mut words: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+    if words.len() > 2 && words[0] == "put" {
+        let rest = line.trim_start()["put".len()..].trim_start();
+        let mut value = rest[words[1].len()..].chars();
+        value.next();
+        words.truncate(2);
+        words.push(value.as_str().to_string());
     }
+    words
+}
 
-    fn add_recursive(&mut self, key: K, value: V) -> Option<BTreeNode<K, V>> {
-        let i = BTreeNode::<K, V>::find_it(&self.keys, &key);
-        if self.children.len() == 0 {
-            // Add directly to leaf node
-            let index = if i < 0 {
-                -(i + 1) as usize
-            } else {
-                i as usize
-            };
-            self.keys.insert(index, key);
-            self.values.insert(index, value);
-        } else {
-            let index = i as usize;
-            let children = &mut self.children;
-
-            assert!(index <= children.len() + 1);
-
-            let split_node = children[index].add_recursive(key.clone(), value);
-            if let Some(mut new_node) = split_node {
-                let new_key = new_node.keys.remove(0);
-                let new_value = new_node.values.remove(0);
-
-                children.insert(index + 1, new_node);
-                self.keys.insert(index, new_key);
-                self.values.insert(index, new_value);
+// Reads commands from `input` until it ends or says quit. The file is
+// loaded once and written back after every change.
+//
+// The terminal sends a line only once it is finished, so a tab can't be
+// answered as it is typed; instead a line holding part of a command name
+// and then a tab lists the commands it could be.
+fn shell(path: &Path, input: impl BufRead, out: &mut impl Write) -> Result<(), Error> {
+    let mut tree = load_or_new(path)?;
+
+    write!(out, "{}", PROMPT)?;
+    out.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        let words = match line.strip_suffix('\t').map(str::trim_start) {
+            Some(prefix) if !prefix.contains(char::is_whitespace) => {
+                writeln!(out, "{}", complete(prefix).join(" "))?;
+                Vec::new()
             }
-        }
+            _ => words(&line),
+        };
 
-        if self.keys.len() == self.node_size + 1 {
-            return Some(self.split());
+        match words.split_first() {
+            None => {}
+            Some((command, _)) if command == "quit" || command == "exit" => return Ok(()),
+            Some((command, _)) if command == "help" => writeln!(out, "{}", SHELL_HELP)?,
+            Some((command, args)) => match execute(&mut tree, path, command, args, out) {
+                Ok(true) => save(&tree, path)?,
+                Ok(false) => {}
+                Err(Error::Missing) => writeln!(out, "not found")?,
+                Err(Error::Usage) => writeln!(out, "{}", SHELL_HELP)?,
+                Err(err @ Error::Io(_)) => return Err(err),
+            },
         }
 
-        None
-    }
-
-    fn display(&self, depth: usize) {
-        println!("{}Node with {:?} keys and {} children", " ".repeat(depth * 2), self.keys, self.children.len());
-
-        for child in self.children.iter() {
-            child.display(depth + 1);
-        }
+        write!(out, "{}", PROMPT)?;
+        out.flush()?;
     }
+    writeln!(out)?;
+    Ok(())
 }
 
-struct BTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
-    root: BTreeNode<K, V>,
-}
-
-impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
-    fn new(node_size: usize) -> BTree<K, V> {
-        BTree {
-            root: BTreeNode::new(node_size),
+fn run(args: &[String], out: &mut impl Write) -> Result<(), Error> {
+    if let [command, flag, addr, path] = args {
+        if command == "serve" && flag == "--http" {
+            let mut server = Server::open(path)?;
+            let listener = TcpListener::bind(addr)?;
+            eprintln!(
+                "ctree: serving {} on http://{}",
+                path,
+                listener.local_addr()?
+            );
+            return Ok(server.serve(listener)?);
         }
     }
 
-    fn find(&self, k: K) -> Option<V> {
-        self.root.find(&k)
-    }
-
-    fn add(&mut self, key: K, value: V) {
-        let overflow = self.root.add_recursive(key, value);
-        if let Some(mut overflow) = overflow {
-            let newroot = BTreeNode::<K, V>::new(self.root.node_size);
-
-            let overflow_key = overflow.keys.remove(0);
-            let overflow_value = overflow.values.remove(0);
-
-            let oldroot = std::mem::replace(&mut self.root, newroot);
-
-            self.root.children.push(oldroot);
-
-            self.root.keys.push(overflow_key);
-            assert!(self.root.keys.len() == 1);
+    let (command, path, rest) = match args {
+        [command, path, rest @ ..] => (command.as_str(), Path::new(path), rest),
+        _ => return Err(Error::Usage),
+    };
 
-            self.root.values.push(overflow_value);
-            assert!(self.root.values.len() == 1);
-
-            self.root.children.push(overflow);
-            assert!(self.root.children.len() == 2);
-        };
+    match (command, rest) {
+        ("shell", []) => shell(path, io::stdin().lock(), out),
+        ("put" | "get" | "del" | "scan" | "stats", _) => {
+            let mut tree = if command == "put" {
+                load_or_new(path)?
+            } else {
+                load(path)?
+            };
+            if execute(&mut tree, path, command, rest, out)? {
+                save(&tree, path)?;
+            }
+            Ok(())
+        }
+        _ => Err(Error::Usage),
     }
 }
 
-
-fn main() {
-    let mut tree = BTree::<u64, String>::new(3);
-
-    let data = vec![
-        (1, "a"),
-        (2, "b"),
-        (3, "c"),
-        (4, "d"),
-        (5, "e"),
-        (6, "f"),
-        (7, "g"),
-        (8, "h"),
-        (9, "i"),
-        (10, "j"),
-        (11, "k"),
-        (12, "l"),
-        (13, "m"),
-        (14, "n"),
-        (15, "o"),
-        (16, "p"),
-        (17, "q"),
-        (18, "r"),
-        (19, "s"),
-        (20, "t"),
-        (21, "u"),
-        (22, "v"),
-        (23, "w"),
-        (24, "x"),
-        (25, "y"),
-        (26, "z"),
-    ];
-
-    for (key, value) in data.iter() {
-        tree.add(key.clone(), value.to_string());
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args, &mut io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Missing) => ExitCode::from(1),
+        Err(Error::Usage) => {
+            eprintln!("{}", USAGE);
+            ExitCode::from(2)
+        }
+        Err(Error::Io(err)) => {
+            eprintln!("ctree: {}", err);
+            ExitCode::from(2)
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::BTree;
+    use crate::testutil::TempDir;
+    use crate::{run, shell, Error};
+
+    fn ctree(args: &[&str]) -> Result<String, Error> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut out = Vec::new();
+        run(&args, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
 
     #[test]
-    fn test_btree() {
-        let mut tree = BTree::<u64, String>::new(12);
-
-        let data = vec![
-            (1, "a"),
-            (2, "b"),
-            (3, "c"),
-            (4, "d"),
-            (5, "e"),
-            (6, "f"),
-            (7, "g"),
-            (8, "h"),
-            (9, "i"),
-            (10, "j"),
-            (11, "k"),
-            (20, "t"),
-            (21, "u"),
-            (22, "v"),
-            (23, "w"),
-            (24, "x"),
-            (25, "y"),
-            (26, "zz"),
-            (27, "zzz"),
-            (28, "zzzz"),
-            (29, "zzzzz"),
-            (30, "zsa"),
-            (31, "zasd"),
-            (32, "zsff"),
-        ];
-
-        for (i, (key, value)) in data.iter().enumerate() {
-            tree.add(key.clone(), value.to_string());
-
-            assert!(tree.find(*key).is_some());
+    fn test_cli() {
+        let dir = TempDir::new("cli");
+        let path = dir.join("tree");
+        let file = path.to_str().unwrap();
+
+        assert!(matches!(ctree(&["get", file, "a"]), Err(Error::Io(_))));
+        for (key, value) in [("b", "2"), ("a", "1"), ("c", "3"), ("b", "two")] {
+            assert_eq!(ctree(&["put", file, key, value]).ok().unwrap(), "");
         }
+        assert_eq!(ctree(&["get", file, "b"]).ok().unwrap(), "two\n");
+        assert!(matches!(ctree(&["get", file, "d"]), Err(Error::Missing)));
+
+        assert_eq!(ctree(&["scan", file]).ok().unwrap(), "a\t1\nb\ttwo\nc\t3\n");
+        assert_eq!(ctree(&["scan", file, "b"]).ok().unwrap(), "b\ttwo\nc\t3\n");
+        assert_eq!(
+            ctree(&["scan", file, "a", "c"]).ok().unwrap(),
+            "a\t1\nb\ttwo\n"
+        );
+
+        assert_eq!(ctree(&["del", file, "a"]).ok().unwrap(), "");
+        assert!(matches!(ctree(&["del", file, "a"]), Err(Error::Missing)));
+        let stats = ctree(&["stats", file]).ok().unwrap();
+        assert!(
+            stats.starts_with("entries: 2\nnode size: 32\nheight: 1\n"),
+            "{}",
+            stats
+        );
+
+        assert!(matches!(ctree(&["put", file, "a"]), Err(Error::Usage)));
+        assert!(matches!(ctree(&["frobnicate", file]), Err(Error::Usage)));
+        assert!(matches!(ctree(&[]), Err(Error::Usage)));
+    }
 
-        tree.root.display(0);
-
-
-        for (key, value) in data.iter() {
-            assert!(tree.find(key.clone()).is_some());
-        }
+    #[test]
+    fn test_shell() {
+        let dir = TempDir::new("shell");
+        let path = dir.join("tree");
+        let input = "put b 2\nput a one  and   two \n\nget a\nget c\ndelete b\nrange\n\
+            frobnicate\nstats\nde\t\n\t\nzz\t\nquit\nput c 3\n";
+        let mut out = Vec::new();
+        assert!(shell(&path, input.as_bytes(), &mut out).is_ok());
+
+        let out = String::from_utf8(out).unwrap();
+        let replies: Vec<&str> = out
+            .split("ctree> ")
+            .filter(|reply| !reply.is_empty())
+            .collect();
+        assert_eq!(replies.len(), 8);
+        assert_eq!(
+            replies[..3],
+            ["one  and   two \n", "not found\n", "a\tone  and   two \n"]
+        );
+        assert!(replies[3].starts_with("commands:\n"), "{}", replies[3]);
+        assert!(replies[4].starts_with("entries: 1\n"), "{}", replies[4]);
+        assert_eq!(
+            replies[5..],
+            ["delete\n", "put get delete range stats help quit\n", "\n"]
+        );
+
+        // Changes were saved as they were made, and nothing after quit ran.
+        let file = path.to_str().unwrap().to_string();
+        let mut scan = Vec::new();
+        assert!(run(&["scan".to_string(), file], &mut scan).is_ok());
+        assert_eq!(String::from_utf8(scan).unwrap(), "a\tone  and   two \n");
     }
 }
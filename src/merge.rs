@@ -0,0 +1,155 @@
+// Values updated through a merge function instead of read-modify-write. A
+// merge only records its operand; operands for the same key are combined
+// with each other as they arrive, and the combined operand is merged into
+// the stored value once the buffer fills, or straight away with a capacity
+// of zero. Reads merge whatever is still pending on the fly.
+//
+// The function must be associative, e.g. adding counters or taking the union
+// of sets, since operands are combined before they meet the stored value.
+
+use std::collections::BTreeMap;
+
+use crate::BTree;
+
+// Merges an operand into an older value or operand.
+pub type MergeFn<V> = Box<dyn Fn(&V, &V) -> V>;
+
+pub struct MergeBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    tree: BTree<K, V>,
+    // Combined operands not yet merged into the tree.
+    operands: BTreeMap<K, V>,
+    operator: MergeFn<V>,
+    capacity: usize,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> MergeBTree<K, V> {
+    // Keeps up to `capacity` keys' operands pending before merging them into
+    // the tree.
+    pub fn new(
+        node_size: usize,
+        capacity: usize,
+        operator: impl Fn(&V, &V) -> V + 'static,
+    ) -> MergeBTree<K, V> {
+        MergeBTree {
+            tree: BTree::new(node_size),
+            operands: BTreeMap::new(),
+            operator: Box::new(operator),
+            capacity,
+        }
+    }
+
+    // Merges `operand` into the key's value, or makes it the value of a new
+    // key.
+    pub fn merge(&mut self, key: K, operand: V) {
+        let operand = match self.operands.remove(&key) {
+            Some(older) => (self.operator)(&older, &operand),
+            None => operand,
+        };
+        self.operands.insert(key, operand);
+
+        if self.operands.len() > self.capacity {
+            self.flush();
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old = self.get(&key);
+        self.operands.remove(&key);
+        self.tree.add(key, value);
+        old
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let old = self.get(key);
+        self.operands.remove(key);
+        self.tree.remove(key);
+        old
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        match (self.tree.get(key), self.operands.get(key)) {
+            (Some(value), Some(operand)) => Some((self.operator)(value, operand)),
+            (value, operand) => value.or(operand).cloned(),
+        }
+    }
+
+    // Merges every pending operand into the tree, in key order.
+    pub fn flush(&mut self) {
+        for (key, operand) in std::mem::take(&mut self.operands) {
+            match self.tree.get_mut(&key) {
+                Some(value) => *value = (self.operator)(value, &operand),
+                None => {
+                    self.tree.add(key, operand);
+                }
+            }
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.operands.len()
+    }
+
+    pub fn len(&self) -> usize {
+        let added = self
+            .operands
+            .keys()
+            .filter(|key| self.tree.get(key).is_none())
+            .count();
+        self.tree.len() + added
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::merge::MergeBTree;
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_merge_btree() {
+        for capacity in [0, 1, 16] {
+            let mut counters = MergeBTree::<u64, u64>::new(4, capacity, |a, b| a + b);
+            let mut expected = BTreeMap::new();
+            let mut rng = Rng::new();
+
+            for step in 0..3000 {
+                let state = rng.next_u64();
+                let key = state % 100;
+
+                match state % 10 {
+                    0 => assert_eq!(counters.remove(&key), expected.remove(&key)),
+                    1 => assert_eq!(counters.insert(key, step), expected.insert(key, step)),
+                    _ => {
+                        counters.merge(key, 1);
+                        *expected.entry(key).or_insert(0) += 1;
+                    }
+                }
+                assert_eq!(counters.get(&key), expected.get(&key).copied());
+                assert!(counters.pending() <= capacity);
+            }
+
+            assert_eq!(counters.len(), expected.len());
+            counters.flush();
+            assert_eq!(counters.pending(), 0);
+            for (key, value) in &expected {
+                assert_eq!(counters.tree.get(key), Some(value));
+            }
+        }
+
+        let mut sets = MergeBTree::<&str, BTreeSet<u64>>::new(4, 8, |a, b| a | b);
+        sets.merge("a", BTreeSet::from([1, 2]));
+        sets.merge("b", BTreeSet::from([5]));
+        sets.flush();
+        sets.merge("a", BTreeSet::from([2, 3]));
+        sets.merge("a", BTreeSet::from([4]));
+        assert_eq!(sets.pending(), 1);
+        assert_eq!(sets.get(&"a"), Some(BTreeSet::from([1, 2, 3, 4])));
+        assert_eq!(sets.get(&"b"), Some(BTreeSet::from([5])));
+        assert_eq!(sets.get(&"c"), None);
+    }
+}
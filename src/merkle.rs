@@ -0,0 +1,174 @@
+// Merkle hashing. A node's hash covers its encoded entries and its children's
+// hashes, so the root hash changes with any entry anywhere. Two replicas with
+// equal root hashes hold the same entries, and where the hashes differ they
+// can compare children to find the differing subtrees. The hash also covers
+// the tree's shape, so replicas with the same entries only hash alike when
+// they were built alike, e.g. both with from_sorted.
+//
+// A proof for a key is the content of every node on the path to it, which is
+// enough for anyone holding only the root hash to check the entry.
+//
+// Hashes are computed when asked for rather than kept in the nodes.
+
+use crate::checksum::sha256;
+use crate::codec::Codec;
+use crate::{BTree, BTreeNode};
+
+pub type Hash = [u8; 32];
+
+// One node on the path to a proven key, root first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProofNode {
+    // Encoded keys and values.
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    children: Vec<Hash>,
+    // The child the path continues in, or in the last node the entry proven.
+    next: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proof {
+    path: Vec<ProofNode>,
+}
+
+fn digest(entries: &[(Vec<u8>, Vec<u8>)], children: &[Hash]) -> Hash {
+    let mut data = vec![u8::from(!children.is_empty())];
+    for (i, (key, value)) in entries.iter().enumerate() {
+        if let Some(child) = children.get(i) {
+            data.extend_from_slice(child);
+        }
+        for item in [key, value] {
+            data.extend_from_slice(&(item.len() as u32).to_le_bytes());
+            data.extend_from_slice(item);
+        }
+    }
+    if let Some(child) = children.get(entries.len()) {
+        data.extend_from_slice(child);
+    }
+
+    sha256(&data)
+}
+
+fn encode<T: Codec>(item: &T) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    item.encode(&mut bytes);
+    bytes
+}
+
+impl<K: Ord + Clone + std::fmt::Debug + Codec, V: Ord + Clone + std::fmt::Debug + Codec>
+    BTreeNode<K, V>
+{
+    fn encoded_entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.keys
+            .iter()
+            .zip(&self.values)
+            .map(|(key, value)| (encode(key), encode(value)))
+            .collect()
+    }
+
+    fn child_hashes(&self) -> Vec<Hash> {
+        self.children
+            .iter()
+            .map(|child| child.merkle_hash())
+            .collect()
+    }
+
+    fn merkle_hash(&self) -> Hash {
+        digest(&self.encoded_entries(), &self.child_hashes())
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug + Codec, V: Ord + Clone + std::fmt::Debug + Codec>
+    BTree<K, V>
+{
+    pub fn root_hash(&self) -> Hash {
+        self.root.merkle_hash()
+    }
+
+    // Returns None if the key is absent.
+    pub fn prove(&self, key: &K) -> Option<Proof> {
+        let mut path = Vec::new();
+        let mut node = &self.root;
+
+        loop {
+            let i = BTreeNode::<K, V>::find_it(&node.keys, key);
+            let next = if i < 0 { -(i + 1) } else { i } as usize;
+            path.push(ProofNode {
+                entries: node.encoded_entries(),
+                children: node.child_hashes(),
+                next,
+            });
+
+            if i < 0 {
+                return Some(Proof { path });
+            }
+            node = node.children.get(next)?;
+        }
+    }
+}
+
+// Checks that the tree with root hash `root` holds `key` with `value`.
+pub fn verify_proof<K: Codec, V: Codec>(root: &Hash, key: &K, value: &V, proof: &Proof) -> bool {
+    let Some(last) = proof.path.last() else {
+        return false;
+    };
+    if last.entries.get(last.next) != Some(&(encode(key), encode(value))) {
+        return false;
+    }
+
+    let mut expected = *root;
+    for node in &proof.path {
+        if digest(&node.entries, &node.children) != expected {
+            return false;
+        }
+        if let Some(child) = node.children.get(node.next) {
+            expected = *child;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::merkle::verify_proof;
+    use crate::BTree;
+
+    #[test]
+    fn test_merkle_proofs() {
+        let mut tree = BTree::<u64, String>::new(4);
+        let mut replica = BTree::<u64, String>::new(4);
+        for key in 0..200 {
+            tree.add(key, key.to_string());
+            replica.add(199 - key, (199 - key).to_string());
+        }
+
+        // Equal contents give equal hashes however they were inserted, as
+        // long as the shape matches; from_sorted builds the same shape.
+        let sorted = |tree: &BTree<u64, String>| {
+            BTree::from_sorted(4, tree.iter().map(|(k, v)| (*k, v.clone())).collect())
+        };
+        assert_eq!(sorted(&tree).root_hash(), sorted(&replica).root_hash());
+        let root = tree.root_hash();
+        replica.add(50, "changed".to_string());
+        assert_ne!(sorted(&replica).root_hash(), sorted(&tree).root_hash());
+
+        for key in [0, 57, 123, 199] {
+            let proof = tree.prove(&key).unwrap();
+            assert!(verify_proof(&root, &key, &key.to_string(), &proof));
+            assert!(!verify_proof(&root, &key, &"forged".to_string(), &proof));
+            assert!(!verify_proof(
+                &replica.root_hash(),
+                &key,
+                &key.to_string(),
+                &proof
+            ));
+        }
+        assert_eq!(tree.prove(&1000), None);
+
+        // A proof stops verifying once anything on its path is altered.
+        let mut proof = tree.prove(&57).unwrap();
+        proof.path[0].entries[0].1 = b"tampered".to_vec();
+        assert!(!verify_proof(&root, &57, &"57".to_string(), &proof));
+    }
+}
@@ -0,0 +1,240 @@
+// Operation counts and latencies for services embedding the tree, exported
+// in the Prometheus text format. Only built with the `metrics` feature; the
+// tree and pager bump these from their hot paths, so without the feature
+// nothing is recorded and nothing is paid.
+//
+// Everything is process-wide and lock-free: counters are relaxed atomics,
+// and a histogram is a fixed set of atomic buckets.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Counter {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Upper bounds of the latency buckets, in seconds, from a microsecond to a
+// second. Slower observations only land in the implicit +Inf bucket.
+const BOUNDS: [f64; 7] = [0.000_001, 0.000_01, 0.000_1, 0.001, 0.01, 0.1, 1.0];
+
+pub struct Histogram {
+    // Observations per bucket, not cumulative; the last is +Inf.
+    buckets: [AtomicU64; BOUNDS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Histogram {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; BOUNDS.len() + 1],
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = BOUNDS.partition_point(|bound| *bound < seconds);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    // Observes the time until the returned guard is dropped.
+    pub fn start(&self) -> Timer<'_> {
+        Timer {
+            histogram: self,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+pub struct Timer<'a> {
+    histogram: &'a Histogram,
+    start: Instant,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        self.histogram.observe(self.start.elapsed());
+    }
+}
+
+pub struct Metrics {
+    pub inserts: Counter,
+    pub removes: Counter,
+    pub lookups: Counter,
+    pub splits: Counter,
+    pub merges: Counter,
+    // Pins served from the page cache and pins that read the page.
+    pub page_hits: Counter,
+    pub page_misses: Counter,
+    pub page_writes: Counter,
+    pub insert_seconds: Histogram,
+    pub flush_seconds: Histogram,
+}
+
+pub static METRICS: Metrics = Metrics {
+    inserts: Counter::new(),
+    removes: Counter::new(),
+    lookups: Counter::new(),
+    splits: Counter::new(),
+    merges: Counter::new(),
+    page_hits: Counter::new(),
+    page_misses: Counter::new(),
+    page_writes: Counter::new(),
+    insert_seconds: Histogram::new(),
+    flush_seconds: Histogram::new(),
+};
+
+fn render_counter(out: &mut String, name: &str, help: &str, counter: &Counter) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} counter", name).unwrap();
+    writeln!(out, "{} {}", name, counter.get()).unwrap();
+}
+
+fn render_histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} histogram", name).unwrap();
+    let mut cumulative = 0;
+    for (bound, bucket) in BOUNDS.iter().zip(&histogram.buckets) {
+        cumulative += bucket.load(Ordering::Relaxed);
+        writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative).unwrap();
+    }
+    cumulative += histogram.buckets[BOUNDS.len()].load(Ordering::Relaxed);
+    writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative).unwrap();
+    let sum = histogram.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+    writeln!(out, "{}_sum {}", name, sum).unwrap();
+    writeln!(out, "{}_count {}", name, cumulative).unwrap();
+}
+
+// Everything recorded so far, for a /metrics endpoint.
+pub fn render() -> String {
+    let m = &METRICS;
+    let counters = [
+        (
+            "ctree_inserts_total",
+            "Entries added to a BTree.",
+            &m.inserts,
+        ),
+        (
+            "ctree_removes_total",
+            "Entries removed from a BTree.",
+            &m.removes,
+        ),
+        ("ctree_lookups_total", "BTree point lookups.", &m.lookups),
+        ("ctree_node_splits_total", "BTree node splits.", &m.splits),
+        ("ctree_node_merges_total", "BTree node merges.", &m.merges),
+        (
+            "ctree_page_cache_hits_total",
+            "Page pins served from the cache.",
+            &m.page_hits,
+        ),
+        (
+            "ctree_page_cache_misses_total",
+            "Page pins that read the page.",
+            &m.page_misses,
+        ),
+        (
+            "ctree_page_writes_total",
+            "Pages written back to the store.",
+            &m.page_writes,
+        ),
+    ];
+    let histograms = [
+        (
+            "ctree_insert_seconds",
+            "Time taken by BTree inserts.",
+            &m.insert_seconds,
+        ),
+        (
+            "ctree_flush_seconds",
+            "Time taken by pager flushes.",
+            &m.flush_seconds,
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, counter) in counters {
+        render_counter(&mut out, name, help, counter);
+    }
+    for (name, help, histogram) in histograms {
+        render_histogram(&mut out, name, help, histogram);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::metrics::{render, Histogram, METRICS};
+    use crate::BTree;
+
+    #[test]
+    fn test_metrics() {
+        // Other tests run alongside and record too, so only lower bounds
+        // hold for the shared counters.
+        let (inserts, splits, lookups) = (
+            METRICS.inserts.get(),
+            METRICS.splits.get(),
+            METRICS.lookups.get(),
+        );
+        let mut tree = BTree::<u64, u64>::new(3);
+        for key in 0..100 {
+            tree.add(key, key);
+        }
+        tree.get(&5);
+        assert!(METRICS.inserts.get() >= inserts + 100);
+        assert!(METRICS.splits.get() > splits);
+        assert!(METRICS.lookups.get() > lookups);
+        assert!(METRICS.insert_seconds.count() >= 100);
+
+        let histogram = Histogram::new();
+        for micros in [0, 1, 5, 50_000, 5_000_000] {
+            histogram.observe(Duration::from_micros(micros));
+        }
+        let mut out = String::new();
+        super::render_histogram(&mut out, "test_seconds", "Test.", &histogram);
+        assert!(
+            out.contains("test_seconds_bucket{le=\"0.000001\"} 2\n"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("test_seconds_bucket{le=\"0.1\"} 4\n"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("test_seconds_bucket{le=\"+Inf\"} 5\n"),
+            "{}",
+            out
+        );
+        assert!(out.contains("test_seconds_sum 5.050006\n"), "{}", out);
+        assert!(out.contains("test_seconds_count 5\n"), "{}", out);
+
+        let text = render();
+        assert!(text.contains("# TYPE ctree_inserts_total counter\n"));
+        assert!(text.contains("# TYPE ctree_flush_seconds histogram\n"));
+    }
+}
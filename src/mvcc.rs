@@ -0,0 +1,470 @@
+// Multi-version tree. Every write is stamped with the next timestamp and adds
+// a version instead of replacing the previous one, so reads at an older
+// timestamp keep seeing the tree as it was. Removals add a tombstone.
+//
+// A retention policy bounds how much history each key keeps. A write prunes
+// the key it wrote, and prune walks the other keys a few at a time, so no
+// single call pays for the whole tree. Neither drops a version an open
+// snapshot can still see.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex};
+
+use crate::BTree;
+
+pub type Timestamp = u64;
+
+// Versions of one key, oldest first. None marks a removal.
+type Versions<V> = Vec<(Timestamp, Option<V>)>;
+
+// Timestamps of open snapshots, with how many are open at each. Shared with
+// the transactions pinning them, so a dropped transaction can unpin its own.
+type Snapshots = Arc<Mutex<BTreeMap<Timestamp, usize>>>;
+
+fn unpin(snapshots: &Snapshots, ts: Timestamp) {
+    let mut snapshots = snapshots.lock().unwrap();
+    if let Some(count) = snapshots.get_mut(&ts) {
+        *count -= 1;
+        if *count == 0 {
+            snapshots.remove(&ts);
+        }
+    }
+}
+
+// A transaction's snapshot, released when the transaction is dropped.
+struct Pin {
+    ts: Timestamp,
+    snapshots: Snapshots,
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        unpin(&self.snapshots, self.ts);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Retention {
+    // Everything, until gc.
+    All,
+    // The newest n versions of each key.
+    Versions(usize),
+    // Every version a read at one of the last n timestamps can see, which
+    // includes the newest one at or before the start of that window.
+    // Timestamps count writes, so this is an age in writes, not in time.
+    Within(Timestamp),
+}
+
+pub struct MvccBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    tree: BTree<K, Versions<V>>,
+    clock: Timestamp,
+    snapshots: Snapshots,
+    retention: Retention,
+    // Where the next prune continues from.
+    prune_cursor: Option<K>,
+}
+
+fn visible<V>(versions: &Versions<V>, ts: Timestamp) -> Option<&V> {
+    let index = versions.partition_point(|(version, _)| *version <= ts);
+    versions[..index].last()?.1.as_ref()
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> MvccBTree<K, V> {
+    pub fn new(node_size: usize) -> MvccBTree<K, V> {
+        MvccBTree::with_retention(node_size, Retention::All)
+    }
+
+    pub fn with_retention(node_size: usize, retention: Retention) -> MvccBTree<K, V> {
+        if let Retention::Versions(n) = retention {
+            assert!(n >= 1, "a key must keep at least one version");
+        }
+
+        MvccBTree {
+            tree: BTree::new(node_size),
+            clock: 0,
+            snapshots: Snapshots::default(),
+            retention,
+            prune_cursor: None,
+        }
+    }
+
+    // Timestamp of the latest write.
+    pub fn now(&self) -> Timestamp {
+        self.clock
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Timestamp {
+        self.write(key, Some(value))
+    }
+
+    pub fn remove(&mut self, key: K) -> Timestamp {
+        self.write(key, None)
+    }
+
+    fn write(&mut self, key: K, value: Option<V>) -> Timestamp {
+        self.clock += 1;
+        let ts = self.clock;
+
+        match self.tree.get_mut(&key) {
+            Some(versions) => versions.push((ts, value)),
+            None => {
+                self.tree.add(key.clone(), vec![(ts, value)]);
+            }
+        }
+        self.prune_key(&key);
+
+        ts
+    }
+
+    // Drops the key's versions the retention policy no longer keeps,
+    // returning how many.
+    fn prune_key(&mut self, key: &K) -> usize {
+        let (retention, clock) = (self.retention, self.clock);
+        let oldest_snapshot = self.oldest_snapshot();
+        let Some(versions) = self.tree.get_mut(key) else {
+            return 0;
+        };
+
+        let mut drop = match retention {
+            Retention::All => 0,
+            Retention::Versions(n) => versions.len().saturating_sub(n),
+            Retention::Within(age) => versions
+                .partition_point(|(version, _)| *version <= clock.saturating_sub(age))
+                .saturating_sub(1),
+        };
+        if let Some(snapshot) = oldest_snapshot {
+            let needed = versions.partition_point(|(version, _)| *version <= snapshot);
+            drop = drop.min(needed.saturating_sub(1));
+        }
+
+        versions.drain(..drop);
+        drop
+    }
+
+    // Applies the retention policy to up to `keys` keys, continuing after the
+    // last key the previous call reached and wrapping around at the end.
+    // Returns the number of versions dropped.
+    pub fn prune(&mut self, keys: usize) -> usize {
+        let start = match self.prune_cursor.take() {
+            Some(cursor) => Bound::Excluded(cursor),
+            None => Bound::Unbounded,
+        };
+        let batch: Vec<K> = self
+            .tree
+            .range((start, Bound::Unbounded))
+            .take(keys)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        if batch.len() == keys {
+            self.prune_cursor = batch.last().cloned();
+        }
+        batch.iter().map(|key| self.prune_key(key)).sum()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.get_at(key, self.clock)
+    }
+
+    // The value of `key` as of `ts`, counting writes stamped `ts` or earlier.
+    pub fn get_at(&self, key: &K, ts: Timestamp) -> Option<&V> {
+        visible(self.tree.get(key)?, ts)
+    }
+
+    pub fn scan_at<R: RangeBounds<K>>(
+        &self,
+        range: R,
+        ts: Timestamp,
+    ) -> impl Iterator<Item = (&K, &V)> {
+        self.tree
+            .range(range)
+            .filter_map(move |(key, versions)| Some((key, visible(versions, ts)?)))
+    }
+
+    // Timestamp of the latest write to `key`, removals included.
+    pub fn last_modified(&self, key: &K) -> Option<Timestamp> {
+        Some(self.tree.get(key)?.last()?.0)
+    }
+
+    // Starts an optimistic transaction reading the tree as of now. Its
+    // snapshot is pinned until it commits, rolls back or is dropped.
+    pub fn begin(&mut self) -> OptimisticTransaction<K, V> {
+        OptimisticTransaction {
+            start: Pin {
+                ts: self.snapshot(),
+                snapshots: Arc::clone(&self.snapshots),
+            },
+            writes: BTreeMap::new(),
+            reads: BTreeSet::new(),
+        }
+    }
+
+    // Pins the current timestamp so gc keeps every version reads at it need,
+    // until the snapshot is released.
+    pub fn snapshot(&mut self) -> Timestamp {
+        *self
+            .snapshots
+            .lock()
+            .unwrap()
+            .entry(self.clock)
+            .or_insert(0) += 1;
+        self.clock
+    }
+
+    pub fn release(&mut self, ts: Timestamp) {
+        unpin(&self.snapshots, ts);
+    }
+
+    fn oldest_snapshot(&self) -> Option<Timestamp> {
+        self.snapshots.lock().unwrap().keys().next().copied()
+    }
+
+    // Drops versions no open snapshot or future read can see: everything older
+    // than the newest version at or before the oldest snapshot, and keys whose
+    // only remaining version is such a tombstone. Returns the number of
+    // versions dropped.
+    pub fn gc(&mut self) -> usize {
+        let horizon = self.oldest_snapshot().unwrap_or(self.clock);
+
+        let keys: Vec<K> = self
+            .tree
+            .iter()
+            .filter(|(_, versions)| versions.len() > 1 || versions[0].1.is_none())
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut dropped = 0;
+        for key in keys {
+            let versions = self.tree.get_mut(&key).unwrap();
+            let keep_from = versions
+                .partition_point(|(version, _)| *version <= horizon)
+                .saturating_sub(1);
+            versions.drain(..keep_from);
+            dropped += keep_from;
+
+            if versions.len() == 1 && versions[0].0 <= horizon && versions[0].1.is_none() {
+                self.tree.remove(&key);
+                dropped += 1;
+            }
+        }
+
+        dropped
+    }
+}
+
+// Returned by commit when a key the transaction read was written after the
+// transaction started.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conflict<K> {
+    pub key: K,
+}
+
+// Reads from a snapshot and buffers writes without blocking anyone. Commit
+// validates that nothing it read has changed since, otherwise the caller
+// retries the whole transaction. Its writes are lost unless it commits.
+#[must_use]
+pub struct OptimisticTransaction<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug>
+{
+    start: Pin,
+    // None marks a removal.
+    writes: BTreeMap<K, Option<V>>,
+    reads: BTreeSet<K>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug>
+    OptimisticTransaction<K, V>
+{
+    pub fn get(&mut self, tree: &MvccBTree<K, V>, key: &K) -> Option<V> {
+        if let Some(write) = self.writes.get(key) {
+            return write.clone();
+        }
+
+        self.reads.insert(key.clone());
+        tree.get_at(key, self.start.ts).cloned()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.writes.insert(key, Some(value));
+    }
+
+    pub fn remove(&mut self, key: K) {
+        self.writes.insert(key, None);
+    }
+
+    // Applies the writes and returns the commit timestamp, unless a key in the
+    // read set was written after the transaction started.
+    pub fn commit(self, tree: &mut MvccBTree<K, V>) -> Result<Timestamp, Conflict<K>> {
+        debug_assert!(Arc::ptr_eq(&self.start.snapshots, &tree.snapshots));
+        let start = self.start.ts;
+        drop(self.start);
+
+        for key in self.reads {
+            if tree.last_modified(&key).is_some_and(|ts| ts > start) {
+                return Err(Conflict { key });
+            }
+        }
+
+        for (key, write) in self.writes {
+            match write {
+                Some(value) => tree.insert(key, value),
+                None => tree.remove(key),
+            };
+        }
+
+        Ok(tree.now())
+    }
+
+    // The same as dropping the transaction.
+    pub fn rollback(self, tree: &mut MvccBTree<K, V>) {
+        debug_assert!(Arc::ptr_eq(&self.start.snapshots, &tree.snapshots));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mvcc::{Conflict, MvccBTree, Retention};
+
+    #[test]
+    fn test_optimistic_transactions() {
+        let mut tree = MvccBTree::<&str, u64>::new(4);
+        tree.insert("a", 100);
+        tree.insert("b", 100);
+
+        // Both move money out of "a"; the one committing second read a stale
+        // balance and must retry.
+        let mut first = tree.begin();
+        let mut second = tree.begin();
+        let balance = first.get(&tree, &"a").unwrap();
+        first.insert("a", balance - 10);
+        let balance = second.get(&tree, &"a").unwrap();
+        second.insert("a", balance - 20);
+        second.insert("b", 120);
+
+        first.commit(&mut tree).unwrap();
+        assert_eq!(second.commit(&mut tree), Err(Conflict { key: "a" }));
+        assert_eq!(tree.get(&"a"), Some(&90));
+        assert_eq!(tree.get(&"b"), Some(&100));
+
+        let mut retry = tree.begin();
+        let balance = retry.get(&tree, &"a").unwrap();
+        retry.insert("a", balance - 20);
+        retry.insert("b", 120);
+        // Blind writes by others to keys it never read do not conflict.
+        tree.insert("c", 1);
+        retry.commit(&mut tree).unwrap();
+        assert_eq!(tree.get(&"a"), Some(&70));
+        assert_eq!(tree.get(&"b"), Some(&120));
+
+        let mut aborted = tree.begin();
+        aborted.remove("a");
+        aborted.rollback(&mut tree);
+        assert_eq!(tree.get(&"a"), Some(&70));
+        assert_eq!(tree.oldest_snapshot(), None);
+
+        // A transaction given up on without a rollback, as by ? on an error,
+        // doesn't keep history pinned.
+        tree.gc();
+        let abandoned = tree.begin();
+        tree.insert("a", 0);
+        assert_eq!(tree.gc(), 0);
+        drop(abandoned);
+        assert_eq!(tree.oldest_snapshot(), None);
+        assert!(tree.gc() > 0);
+    }
+
+    #[test]
+    fn test_mvcc() {
+        let mut tree = MvccBTree::<u64, String>::new(4);
+        for key in 0..100 {
+            tree.insert(key, format!("v1 {}", key));
+        }
+        let before = tree.snapshot();
+
+        for key in (0..100).step_by(2) {
+            tree.insert(key, format!("v2 {}", key));
+        }
+        let removed = tree.remove(5);
+
+        assert_eq!(tree.get(&4), Some(&"v2 4".to_string()));
+        assert_eq!(tree.get_at(&4, before), Some(&"v1 4".to_string()));
+        assert_eq!(tree.get(&5), None);
+        assert_eq!(tree.get_at(&5, removed - 1), Some(&"v1 5".to_string()));
+        assert_eq!(tree.get_at(&5, 0), None);
+
+        let scan = |tree: &MvccBTree<u64, String>, ts| {
+            tree.scan_at(3..7, ts)
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            scan(&tree, before),
+            ["3=v1 3", "4=v1 4", "5=v1 5", "6=v1 6"]
+        );
+        assert_eq!(scan(&tree, tree.now()), ["3=v1 3", "4=v2 4", "6=v2 6"]);
+
+        // The open snapshot still needs the first versions.
+        assert_eq!(tree.gc(), 0);
+        assert_eq!(tree.get_at(&4, before), Some(&"v1 4".to_string()));
+
+        tree.release(before);
+        assert_eq!(tree.gc(), 50 + 2);
+        assert_eq!(scan(&tree, tree.now()), ["3=v1 3", "4=v2 4", "6=v2 6"]);
+        assert_eq!(tree.tree.len(), 99);
+    }
+
+    #[test]
+    fn test_retention() {
+        let mut tree = MvccBTree::<u64, u64>::with_retention(4, Retention::Versions(3));
+        for round in 0..10 {
+            for key in 0..20 {
+                tree.insert(key, round);
+            }
+        }
+        assert!(tree.tree.iter().all(|(_, versions)| versions.len() == 3));
+        let ts = tree.now() - 20;
+        assert_eq!(tree.get_at(&0, ts), Some(&8));
+        assert_eq!(tree.get_at(&0, ts - 40), None);
+
+        // An open snapshot keeps what it can see.
+        let snapshot = tree.snapshot();
+        for round in 10..15 {
+            tree.insert(0, round);
+        }
+        assert_eq!(tree.get_at(&0, snapshot), Some(&9));
+        assert_eq!(tree.tree.get(&0).unwrap().len(), 6);
+        tree.release(snapshot);
+        assert_eq!(tree.prune(100), 3);
+        assert_eq!(tree.get_at(&0, snapshot), None);
+
+        // Keys no one writes again are pruned by prune, a few per call.
+        let mut tree = MvccBTree::<u64, u64>::with_retention(4, Retention::Within(50));
+        for round in 0..3 {
+            for key in 0..20 {
+                tree.insert(key, round);
+            }
+        }
+        assert_eq!(tree.prune(0), 0);
+        for _ in 0..50 {
+            tree.insert(100, 0);
+        }
+        assert_eq!(tree.tree.get(&100).unwrap().len(), 50);
+
+        let mut dropped = 0;
+        for _ in 0..3 {
+            dropped += tree.prune(8);
+        }
+        assert_eq!(dropped, 20 * 2);
+        assert!(tree
+            .tree
+            .iter()
+            .all(|(_, versions)| versions.len() == 1 || versions.len() == 50));
+        assert_eq!(tree.get(&7), Some(&2));
+        assert_eq!(tree.prune(100), 0);
+
+        // A read at the start of the window still sees what was there then.
+        tree.insert(7, 3);
+        assert_eq!(tree.get_at(&7, tree.now() - 50 + 1), Some(&2));
+        assert_eq!(tree.get(&7), Some(&3));
+    }
+}
@@ -0,0 +1,155 @@
+// A tree that reports every change it makes, for audit logs and change
+// feeds. The logger sees each change just before it is applied, with the
+// value it replaces, so a feed can be replayed or undone. Writes that change
+// nothing, like removing a missing key, aren't reported.
+
+use std::ops::RangeBounds;
+
+use crate::batch::WriteBatch;
+use crate::{BTree, Iter, Range};
+
+pub trait OpLogger<K, V> {
+    // `old` is the value before the change and `new` the value after, None
+    // where the key is absent: an insert of a new key has no old value and a
+    // removal no new one.
+    fn log(&mut self, key: &K, old: Option<&V>, new: Option<&V>);
+}
+
+impl<K, V, F: FnMut(&K, Option<&V>, Option<&V>)> OpLogger<K, V> for F {
+    fn log(&mut self, key: &K, old: Option<&V>, new: Option<&V>) {
+        self(key, old, new)
+    }
+}
+
+pub struct LoggedBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug, L> {
+    tree: BTree<K, V>,
+    logger: L,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug, L: OpLogger<K, V>>
+    LoggedBTree<K, V, L>
+{
+    pub fn new(node_size: usize, logger: L) -> LoggedBTree<K, V, L> {
+        LoggedBTree::with_tree(BTree::new(node_size), logger)
+    }
+
+    // Starts from an existing tree. Its current entries aren't reported.
+    pub fn with_tree(tree: BTree<K, V>, logger: L) -> LoggedBTree<K, V, L> {
+        LoggedBTree { tree, logger }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.logger.log(&key, self.tree.get(&key), Some(&value));
+        self.tree.add(key, value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        if let Some(old) = self.tree.get(key) {
+            self.logger.log(key, Some(old), None);
+        }
+        self.tree.remove(key)
+    }
+
+    // Applies the batch in order, reporting each write that changes the tree.
+    pub fn write(&mut self, batch: WriteBatch<K, V>) {
+        for (key, write) in batch {
+            match write {
+                Some(value) => {
+                    self.insert(key, value);
+                }
+                None => {
+                    self.remove(&key);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.tree.get(key)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.tree.iter()
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        self.tree.range(range)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn logger(&self) -> &L {
+        &self.logger
+    }
+
+    pub fn logger_mut(&mut self) -> &mut L {
+        &mut self.logger
+    }
+
+    pub fn into_parts(self) -> (BTree<K, V>, L) {
+        (self.tree, self.logger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::batch::WriteBatch;
+    use crate::oplog::LoggedBTree;
+    use crate::testutil::Rng;
+    use crate::BTree;
+
+    type Change = (u64, Option<u64>, Option<u64>);
+
+    #[test]
+    fn test_logged_btree() {
+        let mut changes: Vec<Change> = Vec::new();
+        let log = |key: &u64, old: Option<&u64>, new: Option<&u64>| {
+            changes.push((*key, old.copied(), new.copied()))
+        };
+        let mut tree = LoggedBTree::new(3, log);
+        let mut expected = BTreeMap::new();
+        let mut rng = Rng::new();
+
+        for _ in 0..2000 {
+            let state = rng.next_u64();
+            let key = state % 100;
+            if state.is_multiple_of(3) {
+                assert_eq!(tree.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(
+                    tree.insert(key, state >> 40),
+                    expected.insert(key, state >> 40)
+                );
+            }
+        }
+        let mut batch = WriteBatch::new();
+        batch.insert(1000, 1).remove(1000).remove(1000);
+        tree.write(batch);
+        let (tree, _) = tree.into_parts();
+        assert!(tree.iter().eq(expected.iter()));
+
+        // Replaying the feed onto an empty tree rebuilds it, and each old
+        // value matches what the replay holds at that point.
+        let mut replay = BTree::<u64, u64>::new(4);
+        for (key, old, new) in &changes {
+            assert_eq!(replay.get(key), old.as_ref());
+            match new {
+                Some(value) => replay.add(*key, *value),
+                None => replay.remove(key),
+            };
+        }
+        assert!(replay.iter().eq(expected.iter()));
+        assert_eq!(
+            changes[changes.len() - 2..],
+            [(1000, None, Some(1)), (1000, Some(1), None)]
+        );
+    }
+}
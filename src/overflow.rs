@@ -0,0 +1,235 @@
+use std::io;
+
+use crate::codec::invalid_data;
+use crate::pager::{PageId, Pager};
+use crate::slotted::{SlottedPage, Value};
+use crate::store::PageStore;
+
+// Values larger than a quarter of a page are moved out of the leaf.
+const INLINE_FRACTION: usize = 4;
+
+// Overflow pages start with the id of the next page in the chain, 0 for the
+// last one, followed by a chunk of the value.
+const NEXT_SIZE: usize = 8;
+
+pub fn inline_limit<S: PageStore>(pager: &Pager<S>) -> usize {
+    pager.page_size() / INLINE_FRACTION
+}
+
+pub fn write_chain<S: PageStore>(pager: &mut Pager<S>, bytes: &[u8]) -> io::Result<PageId> {
+    let chunk_size = pager.payload_size() - NEXT_SIZE;
+    let mut next: PageId = 0;
+
+    // Written back to front so every page already knows its successor.
+    for chunk in bytes.chunks(chunk_size).rev() {
+        let id = pager.allocate()?;
+        let page = pager.page_mut(id);
+        page[..NEXT_SIZE].copy_from_slice(&next.to_le_bytes());
+        page[NEXT_SIZE..NEXT_SIZE + chunk.len()].copy_from_slice(chunk);
+        pager.unpin(id, true)?;
+
+        next = id;
+    }
+
+    Ok(next)
+}
+
+pub fn read_chain<S: PageStore>(
+    pager: &mut Pager<S>,
+    first_page: PageId,
+    len: u64,
+) -> io::Result<Vec<u8>> {
+    let chunk_size = pager.payload_size() - NEXT_SIZE;
+    // The length comes from the leaf, so it is checked against the pages
+    // there are and only caps the reservation.
+    if len > pager.num_pages().saturating_mul(chunk_size as u64) {
+        return Err(invalid_data("overflow value is longer than the file"));
+    }
+    let mut bytes = Vec::with_capacity(len.min(1 << 16) as usize);
+    let mut id = first_page;
+
+    while bytes.len() < len as usize {
+        if id == 0 {
+            return Err(invalid_data("overflow chain ends early"));
+        }
+
+        pager.pin(id)?;
+        let page = pager.page(id);
+        let take = chunk_size.min(len as usize - bytes.len());
+        bytes.extend_from_slice(&page[NEXT_SIZE..NEXT_SIZE + take]);
+        let next = u64::from_le_bytes(page[..NEXT_SIZE].try_into().unwrap());
+        pager.unpin(id, false)?;
+
+        id = next;
+    }
+
+    Ok(bytes)
+}
+
+pub fn free_chain<S: PageStore>(pager: &mut Pager<S>, first_page: PageId) -> io::Result<()> {
+    let mut id = first_page;
+
+    while id != 0 {
+        pager.pin(id)?;
+        let next = u64::from_le_bytes(pager.page(id)[..NEXT_SIZE].try_into().unwrap());
+        pager.unpin(id, false)?;
+
+        pager.free(id)?;
+        id = next;
+    }
+
+    Ok(())
+}
+
+// Stores `key` in the slotted leaf page `leaf`, spilling large values to an
+// overflow chain. Returns false if the entry does not fit in the leaf.
+pub fn insert<S: PageStore>(
+    pager: &mut Pager<S>,
+    leaf: PageId,
+    key: &[u8],
+    value: &[u8],
+) -> io::Result<bool> {
+    let old = overflow_pointer(pager, leaf, key)?;
+
+    let inserted = if value.len() > inline_limit(pager) {
+        let first_page = write_chain(pager, value)?;
+
+        pager.pin(leaf)?;
+        let inserted = SlottedPage::new(pager.page_mut(leaf)).insert_overflow(
+            key,
+            first_page,
+            value.len() as u64,
+        );
+        pager.unpin(leaf, inserted)?;
+
+        if !inserted {
+            free_chain(pager, first_page)?;
+        }
+        inserted
+    } else {
+        pager.pin(leaf)?;
+        let inserted = SlottedPage::new(pager.page_mut(leaf)).insert(key, value);
+        pager.unpin(leaf, inserted)?;
+        inserted
+    };
+
+    if inserted {
+        if let Some(first_page) = old {
+            free_chain(pager, first_page)?;
+        }
+    }
+
+    Ok(inserted)
+}
+
+pub fn get<S: PageStore>(
+    pager: &mut Pager<S>,
+    leaf: PageId,
+    key: &[u8],
+) -> io::Result<Option<Vec<u8>>> {
+    pager.pin(leaf)?;
+    let value = match SlottedPage::new(pager.page(leaf)).get(key) {
+        Some(Value::Inline(bytes)) => Ok(bytes.to_vec()),
+        Some(Value::Overflow { first_page, len }) => Err((first_page, len)),
+        None => {
+            pager.unpin(leaf, false)?;
+            return Ok(None);
+        }
+    };
+    pager.unpin(leaf, false)?;
+
+    match value {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err((first_page, len)) => read_chain(pager, first_page, len).map(Some),
+    }
+}
+
+pub fn remove<S: PageStore>(pager: &mut Pager<S>, leaf: PageId, key: &[u8]) -> io::Result<bool> {
+    let old = overflow_pointer(pager, leaf, key)?;
+
+    pager.pin(leaf)?;
+    let removed = SlottedPage::new(pager.page_mut(leaf)).remove(key);
+    pager.unpin(leaf, removed)?;
+
+    if let Some(first_page) = old {
+        free_chain(pager, first_page)?;
+    }
+
+    Ok(removed)
+}
+
+fn overflow_pointer<S: PageStore>(
+    pager: &mut Pager<S>,
+    leaf: PageId,
+    key: &[u8],
+) -> io::Result<Option<PageId>> {
+    pager.pin(leaf)?;
+    let first_page = match SlottedPage::new(pager.page(leaf)).get(key) {
+        Some(Value::Overflow { first_page, .. }) => Some(first_page),
+        _ => None,
+    };
+    pager.unpin(leaf, false)?;
+
+    Ok(first_page)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::overflow;
+    use crate::pager::Pager;
+    use crate::slotted::SlottedPage;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_overflow_values() {
+        let dir = TempDir::new("overflow");
+        let path = dir.join("pages");
+
+        let mut pager = Pager::open(&path, 512, 3, 8).unwrap();
+        let leaf = pager.allocate().unwrap();
+        SlottedPage::init(pager.page_mut(leaf));
+        pager.unpin(leaf, true).unwrap();
+
+        let big: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        assert!(overflow::insert(&mut pager, leaf, b"small", b"value").unwrap());
+        assert!(overflow::insert(&mut pager, leaf, b"big", &big).unwrap());
+        let pages = pager.num_pages();
+        assert!(pages > 10);
+
+        assert_eq!(
+            overflow::get(&mut pager, leaf, b"small").unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(
+            overflow::get(&mut pager, leaf, b"big").unwrap(),
+            Some(big.clone())
+        );
+        assert_eq!(overflow::get(&mut pager, leaf, b"missing").unwrap(), None);
+
+        // Replacing the big value frees its chain for the next one to reuse.
+        assert!(overflow::insert(&mut pager, leaf, b"big", b"tiny").unwrap());
+        assert_eq!(pager.free_pages(), pages - 2);
+        assert!(overflow::insert(&mut pager, leaf, b"other", &big).unwrap());
+        assert_eq!(pager.num_pages(), pages);
+
+        assert!(overflow::remove(&mut pager, leaf, b"other").unwrap());
+        assert_eq!(pager.free_pages(), pages - 2);
+        assert_eq!(
+            overflow::get(&mut pager, leaf, b"big").unwrap(),
+            Some(b"tiny".to_vec())
+        );
+
+        // A corrupt length in the leaf is refused before anything is read.
+        let first_page = overflow::write_chain(&mut pager, &big).unwrap();
+        assert_eq!(
+            overflow::read_chain(&mut pager, first_page, big.len() as u64).unwrap(),
+            big
+        );
+        for len in [pager.num_pages() * 512, u64::MAX] {
+            let err = overflow::read_chain(&mut pager, first_page, len).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{}", len);
+        }
+
+        drop(pager);
+    }
+}
@@ -0,0 +1,1040 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::checksum::crc32;
+use crate::codec::invalid_data;
+use crate::store::{FileStore, PageStore};
+
+pub type PageId = u64;
+
+// Every page starts with a CRC32 of the rest of the page.
+pub const PAGE_HEADER_SIZE: usize = 4;
+
+// Page sizes are powers of two in this range, chosen when a file is created.
+pub const MIN_PAGE_SIZE: usize = 512;
+pub const MAX_PAGE_SIZE: usize = 64 * 1024;
+
+// Page 0 holds the file header, data pages start at 1.
+pub const HEADER_PAGE: PageId = 0;
+
+const MAGIC: [u8; 8] = *b"c-tree\0\0";
+// Version 2 added the freelist to the header.
+pub const FORMAT_VERSION: u32 = 2;
+const FILE_HEADER_SIZE: usize = 36;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub page_size: u32,
+    pub tree_order: u32,
+    pub freelist_head: PageId,
+    pub free_pages: u64,
+}
+
+impl Header {
+    fn encode(&self, out: &mut [u8]) {
+        out[..8].copy_from_slice(&MAGIC);
+        out[8..12].copy_from_slice(&self.version.to_le_bytes());
+        out[12..16].copy_from_slice(&self.page_size.to_le_bytes());
+        out[16..20].copy_from_slice(&self.tree_order.to_le_bytes());
+        out[20..28].copy_from_slice(&self.freelist_head.to_le_bytes());
+        out[28..36].copy_from_slice(&self.free_pages.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Header> {
+        if bytes[..8] != MAGIC {
+            return Err(invalid_data("not a c-tree page file"));
+        }
+
+        let word = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let long = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+
+        let version = word(8);
+        Ok(Header {
+            version,
+            page_size: word(12),
+            tree_order: word(16),
+            freelist_head: if version >= 2 { long(20) } else { 0 },
+            free_pages: if version >= 2 { long(28) } else { 0 },
+        })
+    }
+
+    // Reads the header straight from the start of the file, before the page
+    // size is known.
+    fn read<S: PageStore>(store: &mut S) -> io::Result<Header> {
+        let mut bytes = [0; PAGE_HEADER_SIZE + FILE_HEADER_SIZE];
+        store.read_at(0, &mut bytes)?;
+
+        Header::decode(&bytes[PAGE_HEADER_SIZE..])
+    }
+}
+
+// When written pages are fsynced. There is no write-ahead log yet, so this
+// only covers the page file itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    // Every dirty page is written and synced as soon as it is unpinned.
+    Always,
+    // Pages are synced on every flush().
+    OnCommit,
+    // Pages are synced on flush() at most once per interval, and dirty pages
+    // are flushed once the interval has passed.
+    Periodic(Duration),
+    // Pages are written on flush() but syncing is left to the OS.
+    Never,
+}
+
+struct Frame {
+    data: Vec<u8>,
+    dirty: bool,
+    pin_count: usize,
+    last_used: u64,
+}
+
+pub struct Pager<S: PageStore = FileStore> {
+    store: S,
+    header: Header,
+    page_size: usize,
+    capacity: usize,
+    num_pages: u64,
+    header_dirty: bool,
+    sync_mode: SyncMode,
+    last_sync: Instant,
+    frames: HashMap<PageId, Frame>,
+    // Unpinned frames ordered by last use, oldest first.
+    lru: BTreeMap<u64, PageId>,
+    tick: u64,
+    // Every page on the freelist, read from the chain the first time free or
+    // vacuum needs it and kept up to date after that.
+    free_set: Option<HashSet<PageId>>,
+}
+
+impl Pager<FileStore> {
+    // Opens the page file at `path`, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        page_size: usize,
+        tree_order: usize,
+        capacity: usize,
+    ) -> io::Result<Pager<FileStore>> {
+        Pager::with_store(FileStore::open(path)?, page_size, tree_order, capacity)
+    }
+
+    // Migrates the file at `path` to the current format version in place.
+    pub fn upgrade<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        let mut store = FileStore::open(path)?;
+        let mut header = Header::read(&mut store)?;
+
+        if header.version > FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "cannot upgrade from unknown format version {}",
+                header.version
+            )));
+        }
+        if header.version == FORMAT_VERSION {
+            return Ok(());
+        }
+
+        while header.version < FORMAT_VERSION {
+            match header.version {
+                1 => {
+                    header.freelist_head = 0;
+                    header.free_pages = 0;
+                }
+                version => {
+                    return Err(invalid_data(format!(
+                        "cannot upgrade from unknown format version {}",
+                        version
+                    )))
+                }
+            }
+            header.version += 1;
+        }
+
+        write_header(&mut store, &header, header.page_size as usize)?;
+        store.sync()
+    }
+}
+
+impl<S: PageStore> Pager<S> {
+    // Opens a pager over `store`, writing a fresh header if it is empty. An
+    // existing store must have been written with the same format version, page
+    // size and tree order.
+    pub fn with_store(
+        mut store: S,
+        page_size: usize,
+        tree_order: usize,
+        capacity: usize,
+    ) -> io::Result<Pager<S>> {
+        if capacity == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the buffer pool needs room for at least one page",
+            ));
+        }
+        if !valid_page_size(page_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "page size {} is not a power of two between {} and {}",
+                    page_size, MIN_PAGE_SIZE, MAX_PAGE_SIZE
+                ),
+            ));
+        }
+
+        let expected = Header {
+            version: FORMAT_VERSION,
+            page_size: page_size as u32,
+            tree_order: tree_order as u32,
+            freelist_head: 0,
+            free_pages: 0,
+        };
+
+        let len = store.size()?;
+        let header = if len == 0 {
+            write_header(&mut store, &expected, page_size)?;
+            store.sync()?;
+            expected
+        } else {
+            let header = Header::read(&mut store)?;
+            if header.version < FORMAT_VERSION {
+                return Err(invalid_data(format!(
+                    "file has format version {}, upgrade it to version {} first",
+                    header.version, FORMAT_VERSION
+                )));
+            }
+            if header.version > FORMAT_VERSION {
+                return Err(invalid_data(format!(
+                    "unsupported format version {}, expected {}",
+                    header.version, FORMAT_VERSION
+                )));
+            }
+            if !valid_page_size(header.page_size as usize) {
+                return Err(invalid_data(format!(
+                    "file header has invalid page size {}",
+                    header.page_size
+                )));
+            }
+            if len % header.page_size as u64 != 0 {
+                return Err(invalid_data(format!(
+                    "file size {} is not a multiple of its page size {}",
+                    len, header.page_size
+                )));
+            }
+            if header.page_size != expected.page_size {
+                return Err(invalid_data(format!(
+                    "file has page size {}, opened with {}",
+                    header.page_size, page_size
+                )));
+            }
+            if header.tree_order != expected.tree_order {
+                return Err(invalid_data(format!(
+                    "file has tree order {}, opened with {}",
+                    header.tree_order, tree_order
+                )));
+            }
+            header
+        };
+
+        let mut pager = Pager {
+            store,
+            header,
+            page_size,
+            capacity,
+            num_pages: len.max(page_size as u64) / page_size as u64,
+            header_dirty: false,
+            sync_mode: SyncMode::OnCommit,
+            last_sync: Instant::now(),
+            frames: HashMap::with_capacity(capacity),
+            lru: BTreeMap::new(),
+            tick: 0,
+            free_set: None,
+        };
+
+        let mut data = vec![0; page_size];
+        pager.read_page(HEADER_PAGE, &mut data)?;
+
+        Ok(pager)
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.sync_mode = sync_mode;
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn payload_size(&self) -> usize {
+        self.page_size - PAGE_HEADER_SIZE
+    }
+
+    pub fn num_pages(&self) -> u64 {
+        self.num_pages
+    }
+
+    // Allocates a zeroed page and returns it pinned. Pages on the freelist are
+    // reused before the file is grown.
+    pub fn allocate(&mut self) -> io::Result<PageId> {
+        if self.header.freelist_head != 0 {
+            let id = self.header.freelist_head;
+            if id >= self.num_pages || self.header.free_pages == 0 {
+                return Err(invalid_data(format!(
+                    "freelist head {} is not a free page",
+                    id
+                )));
+            }
+            self.pin(id)?;
+
+            // The chain ends at the header page, after exactly free_pages links.
+            let next = u64::from_le_bytes(self.page(id)[..8].try_into().unwrap());
+            if next >= self.num_pages || (next == HEADER_PAGE) != (self.header.free_pages == 1) {
+                self.unpin(id, false)?;
+                return Err(invalid_data(format!(
+                    "free page {} links to page {}, which is not a free page",
+                    id, next
+                )));
+            }
+            self.page_mut(id).fill(0);
+
+            self.header.freelist_head = next;
+            self.header.free_pages -= 1;
+            self.header_dirty = true;
+            if let Some(free_set) = &mut self.free_set {
+                free_set.remove(&id);
+            }
+
+            return Ok(id);
+        }
+
+        self.make_room()?;
+
+        let id = self.num_pages;
+        self.num_pages += 1;
+        self.tick += 1;
+        self.frames.insert(
+            id,
+            Frame {
+                data: vec![0; self.page_size],
+                dirty: true,
+                pin_count: 1,
+                last_used: self.tick,
+            },
+        );
+
+        Ok(id)
+    }
+
+    // Returns an unpinned page to the freelist. Free pages are chained through
+    // the first eight bytes of their payload.
+    pub fn free(&mut self, id: PageId) -> io::Result<()> {
+        if id == HEADER_PAGE || id >= self.num_pages {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("page {} is not a data page", id),
+            ));
+        }
+        if let Some(frame) = self.frames.get(&id) {
+            assert!(frame.pin_count == 0, "page {} is still pinned", id);
+        }
+        if self.free_set()?.contains(&id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("page {} is already free", id),
+            ));
+        }
+
+        self.pin(id)?;
+        let next = self.header.freelist_head;
+        let page = self.page_mut(id);
+        page.fill(0);
+        page[..8].copy_from_slice(&next.to_le_bytes());
+
+        self.header.freelist_head = id;
+        self.header.free_pages += 1;
+        self.header_dirty = true;
+        self.free_set()?.insert(id);
+
+        self.unpin(id, true)
+    }
+
+    // Reads the freelist chain the first time it is needed, checking that it
+    // only links data pages, has no cycles and is as long as the header says.
+    fn free_set(&mut self) -> io::Result<&mut HashSet<PageId>> {
+        if self.free_set.is_none() {
+            let mut free_set = HashSet::new();
+            let mut id = self.header.freelist_head;
+            while id != HEADER_PAGE {
+                if id >= self.num_pages
+                    || free_set.len() as u64 == self.header.free_pages
+                    || !free_set.insert(id)
+                {
+                    return Err(invalid_data(format!(
+                        "freelist link to page {} is not a free page",
+                        id
+                    )));
+                }
+                self.pin(id)?;
+                let next = u64::from_le_bytes(self.page(id)[..8].try_into().unwrap());
+                self.unpin(id, false)?;
+                id = next;
+            }
+            if free_set.len() as u64 != self.header.free_pages {
+                return Err(invalid_data(format!(
+                    "freelist has {} pages, header says {}",
+                    free_set.len(),
+                    self.header.free_pages
+                )));
+            }
+            self.free_set = Some(free_set);
+        }
+
+        Ok(self.free_set.as_mut().unwrap())
+    }
+
+    pub fn free_pages(&self) -> u64 {
+        self.header.free_pages
+    }
+
+    pub fn pin(&mut self, id: PageId) -> io::Result<()> {
+        assert!(id != HEADER_PAGE, "the header page cannot be pinned");
+        assert!(id < self.num_pages, "page {} out of bounds", id);
+
+        #[cfg(feature = "metrics")]
+        if self.frames.contains_key(&id) {
+            crate::metrics::METRICS.page_hits.inc();
+        } else {
+            crate::metrics::METRICS.page_misses.inc();
+        }
+
+        if !self.frames.contains_key(&id) {
+            self.make_room()?;
+
+            let mut data = vec![0; self.page_size];
+            self.read_page(id, &mut data)?;
+
+            self.frames.insert(
+                id,
+                Frame {
+                    data,
+                    dirty: false,
+                    pin_count: 0,
+                    last_used: 0,
+                },
+            );
+        }
+
+        let frame = self.frames.get_mut(&id).unwrap();
+        if frame.pin_count == 0 {
+            self.lru.remove(&frame.last_used);
+        }
+        frame.pin_count += 1;
+        self.tick += 1;
+        frame.last_used = self.tick;
+
+        Ok(())
+    }
+
+    pub fn unpin(&mut self, id: PageId, dirty: bool) -> io::Result<()> {
+        let frame = self.frames.get_mut(&id).expect("page is not pinned");
+        assert!(frame.pin_count > 0, "page {} is not pinned", id);
+
+        frame.dirty |= dirty;
+        frame.pin_count -= 1;
+        if frame.pin_count == 0 {
+            self.lru.insert(frame.last_used, id);
+        }
+
+        match self.sync_mode {
+            SyncMode::Always if self.frames[&id].dirty => {
+                self.write_back(id)?;
+                self.write_header()?;
+                self.sync()
+            }
+            SyncMode::Periodic(interval) if dirty && self.last_sync.elapsed() >= interval => {
+                self.flush()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn page(&self, id: PageId) -> &[u8] {
+        let frame = &self.frames[&id];
+        assert!(frame.pin_count > 0, "page {} is not pinned", id);
+
+        &frame.data[PAGE_HEADER_SIZE..]
+    }
+
+    pub fn page_mut(&mut self, id: PageId) -> &mut [u8] {
+        let frame = self.frames.get_mut(&id).expect("page is not pinned");
+        assert!(frame.pin_count > 0, "page {} is not pinned", id);
+
+        frame.dirty = true;
+        &mut frame.data[PAGE_HEADER_SIZE..]
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        #[cfg(feature = "metrics")]
+        let _timer = crate::metrics::METRICS.flush_seconds.start();
+
+        let mut dirty: Vec<PageId> = self
+            .frames
+            .iter()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(id, _)| *id)
+            .collect();
+        dirty.sort();
+
+        for id in dirty {
+            self.write_back(id)?;
+        }
+        self.write_header()?;
+
+        match self.sync_mode {
+            SyncMode::Never => Ok(()),
+            SyncMode::Periodic(interval) if self.last_sync.elapsed() < interval => Ok(()),
+            _ => self.sync(),
+        }
+    }
+
+    // Writes back up to `limit` dirty unpinned pages, least recently used
+    // first, without syncing, and returns how many it wrote. Pages written
+    // ahead of time are clean when flush or eviction reaches them.
+    pub fn write_dirty(&mut self, limit: usize) -> io::Result<usize> {
+        let dirty: Vec<PageId> = self
+            .lru
+            .values()
+            .filter(|id| self.frames[id].dirty)
+            .take(limit)
+            .copied()
+            .collect();
+
+        for &id in dirty.iter() {
+            self.write_back(id)?;
+        }
+
+        Ok(dirty.len())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.store.sync()?;
+        self.last_sync = Instant::now();
+
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.header_dirty {
+            write_header(&mut self.store, &self.header, self.page_size)?;
+            self.header_dirty = false;
+        }
+
+        Ok(())
+    }
+
+    // Shrinks the file by dropping free pages from its end and returns how many
+    // were reclaimed. Live pages are never moved, only the tree that owns them
+    // knows where they are referenced.
+    pub fn vacuum(&mut self) -> io::Result<u64> {
+        let mut free: Vec<PageId> = self.free_set()?.iter().copied().collect();
+        free.sort();
+
+        let mut reclaimed = 0;
+        while free.last() == Some(&(self.num_pages - 1)) {
+            let id = free.pop().unwrap();
+            if let Some(frame) = self.frames.remove(&id) {
+                self.lru.remove(&frame.last_used);
+            }
+
+            self.num_pages -= 1;
+            reclaimed += 1;
+        }
+
+        if reclaimed == 0 {
+            return Ok(0);
+        }
+
+        // Relink what is left lowest page first, so allocations fill the front
+        // of the file and keep its tail free for the next vacuum.
+        let mut next: PageId = 0;
+        for id in free.iter().rev() {
+            self.pin(*id)?;
+            self.page_mut(*id)[..8].copy_from_slice(&next.to_le_bytes());
+            self.unpin(*id, true)?;
+            next = *id;
+        }
+        self.header.freelist_head = next;
+        self.header.free_pages = free.len() as u64;
+        self.header_dirty = true;
+        self.free_set = Some(free.into_iter().collect());
+
+        self.flush()?;
+        self.store
+            .set_size(self.num_pages * self.page_size as u64)?;
+        self.sync()?;
+
+        Ok(reclaimed)
+    }
+
+    // Reads every page from disk and returns the ids of those whose checksum
+    // does not match their contents.
+    pub fn verify(&mut self) -> io::Result<Vec<PageId>> {
+        self.flush()?;
+
+        let mut corrupt = Vec::new();
+        let mut data = vec![0; self.page_size];
+
+        for id in 0..self.store.size()? / self.page_size as u64 {
+            self.store.read_at(id * self.page_size as u64, &mut data)?;
+            if !checksum_matches(&data) {
+                corrupt.push(id);
+            }
+        }
+
+        Ok(corrupt)
+    }
+
+    fn read_page(&mut self, id: PageId, data: &mut [u8]) -> io::Result<()> {
+        self.store.read_at(id * self.page_size as u64, data)?;
+
+        if !checksum_matches(data) {
+            return Err(invalid_data(format!("checksum mismatch on page {}", id)));
+        }
+
+        Ok(())
+    }
+
+    fn write_back(&mut self, id: PageId) -> io::Result<()> {
+        #[cfg(feature = "metrics")]
+        crate::metrics::METRICS.page_writes.inc();
+        let frame = self.frames.get_mut(&id).unwrap();
+
+        let crc = crc32(&frame.data[PAGE_HEADER_SIZE..]);
+        frame.data[..PAGE_HEADER_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+        self.store
+            .write_at(id * self.page_size as u64, &frame.data)?;
+        frame.dirty = false;
+
+        Ok(())
+    }
+
+    // Evicts the least recently used unpinned frame if the pool is full.
+    fn make_room(&mut self) -> io::Result<()> {
+        if self.frames.len() < self.capacity {
+            return Ok(());
+        }
+
+        let (_, id) = match self.lru.pop_first() {
+            Some(entry) => entry,
+            None => {
+                return Err(io::Error::other(
+                    "buffer pool exhausted, all pages are pinned",
+                ))
+            }
+        };
+
+        if self.frames[&id].dirty {
+            self.write_back(id)?;
+        }
+        self.frames.remove(&id);
+
+        Ok(())
+    }
+}
+
+fn write_header<S: PageStore>(store: &mut S, header: &Header, page_size: usize) -> io::Result<()> {
+    let mut data = vec![0; page_size];
+    header.encode(&mut data[PAGE_HEADER_SIZE..]);
+
+    let crc = crc32(&data[PAGE_HEADER_SIZE..]);
+    data[..PAGE_HEADER_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+    store.write_at(0, &data)
+}
+
+fn valid_page_size(page_size: usize) -> bool {
+    page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+}
+
+fn checksum_matches(data: &[u8]) -> bool {
+    let stored = u32::from_le_bytes(data[..PAGE_HEADER_SIZE].try_into().unwrap());
+    stored == crc32(&data[PAGE_HEADER_SIZE..])
+}
+
+// Calls write_dirty(pages_per_tick) every `interval` on a background thread,
+// which exits once the last other handle to the pager is dropped or a write
+// fails.
+pub fn spawn_flusher<S: PageStore + Send + 'static>(
+    pager: &Arc<Mutex<Pager<S>>>,
+    interval: Duration,
+    pages_per_tick: usize,
+) -> JoinHandle<io::Result<()>> {
+    let pager = Arc::downgrade(pager);
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        match pager.upgrade() {
+            Some(pager) => {
+                pager.lock().unwrap().write_dirty(pages_per_tick)?;
+            }
+            None => return Ok(()),
+        }
+    })
+}
+
+impl<S: PageStore> Drop for Pager<S> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::crc32;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::pager::{
+        spawn_flusher, PageId, Pager, SyncMode, FORMAT_VERSION, HEADER_PAGE, PAGE_HEADER_SIZE,
+    };
+    use crate::store::MemoryStore;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_pager_eviction() {
+        let dir = TempDir::new("pager");
+        let path = dir.join("pages");
+
+        {
+            let mut pager = Pager::open(&path, 1024, 3, 4).unwrap();
+
+            let pinned = pager.allocate().unwrap();
+            pager.page_mut(pinned)[0] = 42;
+
+            let mut ids = Vec::new();
+            for i in 1..16u8 {
+                let id = pager.allocate().unwrap();
+                pager.page_mut(id)[0] = i;
+                pager.unpin(id, true).unwrap();
+                ids.push(id);
+            }
+
+            // The pinned page must survive all the evictions above.
+            assert_eq!(pager.page(pinned)[0], 42);
+            pager.unpin(pinned, false).unwrap();
+
+            for (i, id) in ids.into_iter().enumerate() {
+                pager.pin(id).unwrap();
+                assert_eq!(pager.page(id)[0], i as u8 + 1);
+                pager.unpin(id, false).unwrap();
+            }
+        }
+
+        let mut pager = Pager::open(&path, 1024, 3, 2).unwrap();
+        assert_eq!(pager.num_pages(), 17);
+
+        pager.pin(1).unwrap();
+        pager.pin(2).unwrap();
+        assert!(pager.pin(3).is_err());
+        assert_eq!(pager.page(1)[0], 42);
+    }
+
+    #[test]
+    fn test_pager_checksums() {
+        let dir = TempDir::new("checksum");
+        let path = dir.join("pages");
+
+        {
+            let mut pager = Pager::open(&path, 512, 3, 4).unwrap();
+            for i in 0..4u8 {
+                let id = pager.allocate().unwrap();
+                pager.page_mut(id).fill(i);
+                pager.unpin(id, true).unwrap();
+            }
+            assert!(pager.verify().unwrap().is_empty());
+        }
+
+        // Flip a byte in the payload of page 2.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[2 * 512 + 10] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut pager = Pager::open(&path, 512, 3, 4).unwrap();
+        assert_eq!(pager.verify().unwrap(), vec![2]);
+        assert!(pager.pin(1).is_ok());
+        assert_eq!(
+            pager.pin(2).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_pager_freelist() {
+        let dir = TempDir::new("freelist");
+        let path = dir.join("pages");
+
+        {
+            let mut pager = Pager::open(&path, 512, 3, 4).unwrap();
+            for _ in 0..6 {
+                let id = pager.allocate().unwrap();
+                pager.page_mut(id).fill(0xff);
+                pager.unpin(id, true).unwrap();
+            }
+
+            pager.free(2).unwrap();
+            pager.free(5).unwrap();
+            pager.free(3).unwrap();
+            assert_eq!(pager.free_pages(), 3);
+
+            let id = pager.allocate().unwrap();
+            assert_eq!(id, 3);
+            assert!(pager.page(id).iter().all(|byte| *byte == 0));
+            pager.unpin(id, true).unwrap();
+        }
+
+        let mut pager = Pager::open(&path, 512, 3, 4).unwrap();
+        assert_eq!(pager.free_pages(), 2);
+
+        let mut reused = Vec::new();
+        for _ in 0..3 {
+            let id = pager.allocate().unwrap();
+            pager.unpin(id, true).unwrap();
+            reused.push(id);
+        }
+        assert_eq!(reused, vec![5, 2, 7]);
+        assert_eq!(pager.num_pages(), 8);
+
+        pager.free(4).unwrap();
+        for id in [4, HEADER_PAGE, 8] {
+            let err = pager.free(id).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "page {}", id);
+        }
+        assert_eq!(pager.free_pages(), 1);
+    }
+
+    #[test]
+    fn test_pager_corrupt_freelist() {
+        let dir = TempDir::new("bad-freelist");
+        let path = dir.join("pages");
+
+        // Rewrites the link in a free page, checksum and all, as a bad write
+        // might have left it.
+        let link = |id: PageId, next: PageId| {
+            let mut pager = Pager::open(&path, 512, 3, 4).unwrap();
+            pager.pin(id).unwrap();
+            pager.page_mut(id)[..8].copy_from_slice(&next.to_le_bytes());
+            pager.unpin(id, true).unwrap();
+            pager.flush().unwrap();
+        };
+
+        let mut pager = Pager::open(&path, 512, 3, 4).unwrap();
+        for _ in 0..4 {
+            let id = pager.allocate().unwrap();
+            pager.unpin(id, true).unwrap();
+        }
+        pager.free(2).unwrap();
+        pager.free(3).unwrap();
+        pager.flush().unwrap();
+        drop(pager);
+
+        // 3 -> 1000, past the end of the file.
+        link(3, 1000);
+        let mut pager = Pager::open(&path, 512, 3, 4).unwrap();
+        assert_eq!(
+            pager.allocate().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            pager.free(1).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        drop(pager);
+
+        // 3 -> 2 -> 3, a cycle that never reaches the end.
+        link(3, 2);
+        link(2, 3);
+        let mut pager = Pager::open(&path, 512, 3, 4).unwrap();
+        assert_eq!(
+            pager.free(1).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            pager.vacuum().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(pager.allocate().unwrap(), 3);
+        pager.unpin(3, true).unwrap();
+        assert_eq!(
+            pager.allocate().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        drop(pager);
+    }
+
+    #[test]
+    fn test_pager_vacuum() {
+        let dir = TempDir::new("vacuum");
+        let path = dir.join("pages");
+
+        let mut pager = Pager::open(&path, 512, 3, 4).unwrap();
+        for _ in 0..10 {
+            let id = pager.allocate().unwrap();
+            pager.unpin(id, true).unwrap();
+        }
+        for id in [9, 3, 10, 8] {
+            pager.free(id).unwrap();
+        }
+
+        assert_eq!(pager.vacuum().unwrap(), 3);
+        assert_eq!(pager.vacuum().unwrap(), 0);
+        assert_eq!(pager.num_pages(), 8);
+        assert_eq!(pager.free_pages(), 1);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 8 * 512);
+        assert!(pager.verify().unwrap().is_empty());
+
+        assert_eq!(pager.allocate().unwrap(), 3);
+        pager.unpin(3, true).unwrap();
+        assert_eq!(pager.allocate().unwrap(), 8);
+        pager.unpin(8, true).unwrap();
+
+        drop(pager);
+    }
+
+    #[test]
+    fn test_pager_sync_modes() {
+        let dir = TempDir::new("sync");
+        let path = dir.join("pages");
+
+        let mut pager = Pager::open(&path, 512, 3, 4).unwrap();
+        assert_eq!(pager.sync_mode(), SyncMode::OnCommit);
+
+        let id = pager.allocate().unwrap();
+        pager.page_mut(id).fill(7);
+        pager.unpin(id, true).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap().len(), 512);
+        pager.flush().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[512 + 10], 7);
+
+        pager.set_sync_mode(SyncMode::Always);
+        pager.pin(id).unwrap();
+        pager.page_mut(id).fill(8);
+        pager.unpin(id, true).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[512 + 10], 8);
+
+        pager.set_sync_mode(SyncMode::Periodic(Duration::ZERO));
+        pager.pin(id).unwrap();
+        pager.page_mut(id).fill(9);
+        pager.unpin(id, true).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[512 + 10], 9);
+
+        drop(pager);
+    }
+
+    #[test]
+    fn test_pager_flusher() {
+        let mut pager = Pager::with_store(MemoryStore::new(), 512, 3, 16).unwrap();
+        for i in 0..10u8 {
+            let id = pager.allocate().unwrap();
+            pager.page_mut(id).fill(i);
+            pager.unpin(id, true).unwrap();
+        }
+        let pinned = pager.allocate().unwrap();
+        pager.page_mut(pinned).fill(99);
+
+        assert_eq!(pager.write_dirty(4).unwrap(), 4);
+        assert_eq!(
+            pager.frames.values().filter(|frame| frame.dirty).count(),
+            6 + 1
+        );
+        assert_eq!(pager.store().bytes()[512 * 2 + 10], 1);
+
+        let pager = Arc::new(Mutex::new(pager));
+        let flusher = spawn_flusher(&pager, Duration::from_millis(1), 2);
+        std::thread::sleep(Duration::from_millis(100));
+        {
+            let pager = pager.lock().unwrap();
+            // Only the pinned page is left dirty.
+            assert_eq!(pager.frames.values().filter(|frame| frame.dirty).count(), 1);
+            assert_eq!(pager.store().bytes()[512 * 10 + 10], 9);
+        }
+
+        drop(pager);
+        flusher.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_pager_header() {
+        let dir = TempDir::new("header");
+        let path = dir.join("pages");
+
+        drop(Pager::open(&path, 512, 3, 4).unwrap());
+        assert_eq!(
+            Pager::open(&path, 512, 3, 4).unwrap().header().version,
+            FORMAT_VERSION
+        );
+        assert!(Pager::open(&path, 1024, 3, 4).is_err());
+        assert!(Pager::open(&path, 512, 5, 4).is_err());
+        assert!(Pager::open(&path, 1000, 3, 4).is_err());
+        assert!(Pager::open(&path, 128 * 1024, 3, 4).is_err());
+        match Pager::with_store(MemoryStore::new(), 512, 3, 0) {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidInput),
+            Ok(_) => panic!("opened a pager with no frames"),
+        }
+        assert!(Pager::upgrade(&path).is_ok());
+
+        // Rewrite the header as version 1, which had no freelist.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[PAGE_HEADER_SIZE + 8] = 1;
+        let crc = crc32(&bytes[PAGE_HEADER_SIZE..512]);
+        bytes[..PAGE_HEADER_SIZE].copy_from_slice(&crc.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+
+        assert!(Pager::open(&path, 512, 3, 4).is_err());
+        Pager::upgrade(&path).unwrap();
+        assert_eq!(
+            Pager::open(&path, 512, 3, 4).unwrap().header().version,
+            FORMAT_VERSION
+        );
+
+        // Pretend the file was written by a newer version.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[PAGE_HEADER_SIZE + 8] = 9;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(Pager::upgrade(&path).is_err());
+
+        // A header claiming a page size that is not allowed.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[PAGE_HEADER_SIZE + 8] = FORMAT_VERSION as u8;
+        bytes[PAGE_HEADER_SIZE + 12..PAGE_HEADER_SIZE + 16].copy_from_slice(&100u32.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        match Pager::open(&path, 512, 3, 4) {
+            Err(err) => assert_eq!(err.to_string(), "file header has invalid page size 100"),
+            Ok(_) => panic!("opened a file with an invalid page size"),
+        }
+
+        std::fs::write(&path, vec![0xaa; 512]).unwrap();
+        match Pager::open(&path, 512, 3, 4) {
+            Err(err) => assert_eq!(err.to_string(), "not a c-tree page file"),
+            Ok(_) => panic!("opened a file without a header"),
+        }
+    }
+}
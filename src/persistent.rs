@@ -0,0 +1,136 @@
+// An immutable tree. Inserting or removing returns a new version and leaves
+// the old one as it was. Versions share every node the change didn't touch,
+// so keeping old ones around, e.g. in an undo stack, costs only the copied
+// path from the root to each change.
+
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+use crate::{BTree, BTreeNode, Iter, Range};
+
+#[derive(Clone)]
+pub struct PersistentBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    root: Arc<BTreeNode<K, V>>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> PersistentBTree<K, V> {
+    pub fn new(node_size: usize) -> PersistentBTree<K, V> {
+        PersistentBTree {
+            root: Arc::new(BTreeNode::new(node_size)),
+        }
+    }
+
+    // Applies `change` to a tree that starts out sharing all of this
+    // version's nodes. The tree copies those it changes.
+    fn edit(&self, change: impl FnOnce(&mut BTree<K, V>)) -> PersistentBTree<K, V> {
+        let mut tree = BTree {
+            root: BTreeNode::clone(&self.root),
+        };
+        change(&mut tree);
+
+        PersistentBTree {
+            root: Arc::new(tree.root),
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) -> PersistentBTree<K, V> {
+        self.edit(|tree| {
+            tree.add(key, value);
+        })
+    }
+
+    pub fn remove(&self, key: &K) -> PersistentBTree<K, V> {
+        if self.get(key).is_none() {
+            return self.clone();
+        }
+
+        self.edit(|tree| {
+            tree.remove(key);
+        })
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.root.get(key)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter::new(&self.root)
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        Range {
+            iter: Iter::seek(&self.root, range.start_bound()),
+            end: range.end_bound().cloned(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.keys.is_empty()
+    }
+
+    // Whether both are the same version, which is cheaper than comparing
+    // their entries.
+    pub fn ptr_eq(&self, other: &PersistentBTree<K, V>) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::persistent::PersistentBTree;
+
+    #[test]
+    fn test_persistent_btree() {
+        let mut versions = vec![PersistentBTree::<u64, u64>::new(4)];
+        for key in 0..500 {
+            let next = versions.last().unwrap().insert(key, key);
+            versions.push(next);
+        }
+        for key in (0..500).step_by(2) {
+            let next = versions.last().unwrap().remove(&key);
+            versions.push(next);
+        }
+
+        // Every version still holds exactly what it held when it was made.
+        for (i, version) in versions.iter().enumerate().step_by(37) {
+            let expected: Vec<u64> = if i <= 500 {
+                (0..i as u64).collect()
+            } else {
+                let removed = (i - 500) as u64;
+                (0..500)
+                    .filter(|key| key % 2 == 1 || *key >= removed * 2)
+                    .collect()
+            };
+            assert_eq!(
+                version.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+                expected
+            );
+        }
+        assert_eq!(versions[500].len(), 500);
+        assert_eq!(versions[750].len(), 250);
+        assert_eq!(versions[300].get(&299), Some(&299));
+        assert_eq!(versions[300].get(&300), None);
+        assert_eq!(versions[750].range(10..20).count(), 5);
+
+        // An edit copies only the path to the changed leaf.
+        let before = &versions[500];
+        let after = before.insert(1000, 1000);
+        let shared = before
+            .root
+            .children
+            .iter()
+            .zip(&after.root.children)
+            .filter(|(old, new)| Arc::ptr_eq(old, new))
+            .count();
+        assert_eq!(shared, before.root.children.len() - 1);
+
+        assert!(before.remove(&1000).ptr_eq(before));
+        assert!(PersistentBTree::<u64, u64>::new(4).is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+// A tree for byte-string keys that stores each node's keys as one shared
+// prefix plus the part after it. Path-like keys such as URLs mostly differ
+// near the end, so nodes shrink a lot, and a descent compares a key with a
+// node's prefix once and then only with the short suffixes. The tree is an
+// AggregateBTree with no summary, keeping its keys in PrefixKeys.
+//
+// A key that doesn't start with a node's prefix sorts before or after all of
+// the node's keys, depending only on how it compares with the prefix.
+
+use std::borrow::Cow;
+
+use crate::aggregate::{AggregateBTree, Keys, Node};
+
+// One node's keys. The prefix is always all that the keys share.
+#[derive(Default)]
+struct PrefixKeys {
+    prefix: Vec<u8>,
+    suffixes: Vec<Vec<u8>>,
+}
+
+fn common_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+impl PrefixKeys {
+    fn search_bytes(&self, key: &[u8]) -> Result<usize, usize> {
+        match key.strip_prefix(self.prefix.as_slice()) {
+            Some(rest) => self
+                .suffixes
+                .binary_search_by(|suffix| suffix.as_slice().cmp(rest)),
+            None if key < self.prefix.as_slice() => Err(0),
+            None => Err(self.suffixes.len()),
+        }
+    }
+
+    // Lengthens the prefix to all the keys still share. The keys are sorted,
+    // so that is what the first and last share.
+    fn compact(&mut self) {
+        let (Some(first), Some(last)) = (self.suffixes.first(), self.suffixes.last()) else {
+            self.prefix.clear();
+            return;
+        };
+
+        let extra = common_len(first, last);
+        if extra > 0 {
+            self.prefix.extend_from_slice(&first[..extra]);
+            for suffix in &mut self.suffixes {
+                suffix.drain(..extra);
+            }
+        }
+    }
+}
+
+impl Keys<Vec<u8>> for PrefixKeys {
+    fn len(&self) -> usize {
+        self.suffixes.len()
+    }
+
+    fn key(&self, i: usize) -> Cow<'_, Vec<u8>> {
+        Cow::Owned([self.prefix.as_slice(), &self.suffixes[i]].concat())
+    }
+
+    fn search(&self, key: &Vec<u8>) -> Result<usize, usize> {
+        self.search_bytes(key)
+    }
+
+    // Shortens the prefix if the key doesn't share all of it.
+    fn insert(&mut self, i: usize, key: Vec<u8>) {
+        if self.suffixes.is_empty() {
+            self.prefix = key;
+            self.suffixes.push(Vec::new());
+            return;
+        }
+
+        let common = common_len(&self.prefix, &key);
+        if common < self.prefix.len() {
+            let rest = self.prefix.split_off(common);
+            for suffix in &mut self.suffixes {
+                suffix.splice(0..0, rest.iter().copied());
+            }
+        }
+        self.suffixes.insert(i, key[common..].to_vec());
+    }
+
+    fn remove(&mut self, i: usize) -> Vec<u8> {
+        let suffix = self.suffixes.remove(i);
+        let key = [self.prefix.as_slice(), &suffix].concat();
+        self.compact();
+        key
+    }
+
+    fn split_off(&mut self, at: usize) -> PrefixKeys {
+        let mut right = PrefixKeys {
+            prefix: self.prefix.clone(),
+            suffixes: self.suffixes.split_off(at),
+        };
+        self.compact();
+        right.compact();
+        right
+    }
+
+    fn append(&mut self, other: PrefixKeys) {
+        for suffix in other.suffixes {
+            self.insert(self.len(), [other.prefix.as_slice(), &suffix].concat());
+        }
+    }
+}
+
+fn collect<'a, V: Ord + Clone + std::fmt::Debug>(
+    node: &'a Node<Vec<u8>, V, (), PrefixKeys>,
+    out: &mut Vec<(Vec<u8>, &'a V)>,
+) {
+    for i in 0..=node.keys.len() {
+        if let Some(child) = node.children.get(i) {
+            collect(child, out);
+        }
+        if i < node.keys.len() {
+            out.push((node.keys.key(i).into_owned(), &node.values[i]));
+        }
+    }
+}
+
+fn stored_bytes<V>(node: &Node<Vec<u8>, V, (), PrefixKeys>) -> usize {
+    let keys = &node.keys;
+    let own = keys.prefix.len() + keys.suffixes.iter().map(Vec::len).sum::<usize>();
+    own + node.children.iter().map(stored_bytes).sum::<usize>()
+}
+
+pub struct PrefixBTree<V: Ord + Clone + std::fmt::Debug> {
+    tree: AggregateBTree<Vec<u8>, V, (), PrefixKeys>,
+}
+
+impl<V: Ord + Clone + std::fmt::Debug> PrefixBTree<V> {
+    pub fn new(node_size: usize) -> PrefixBTree<V> {
+        PrefixBTree {
+            tree: AggregateBTree::new(node_size),
+        }
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: V) -> Option<V> {
+        self.tree.insert(key, value)
+    }
+
+    // Descends by the bytes, so a lookup doesn't need an owned key.
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        let mut node = self.tree.root();
+        loop {
+            match node.keys.search_bytes(key) {
+                Ok(i) => return Some(&node.values[i]),
+                Err(i) => node = node.children.get(i)?,
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        self.tree.remove(&key.to_vec())
+    }
+
+    // All entries in key order, with the keys put back together.
+    pub fn entries(&self) -> Vec<(Vec<u8>, &V)> {
+        let mut out = Vec::with_capacity(self.len());
+        collect(self.tree.root(), &mut out);
+        out
+    }
+
+    // Bytes of key data held, prefixes included.
+    pub fn stored_bytes(&self) -> usize {
+        stored_bytes(self.tree.root())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::prefix::PrefixBTree;
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_prefix_btree() {
+        for node_size in [2, 3, 8] {
+            let mut tree = PrefixBTree::<u64>::new(node_size);
+            let mut expected = BTreeMap::new();
+            let mut rng = Rng::new();
+
+            for step in 0..4000 {
+                let state = rng.next_u64();
+                let key = match state % 4 {
+                    0 => format!(
+                        "https://example.com/users/{}/posts/{}",
+                        state % 7,
+                        (state >> 8) % 50
+                    ),
+                    1 => format!("https://example.com/users/{}", (state >> 8) % 30),
+                    2 => format!("https://example.org/{}", (state >> 8) % 20),
+                    _ => format!("/var/log/{}", (state >> 8) % 20),
+                }
+                .into_bytes();
+
+                if state.is_multiple_of(5) {
+                    assert_eq!(tree.remove(&key), expected.remove(&key));
+                } else {
+                    assert_eq!(
+                        tree.insert(key.clone(), step),
+                        expected.insert(key.clone(), step)
+                    );
+                }
+                assert_eq!(tree.get(&key), expected.get(&key));
+            }
+
+            let entries: Vec<(Vec<u8>, &u64)> =
+                expected.iter().map(|(k, v)| (k.clone(), v)).collect();
+            assert_eq!(tree.entries(), entries);
+            assert_eq!(tree.len(), expected.len());
+            assert_eq!(tree.get(b"https://example"), None);
+            assert_eq!(tree.get(b"zzz"), None);
+
+            // Larger nodes share their prefix among more keys.
+            let key_bytes: usize = expected.keys().map(Vec::len).sum();
+            let ratio = if node_size >= 8 { 3 } else { 1 };
+            assert!(
+                tree.stored_bytes() * ratio < key_bytes,
+                "{} of {} bytes",
+                tree.stored_bytes(),
+                key_bytes
+            );
+        }
+    }
+}
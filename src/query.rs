@@ -0,0 +1,131 @@
+// Queries answered by walking part of the tree rather than collecting it.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ops::RangeBounds;
+
+use crate::{BTree, BTreeNode};
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTreeNode<K, V> {
+    // The entry with the largest key at or below `key`.
+    fn floor(&self, key: &K) -> Option<(&K, &V)> {
+        let i = self.keys.partition_point(|k| k <= key);
+        let below = self.children.get(i).and_then(|child| child.floor(key));
+        below.or_else(|| Some((self.keys.get(i.checked_sub(1)?)?, &self.values[i - 1])))
+    }
+
+    // The entry with the smallest key above `key`.
+    fn above(&self, key: &K) -> Option<(&K, &V)> {
+        let i = self.keys.partition_point(|k| k <= key);
+        let below = self.children.get(i).and_then(|child| child.above(key));
+        below.or_else(|| Some((self.keys.get(i)?, &self.values[i])))
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    // The `k` entries in `range` with the highest scores, best first. Equal
+    // scores keep key order. Only the best `k` so far are held during the
+    // scan.
+    pub fn top_k_by<R: RangeBounds<K>, S: Ord>(
+        &self,
+        range: R,
+        k: usize,
+        score: impl Fn(&K, &V) -> S,
+    ) -> Vec<(&K, &V)> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        // A min-heap on the score, so the worst kept entry is on top. The
+        // position breaks ties in favour of the earlier key.
+        let mut best = BinaryHeap::with_capacity(k + 1);
+        for (position, (key, value)) in self.range(range).enumerate() {
+            best.push(Reverse((score(key, value), Reverse(position), key, value)));
+            if best.len() > k {
+                best.pop();
+            }
+        }
+
+        best.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((_, _, key, value))| (key, value))
+            .collect()
+    }
+
+    // The entry for `key`, or failing that the one just before it, or
+    // failing that the one just after. Snaps a timestamp to the latest entry
+    // at or before it.
+    pub fn nearest(&self, key: &K) -> Option<(&K, &V)> {
+        self.root.floor(key).or_else(|| self.root.above(key))
+    }
+
+    // Whichever of the entries just before and just after `key` is closer
+    // under `distance`, the one before on a tie. An entry for `key` itself
+    // counts as the one before.
+    pub fn nearest_by<D: Ord>(&self, key: &K, distance: impl Fn(&K, &K) -> D) -> Option<(&K, &V)> {
+        match (self.root.floor(key), self.root.above(key)) {
+            (Some(before), Some(after)) if distance(after.0, key) < distance(before.0, key) => {
+                Some(after)
+            }
+            (before, after) => before.or(after),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::Rng;
+    use crate::BTree;
+
+    #[test]
+    fn test_top_k_by() {
+        let mut tree = BTree::<u64, u64>::new(3);
+        let mut rng = Rng::new();
+        for key in 0..500 {
+            let state = rng.next_u64();
+            tree.add(key, state % 50);
+        }
+
+        for k in [0, 1, 7, 1000] {
+            let mut want: Vec<(&u64, &u64)> = tree.range(100..400).collect();
+            want.sort_by_key(|(_, value)| std::cmp::Reverse(**value));
+            want.truncate(k);
+            assert_eq!(tree.top_k_by(100..400, k, |_, value| *value), want);
+        }
+
+        let smallest = tree.top_k_by(.., 3, |key, _| std::cmp::Reverse(*key));
+        assert_eq!(
+            smallest,
+            [
+                (&0, tree.get(&0).unwrap()),
+                (&1, tree.get(&1).unwrap()),
+                (&2, tree.get(&2).unwrap())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nearest() {
+        let empty = BTree::<u64, u64>::new(3);
+        assert_eq!(empty.nearest(&5), None);
+        assert_eq!(empty.nearest_by(&5, |a, b| a.abs_diff(*b)), None);
+
+        let tree = BTree::from_sorted(3, (1..200u64).map(|i| (i * 10, i)).collect());
+        let distance = |a: &u64, b: &u64| a.abs_diff(*b);
+        for key in 0..2100u64 {
+            let keys: Vec<u64> = tree.iter().map(|(k, _)| *k).collect();
+            let before = keys.iter().rev().find(|k| **k <= key);
+            let after = keys.iter().find(|k| **k > key);
+            assert_eq!(tree.nearest(&key).map(|(k, _)| k), before.or(after));
+
+            let want = match (before, after) {
+                (Some(b), Some(a)) if a - key < key - b => Some(a),
+                (b, a) => b.or(a),
+            };
+            assert_eq!(tree.nearest_by(&key, distance).map(|(k, _)| k), want);
+        }
+        assert_eq!(tree.nearest_by(&15, distance), Some((&10, &1)));
+        assert_eq!(tree.nearest_by(&16, distance), Some((&20, &2)));
+        assert_eq!(tree.nearest(&19), Some((&10, &1)));
+    }
+}
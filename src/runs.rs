@@ -0,0 +1,113 @@
+// Merging sorted runs, as produced by external sorting or found in the levels
+// of a compaction. A heap holds the next key of every run, so the merge reads
+// each run once, front to back, and holds one entry per run at a time.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::BTree;
+
+// Yields the entries of every run in key order. Each run must be sorted with
+// no key twice. Runs are given oldest first: if several hold a key, the
+// entry from the last of them wins.
+pub struct MergeRuns<K, V, I> {
+    runs: Vec<I>,
+    // The value of each run's entry in the heap.
+    heads: Vec<Option<V>>,
+    // Each run's next key. Among equal keys the newest run comes out first.
+    heap: BinaryHeap<Reverse<(K, Reverse<usize>)>>,
+}
+
+impl<K: Ord, V, I: Iterator<Item = (K, V)>> MergeRuns<K, V, I> {
+    pub fn new(runs: Vec<I>) -> MergeRuns<K, V, I> {
+        let mut merge = MergeRuns {
+            heads: runs.iter().map(|_| None).collect(),
+            heap: BinaryHeap::with_capacity(runs.len()),
+            runs,
+        };
+        for run in 0..merge.runs.len() {
+            merge.advance(run);
+        }
+        merge
+    }
+
+    fn advance(&mut self, run: usize) {
+        if let Some((key, value)) = self.runs[run].next() {
+            self.heads[run] = Some(value);
+            self.heap.push(Reverse((key, Reverse(run))));
+        }
+    }
+}
+
+impl<K: Ord, V, I: Iterator<Item = (K, V)>> Iterator for MergeRuns<K, V, I> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        let Reverse((key, Reverse(run))) = self.heap.pop()?;
+        let value = self.heads[run].take().unwrap();
+        self.advance(run);
+
+        // Older entries for the same key are shadowed.
+        while let Some(Reverse((next, _))) = self.heap.peek() {
+            if *next != key {
+                break;
+            }
+            let Reverse((_, Reverse(older))) = self.heap.pop().unwrap();
+            self.heads[older] = None;
+            self.advance(older);
+        }
+
+        Some((key, value))
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    // Builds a tree from the merged runs, bottom-up like from_sorted.
+    pub fn merge_sorted_runs<I: Iterator<Item = (K, V)>>(
+        node_size: usize,
+        runs: Vec<I>,
+    ) -> BTree<K, V> {
+        BTree::from_sorted(node_size, MergeRuns::new(runs).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::runs::MergeRuns;
+    use crate::testutil::Rng;
+    use crate::BTree;
+
+    #[test]
+    fn test_merge_sorted_runs() {
+        let mut expected = BTreeMap::new();
+        let mut runs = Vec::new();
+        let mut rng = Rng::new();
+
+        for run in 0..6u64 {
+            let mut entries = BTreeMap::new();
+            for _ in 0..200 {
+                let state = rng.next_u64();
+                entries.insert(state % 1000, run);
+            }
+            expected.extend(entries.clone());
+            runs.push(entries.into_iter());
+        }
+        runs.push(BTreeMap::new().into_iter());
+
+        let tree = BTree::merge_sorted_runs(4, runs);
+        assert!(tree.iter().eq(expected.iter()));
+        assert_eq!(
+            MergeRuns::<u64, u64, std::vec::IntoIter<(u64, u64)>>::new(Vec::new()).next(),
+            None
+        );
+
+        let merged: Vec<_> = MergeRuns::new(vec![
+            vec![(1, "old"), (3, "old")].into_iter(),
+            vec![(1, "new"), (2, "new")].into_iter(),
+        ])
+        .collect();
+        assert_eq!(merged, [(1, "new"), (2, "new"), (3, "old")]);
+    }
+}
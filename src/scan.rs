@@ -0,0 +1,125 @@
+// Prefix scans over byte and string keys. The keys starting with a prefix are
+// exactly those from the prefix up to, but not including, its successor: the
+// smallest key above every key that starts with it. That turns the scan into
+// an ordinary bounded range.
+
+use std::ops::Bound;
+
+use crate::{BTree, Range};
+
+pub trait PrefixKey: Sized {
+    // The successor described above, or None if every key from the prefix on
+    // starts with it.
+    fn prefix_end(prefix: &Self) -> Option<Self>;
+}
+
+impl PrefixKey for Vec<u8> {
+    // Drops trailing 0xff bytes and increments the last byte left.
+    fn prefix_end(prefix: &Vec<u8>) -> Option<Vec<u8>> {
+        let mut end = prefix.clone();
+        while let Some(last) = end.pop() {
+            if last < 0xff {
+                end.push(last + 1);
+                return Some(end);
+            }
+        }
+        None
+    }
+}
+
+impl PrefixKey for String {
+    // Strings order by code point, so this works on the last char instead of
+    // the last byte, which keeps the end valid UTF-8.
+    fn prefix_end(prefix: &String) -> Option<String> {
+        let mut end = prefix.clone();
+        while let Some(last) = end.pop() {
+            let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+            if let Some(next) = next {
+                end.push(next);
+                return Some(end);
+            }
+        }
+        None
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug + PrefixKey, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    // Entries whose keys start with `prefix`, in key order.
+    pub fn scan_prefix(&self, prefix: &K) -> Range<'_, K, V> {
+        let end = match K::prefix_end(prefix) {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        };
+        self.range((Bound::Included(prefix.clone()), end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scan::PrefixKey;
+    use crate::BTree;
+
+    #[test]
+    fn test_scan_prefix() {
+        let mut tree = BTree::<String, u64>::new(3);
+        let keys = [
+            "user:4",
+            "user:42",
+            "user:42:",
+            "user:42:a",
+            "user:42:zz",
+            "user:42;",
+            "user:43:a",
+            "user:\u{d7ff}",
+            "user:\u{e000}",
+            "user:\u{10ffff}",
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            tree.add(key.to_string(), i as u64);
+        }
+
+        let scan = |prefix: &str| -> Vec<&str> {
+            tree.scan_prefix(&prefix.to_string())
+                .map(|(key, _)| key.as_str())
+                .collect()
+        };
+        assert_eq!(scan("user:42:"), ["user:42:", "user:42:a", "user:42:zz"]);
+        assert_eq!(
+            scan("user:42"),
+            ["user:42", "user:42:", "user:42:a", "user:42:zz", "user:42;"]
+        );
+        assert_eq!(scan("user:\u{d7ff}"), ["user:\u{d7ff}"]);
+        assert_eq!(scan("user:\u{10ffff}"), ["user:\u{10ffff}"]);
+        assert_eq!(scan("").len(), keys.len());
+        assert!(scan("nope").is_empty());
+        assert_eq!(
+            String::prefix_end(&"a\u{10ffff}".to_string()),
+            Some("b".to_string())
+        );
+
+        let mut bytes = BTree::<Vec<u8>, u64>::new(3);
+        for key in [
+            &b"\x01\xff"[..],
+            b"\x01\xff\x00",
+            b"\x01\xff\xff",
+            b"\x02",
+            b"\xff\xff",
+        ] {
+            bytes.add(key.to_vec(), 0);
+        }
+        let scanned: Vec<&Vec<u8>> = bytes
+            .scan_prefix(&b"\x01\xff".to_vec())
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(
+            scanned,
+            [&b"\x01\xff"[..], b"\x01\xff\x00", b"\x01\xff\xff"]
+        );
+        assert_eq!(bytes.scan_prefix(&b"\xff".to_vec()).count(), 1);
+        assert_eq!(
+            Vec::prefix_end(&b"\x01\xff".to_vec()),
+            Some(b"\x02".to_vec())
+        );
+        assert_eq!(Vec::prefix_end(&b"\xff\xff".to_vec()), None);
+    }
+}
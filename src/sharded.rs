@@ -0,0 +1,336 @@
+// Splits the key space into ranges, each held by an independent BTree behind
+// its own lock, so writers to different ranges never wait for each other.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
+
+use crate::batch::WriteBatch;
+use crate::BTree;
+
+pub struct ShardedBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    // Shard `i + 1` holds the keys from `boundaries[i]` up to the next boundary.
+    boundaries: Vec<K>,
+    shards: Vec<RwLock<Shard<K, V>>>,
+}
+
+// The most keys a rebuild remembers writes to. A shard written to more
+// widely than that while it is rebuilt keeps its old tree.
+const MAX_PENDING: usize = 1 << 16;
+
+enum Rebuild<K, V> {
+    Idle,
+    // The last write to each key since the rebuild began, to replay onto the
+    // new tree.
+    Running(BTreeMap<K, Option<V>>),
+    // Too many keys were written, so the new tree will be thrown away.
+    Abandoned,
+}
+
+struct Shard<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    tree: BTree<K, V>,
+    rebuild: Rebuild<K, V>,
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Shard<K, V> {
+    fn record(&mut self, key: &K, write: Option<&V>) {
+        let Rebuild::Running(pending) = &mut self.rebuild else {
+            return;
+        };
+        if pending.len() >= MAX_PENDING && !pending.contains_key(key) {
+            self.rebuild = Rebuild::Abandoned;
+            return;
+        }
+        pending.insert(key.clone(), write.cloned());
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.record(&key, Some(&value));
+        self.tree.add(key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.record(key, None);
+        self.tree.remove(key)
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> ShardedBTree<K, V> {
+    // Creates `boundaries.len() + 1` shards split at the given keys, which must
+    // be strictly ascending.
+    pub fn new(node_size: usize, boundaries: Vec<K>) -> ShardedBTree<K, V> {
+        assert!(
+            boundaries.windows(2).all(|pair| pair[0] < pair[1]),
+            "shard boundaries must be strictly ascending"
+        );
+
+        let shards = (0..=boundaries.len())
+            .map(|_| {
+                RwLock::new(Shard {
+                    tree: BTree::new(node_size),
+                    rebuild: Rebuild::Idle,
+                })
+            })
+            .collect();
+
+        ShardedBTree { boundaries, shards }
+    }
+
+    fn shard(&self, key: &K) -> usize {
+        self.boundaries.partition_point(|boundary| boundary <= key)
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shards[self.shard(&key)]
+            .write()
+            .unwrap()
+            .insert(key, value)
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.shards[self.shard(key)]
+            .read()
+            .unwrap()
+            .tree
+            .get(key)
+            .cloned()
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.shards[self.shard(key)].write().unwrap().remove(key)
+    }
+
+    // Applies the batch with every shard it touches write-locked, so readers
+    // see either none or all of it.
+    pub fn write(&self, batch: WriteBatch<K, V>) {
+        let touched: BTreeSet<usize> = batch.keys().map(|key| self.shard(key)).collect();
+        let mut shards: BTreeMap<usize, _> = touched
+            .into_iter()
+            .map(|shard| (shard, self.shards[shard].write().unwrap()))
+            .collect();
+
+        for (key, write) in batch {
+            let shard = shards.get_mut(&self.shard(&key)).unwrap();
+            match write {
+                Some(value) => {
+                    shard.insert(key, value);
+                }
+                None => {
+                    shard.remove(&key);
+                }
+            }
+        }
+    }
+
+    // Copies the entries in `range` out. Every shard the range touches is
+    // locked, in key order, before any of them is read, so the result is
+    // consistent across shards.
+    pub fn range_snapshot<R: RangeBounds<K>>(&self, range: R) -> Vec<(K, V)> {
+        let first = match range.start_bound() {
+            Bound::Included(key) | Bound::Excluded(key) => self.shard(key),
+            Bound::Unbounded => 0,
+        };
+        let last = match range.end_bound() {
+            Bound::Included(key) | Bound::Excluded(key) => self.shard(key),
+            Bound::Unbounded => self.shards.len() - 1,
+        };
+        if first > last {
+            return Vec::new();
+        }
+
+        let shards: Vec<_> = self.shards[first..=last]
+            .iter()
+            .map(|shard| shard.read().unwrap())
+            .collect();
+
+        // Shards cover ascending key ranges, so concatenating them keeps the
+        // entries in order.
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        shards
+            .iter()
+            .flat_map(|shard| shard.tree.range(bounds.clone()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap().tree.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Replaces every shard's tree with a freshly packed copy, which keeps
+    // lookups short after many removals have left nodes half empty. Shards
+    // are rebuilt in parallel from snapshots while they keep serving reads
+    // and writes. The writes made meanwhile are replayed onto the new tree
+    // under the shard's write lock, just before it takes the old one's place.
+    // The rebuild runs on its own thread, which the handle joins.
+    pub fn rebuild_in_background(self: &Arc<Self>) -> JoinHandle<()>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let tree = Arc::clone(self);
+        std::thread::spawn(move || tree.rebuild())
+    }
+
+    // Rebuilds every shard, in parallel, and returns once they are done.
+    fn rebuild(&self)
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+    {
+        std::thread::scope(|scope| {
+            for shard in &self.shards {
+                scope.spawn(move || {
+                    let (node_size, snapshot) = {
+                        let mut shard = shard.write().unwrap();
+                        if !matches!(shard.rebuild, Rebuild::Idle) {
+                            // Another rebuild of this shard is running.
+                            return;
+                        }
+                        shard.rebuild = Rebuild::Running(BTreeMap::new());
+                        (shard.tree.root.node_size, shard.tree.snapshot_iter())
+                    };
+
+                    let mut rebuilt = BTree::from_sorted(node_size, snapshot.collect());
+
+                    let mut shard = shard.write().unwrap();
+                    let Rebuild::Running(pending) =
+                        std::mem::replace(&mut shard.rebuild, Rebuild::Idle)
+                    else {
+                        return;
+                    };
+                    for (key, write) in pending {
+                        match write {
+                            Some(value) => {
+                                rebuilt.add(key, value);
+                            }
+                            None => {
+                                rebuilt.remove(&key);
+                            }
+                        }
+                    }
+                    shard.tree = rebuilt;
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use crate::sharded::{Rebuild, ShardedBTree, MAX_PENDING};
+    use crate::BTreeNode;
+
+    fn count_nodes(node: &BTreeNode<u64, u64>) -> usize {
+        1 + node
+            .children
+            .iter()
+            .map(|child| count_nodes(child))
+            .sum::<usize>()
+    }
+
+    #[test]
+    fn test_rebuild_in_background() {
+        let tree = Arc::new(ShardedBTree::<u64, u64>::new(8, vec![1000, 2000]));
+        for key in 0..3000 {
+            tree.insert(key, key);
+        }
+        for key in (0..3000).filter(|key| key % 5 != 0) {
+            tree.remove(&key);
+        }
+        let nodes = |tree: &ShardedBTree<u64, u64>| -> usize {
+            tree.shards
+                .iter()
+                .map(|shard| count_nodes(&shard.read().unwrap().tree.root))
+                .sum()
+        };
+        let before = nodes(&tree);
+        tree.rebuild_in_background().join().unwrap();
+        assert!(
+            nodes(&tree) < before,
+            "{} nodes, {} before",
+            nodes(&tree),
+            before
+        );
+
+        // The caller goes on writing while the rebuild runs, and the writes
+        // survive it.
+        let mut expected: BTreeMap<u64, u64> = tree.range_snapshot(..).into_iter().collect();
+        let rebuild = tree.rebuild_in_background();
+        let mut key = 3000;
+        while key < 3500 || !rebuild.is_finished() {
+            tree.insert(key, key);
+            expected.insert(key, key);
+            assert_eq!(tree.remove(&(key - 3000)), expected.remove(&(key - 3000)));
+            key += 1;
+        }
+        rebuild.join().unwrap();
+        assert_eq!(
+            tree.range_snapshot(..),
+            expected.into_iter().collect::<Vec<_>>()
+        );
+
+        // Writes to too many keys during a rebuild give it up, rather than
+        // being remembered without limit.
+        let mut shard = tree.shards[0].write().unwrap();
+        shard.rebuild = Rebuild::Running(Default::default());
+        for key in 0..MAX_PENDING as u64 {
+            shard.insert(key % 1000, key);
+            shard.insert(key, key);
+        }
+        assert!(
+            matches!(&shard.rebuild, Rebuild::Running(pending) if pending.len() == MAX_PENDING)
+        );
+        shard.remove(&(MAX_PENDING as u64));
+        assert!(matches!(shard.rebuild, Rebuild::Abandoned));
+        shard.rebuild = Rebuild::Idle;
+    }
+
+    #[test]
+    fn test_sharded_btree() {
+        let tree = ShardedBTree::<u64, u64>::new(8, vec![1000, 2000, 3000]);
+
+        std::thread::scope(|scope| {
+            for shard in 0..4u64 {
+                let tree = &tree;
+                scope.spawn(move || {
+                    for key in (shard * 1000)..(shard * 1000 + 1000) {
+                        assert_eq!(tree.insert(key, key), None);
+                    }
+                    for key in (shard * 1000..shard * 1000 + 1000).step_by(2) {
+                        assert_eq!(tree.remove(&key), Some(key));
+                    }
+                });
+            }
+        });
+
+        assert_eq!(tree.len(), 2000);
+        assert_eq!(tree.insert(2001, 7), Some(2001));
+        assert_eq!(tree.get(&2001), Some(7));
+        assert_eq!(tree.get(&2002), None);
+
+        // A scan across shard boundaries comes back in key order.
+        let keys: Vec<u64> = tree
+            .range_snapshot(995..=2005)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(
+            keys,
+            (995..=2005).filter(|key| key % 2 == 1).collect::<Vec<_>>()
+        );
+        assert_eq!(tree.range_snapshot(..).len(), 2000);
+        assert!(tree.range_snapshot(1500..1500).is_empty());
+    }
+}
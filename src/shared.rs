@@ -0,0 +1,720 @@
+// A B-tree that can be shared between threads. Every node has its own latch
+// and operations couple latches on the way down: a child is latched before its
+// parent is released. Writers keep their ancestors latched only while the node
+// below might still split or underflow, so writers to disjoint key ranges only
+// meet briefly near the root.
+
+use std::ops::{Bound, RangeBounds};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use std::time::{Duration, Instant};
+
+// Writers that wait this long for a single latch count as stalled.
+const STALL_THRESHOLD: Duration = Duration::from_millis(1);
+
+struct Node<K, V> {
+    keys: Vec<K>,
+    values: Vec<V>,
+    children: Vec<NodePtr<K, V>>,
+}
+
+type Latch<K, V> = RwLock<Node<K, V>>;
+type Subscriber<K, V> = ((Bound<K>, Bound<K>), Sender<Event<K, V>>);
+
+// Latch contention since the tree was created. Every latch, including the
+// root pointer, counts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub latches: u64,
+    // Latches that were held by someone else when requested.
+    pub contended: u64,
+    // Total time spent waiting for contended latches.
+    pub wait: Duration,
+    // Write latches that took longer than STALL_THRESHOLD to get.
+    pub writer_stalls: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    latches: AtomicU64,
+    contended: AtomicU64,
+    wait_nanos: AtomicU64,
+    writer_stalls: AtomicU64,
+    // Latch requests blocked right now.
+    waiting: AtomicU64,
+}
+
+// Returned by compare_and_swap when the key did not hold the expected value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CasError<V> {
+    pub current: Option<V>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event<K, V> {
+    Insert { key: K, value: V },
+    Update { key: K, old: V, new: V },
+    Remove { key: K, value: V },
+}
+type NodePtr<K, V> = NonNull<Latch<K, V>>;
+
+pub struct SharedBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    node_size: usize,
+    // Written by operations that may replace the root node and read by the
+    // rest just long enough to latch it.
+    root: RwLock<NodePtr<K, V>>,
+    len: AtomicUsize,
+    subscribers: Mutex<Vec<Subscriber<K, V>>>,
+    counters: Counters,
+}
+
+// Nodes are only reached through their latches, so the tree is as thread-safe
+// as its keys and values.
+unsafe impl<K, V> Send for SharedBTree<K, V>
+where
+    K: Ord + Clone + std::fmt::Debug + Send + Sync,
+    V: Ord + Clone + std::fmt::Debug + Send + Sync,
+{
+}
+
+unsafe impl<K, V> Sync for SharedBTree<K, V>
+where
+    K: Ord + Clone + std::fmt::Debug + Send + Sync,
+    V: Ord + Clone + std::fmt::Debug + Send + Sync,
+{
+}
+
+fn allocate<K, V>(node: Node<K, V>) -> NodePtr<K, V> {
+    NonNull::from(Box::leak(Box::new(RwLock::new(node))))
+}
+
+// Safety: the caller must hold the write latches of `node` and its parent and
+// have dropped its own guard of `node`, so no other thread can reach it.
+unsafe fn free<K, V>(node: NodePtr<K, V>) {
+    drop(Box::from_raw(node.as_ptr()));
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> SharedBTree<K, V> {
+    pub fn new(node_size: usize) -> SharedBTree<K, V> {
+        assert!(node_size >= 2, "node size must be at least 2");
+
+        SharedBTree {
+            node_size,
+            root: RwLock::new(allocate(Node {
+                keys: Vec::new(),
+                values: Vec::new(),
+                children: Vec::new(),
+            })),
+            len: AtomicUsize::new(0),
+            subscribers: Mutex::new(Vec::new()),
+            counters: Counters::default(),
+        }
+    }
+
+    // Nodes are freed only while their parent is write-latched, and every
+    // caller holds the latch of the parent (or of the root pointer) of the node
+    // it is about to latch, so the node outlives the returned reference.
+    fn latch(&self, node: NodePtr<K, V>) -> &Latch<K, V> {
+        unsafe { node.as_ref() }
+    }
+
+    fn read<'a, T>(&self, lock: &'a RwLock<T>) -> RwLockReadGuard<'a, T> {
+        self.counters.latches.fetch_add(1, Ordering::Relaxed);
+        match lock.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                let start = Instant::now();
+                self.counters.waiting.fetch_add(1, Ordering::Relaxed);
+                let guard = lock.read().unwrap();
+                self.counters.waiting.fetch_sub(1, Ordering::Relaxed);
+                self.waited(start.elapsed(), false);
+                guard
+            }
+            Err(TryLockError::Poisoned(error)) => panic!("{}", error),
+        }
+    }
+
+    fn write<'a, T>(&self, lock: &'a RwLock<T>) -> RwLockWriteGuard<'a, T> {
+        self.counters.latches.fetch_add(1, Ordering::Relaxed);
+        match lock.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                let start = Instant::now();
+                self.counters.waiting.fetch_add(1, Ordering::Relaxed);
+                let guard = lock.write().unwrap();
+                self.counters.waiting.fetch_sub(1, Ordering::Relaxed);
+                self.waited(start.elapsed(), true);
+                guard
+            }
+            Err(TryLockError::Poisoned(error)) => panic!("{}", error),
+        }
+    }
+
+    fn waited(&self, wait: Duration, writer: bool) {
+        let counters = &self.counters;
+        counters.contended.fetch_add(1, Ordering::Relaxed);
+        counters
+            .wait_nanos
+            .fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+        if writer && wait > STALL_THRESHOLD {
+            counters.writer_stalls.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let counters = &self.counters;
+        Metrics {
+            latches: counters.latches.load(Ordering::Relaxed),
+            contended: counters.contended.load(Ordering::Relaxed),
+            wait: Duration::from_nanos(counters.wait_nanos.load(Ordering::Relaxed)),
+            writer_stalls: counters.writer_stalls.load(Ordering::Relaxed),
+        }
+    }
+
+    fn min_keys(&self) -> usize {
+        self.node_size / 2
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let Ok(old) = self.insert_if(key, value, |_| true) else {
+            unreachable!()
+        };
+        old
+    }
+
+    // Inserts the entry if `check` accepts the current value of the key,
+    // returning the old value, or the current one if `check` refused it.
+    // `check` runs with the key's node latched, so nothing changes in between.
+    fn insert_if(
+        &self,
+        key: K,
+        value: V,
+        check: impl Fn(Option<&V>) -> bool,
+    ) -> Result<Option<V>, Option<V>> {
+        let mut root = Some(self.write(&self.root));
+        let mut node = self.write(self.latch(**root.as_ref().unwrap()));
+        // Latched ancestors of `node`, with the index of the child taken.
+        let mut path: Vec<(RwLockWriteGuard<'_, Node<K, V>>, usize)> = Vec::new();
+
+        loop {
+            let index = match node.keys.binary_search(&key) {
+                Ok(index) if !check(Some(&node.values[index])) => {
+                    return Err(Some(node.values[index].clone()))
+                }
+                Ok(index) => {
+                    let old = std::mem::replace(&mut node.values[index], value);
+                    self.publish(&key, || Event::Update {
+                        key: key.clone(),
+                        old: old.clone(),
+                        new: node.values[index].clone(),
+                    });
+                    return Ok(Some(old));
+                }
+                Err(index) => index,
+            };
+            if node.children.is_empty() {
+                if !check(None) {
+                    return Err(None);
+                }
+                self.publish(&key, || Event::Insert {
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+                node.keys.insert(index, key);
+                node.values.insert(index, value);
+                break;
+            }
+
+            let child = self.write(self.latch(node.children[index]));
+            if child.keys.len() < self.node_size {
+                // The child has room for one more key, so nothing above it changes.
+                root = None;
+                path.clear();
+            } else {
+                path.push((node, index));
+            }
+            node = child;
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+
+        while node.keys.len() > self.node_size {
+            let (key, value, right) = split(&mut node);
+            let right = allocate(right);
+
+            match path.pop() {
+                Some((mut parent, index)) => {
+                    parent.keys.insert(index, key);
+                    parent.values.insert(index, value);
+                    parent.children.insert(index + 1, right);
+                    node = parent;
+                }
+                None => {
+                    let root = root.as_mut().expect("root splits with its pointer latched");
+                    let left = **root;
+                    **root = allocate(Node {
+                        keys: vec![key],
+                        values: vec![value],
+                        children: vec![left, right],
+                    });
+                    break;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let root = self.read(&self.root);
+        let mut node = self.read(self.latch(*root));
+        drop(root);
+
+        loop {
+            match node.keys.binary_search(key) {
+                Ok(index) => return Some(node.values[index].clone()),
+                Err(_) if node.children.is_empty() => return None,
+                Err(index) => node = self.read(self.latch(node.children[index])),
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let Ok(old) = self.remove_if(key, |_| true) else {
+            unreachable!()
+        };
+        old
+    }
+
+    // Like insert_if, for removals.
+    fn remove_if(
+        &self,
+        key: &K,
+        check: impl Fn(Option<&V>) -> bool,
+    ) -> Result<Option<V>, Option<V>> {
+        let mut root = Some(self.write(&self.root));
+        let mut node = self.write(self.latch(**root.as_ref().unwrap()));
+        let mut path: Vec<(RwLockWriteGuard<'_, Node<K, V>>, usize)> = Vec::new();
+        // Position in `path` and key index of the internal node holding `key`,
+        // once it has been found.
+        let mut found: Option<(usize, usize)> = None;
+
+        let removed = loop {
+            let leaf = node.children.is_empty();
+            let index = match found {
+                // Replace the entry with its predecessor from the left subtree.
+                Some((position, index)) if leaf => {
+                    let (key, value) = (node.keys.pop().unwrap(), node.values.pop().unwrap());
+                    let holder = &mut path[position].0;
+                    let key = std::mem::replace(&mut holder.keys[index], key);
+                    let value = std::mem::replace(&mut holder.values[index], value);
+                    self.publish(&key, || Event::Remove {
+                        key: key.clone(),
+                        value: value.clone(),
+                    });
+                    break value;
+                }
+                Some(_) => node.keys.len(),
+                None => match node.keys.binary_search(key) {
+                    Ok(index) if !check(Some(&node.values[index])) => {
+                        return Err(Some(node.values[index].clone()))
+                    }
+                    Ok(index) if leaf => {
+                        let (key, value) = (node.keys.remove(index), node.values.remove(index));
+                        self.publish(&key, || Event::Remove {
+                            key: key.clone(),
+                            value: value.clone(),
+                        });
+                        break value;
+                    }
+                    Ok(index) => {
+                        found = Some((path.len(), index));
+                        index
+                    }
+                    Err(_) if leaf => return if check(None) { Ok(None) } else { Err(None) },
+                    Err(index) => index,
+                },
+            };
+
+            let child = self.write(self.latch(node.children[index]));
+            if found.is_none() && child.keys.len() > self.min_keys() {
+                // The child can lose a key without underflowing.
+                root = None;
+                path.clear();
+            } else {
+                path.push((node, index));
+            }
+            node = child;
+        };
+        self.len.fetch_sub(1, Ordering::Relaxed);
+
+        while let Some((mut parent, index)) = path.pop() {
+            if node.keys.len() < self.min_keys() {
+                self.fix_child(&mut parent, index, node);
+            }
+            node = parent;
+        }
+
+        if node.keys.is_empty() && !node.children.is_empty() {
+            let root = root
+                .as_mut()
+                .expect("root shrinks with its pointer latched");
+            let old = std::mem::replace(&mut **root, node.children[0]);
+            drop(node);
+            unsafe { free(old) };
+        }
+
+        Ok(Some(removed))
+    }
+
+    // Replaces the value of `key` with `new`, None meaning absent, but only if
+    // it currently is `expected`, all under the key's latch.
+    pub fn compare_and_swap(
+        &self,
+        key: K,
+        expected: Option<&V>,
+        new: Option<V>,
+    ) -> Result<(), CasError<V>> {
+        let check = |current: Option<&V>| current == expected;
+        let result = match new {
+            Some(value) => self.insert_if(key, value, check),
+            None => self.remove_if(&key, check),
+        };
+
+        result.map(|_| ()).map_err(|current| CasError { current })
+    }
+
+    // Refills `child`, the child at `index` of `parent`, after a removal left
+    // it with too few keys, by borrowing from a sibling or merging with one.
+    fn fix_child(
+        &self,
+        parent: &mut Node<K, V>,
+        index: usize,
+        mut child: RwLockWriteGuard<'_, Node<K, V>>,
+    ) {
+        let min = self.min_keys();
+        let mut left = (index > 0).then(|| self.write(self.latch(parent.children[index - 1])));
+        let mut right = parent
+            .children
+            .get(index + 1)
+            .map(|&right| self.write(self.latch(right)));
+
+        match (&mut left, &mut right) {
+            (Some(left), _) if left.keys.len() > min => {
+                let key = std::mem::replace(&mut parent.keys[index - 1], left.keys.pop().unwrap());
+                let value =
+                    std::mem::replace(&mut parent.values[index - 1], left.values.pop().unwrap());
+                child.keys.insert(0, key);
+                child.values.insert(0, value);
+                if let Some(grandchild) = left.children.pop() {
+                    child.children.insert(0, grandchild);
+                }
+                return;
+            }
+            (_, Some(right)) if right.keys.len() > min => {
+                let key = std::mem::replace(&mut parent.keys[index], right.keys.remove(0));
+                let value = std::mem::replace(&mut parent.values[index], right.values.remove(0));
+                child.keys.push(key);
+                child.values.push(value);
+                if !right.children.is_empty() {
+                    child.children.push(right.children.remove(0));
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        if let Some(mut left) = left {
+            drop(right);
+            merge(parent, index - 1, &mut left, &mut child);
+            drop(child);
+            unsafe { free(parent.children.remove(index)) };
+        } else if let Some(mut right) = right {
+            merge(parent, index, &mut child, &mut right);
+            drop(right);
+            unsafe { free(parent.children.remove(index + 1)) };
+        }
+    }
+
+    // Copies the entries in `range` out. The root stays latched until the copy
+    // is done, which keeps new writers out while those already past the root
+    // finish before the scan reaches their nodes, so the result is consistent.
+    pub fn range_snapshot<R: RangeBounds<K>>(&self, range: R) -> Vec<(K, V)> {
+        let root = self.read(&self.root);
+        let node = self.read(self.latch(*root));
+        drop(root);
+
+        let mut entries = Vec::new();
+        self.collect(&node, &range, &mut entries);
+        entries
+    }
+
+    fn collect<R: RangeBounds<K>>(&self, node: &Node<K, V>, range: &R, out: &mut Vec<(K, V)>) {
+        let start = match range.start_bound() {
+            Bound::Included(start) => node.keys.partition_point(|key| key < start),
+            Bound::Excluded(start) => node.keys.partition_point(|key| key <= start),
+            Bound::Unbounded => 0,
+        };
+
+        for index in start..=node.keys.len() {
+            if let Some(&child) = node.children.get(index) {
+                self.collect(&self.read(self.latch(child)), range, out);
+            }
+            match node.keys.get(index) {
+                Some(key) if range.contains(key) => {
+                    out.push((key.clone(), node.values[index].clone()))
+                }
+                _ => break,
+            }
+        }
+    }
+
+    // Delivers an event for every later insert, update and removal of a key in
+    // `range`. Events for one key arrive in the order the changes were made.
+    pub fn subscribe<R: RangeBounds<K>>(&self, range: R) -> Receiver<Event<K, V>> {
+        let (sender, receiver) = mpsc::channel();
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        self.subscribers.lock().unwrap().push((range, sender));
+
+        receiver
+    }
+
+    // Sends the event to every subscriber whose range holds `key`, dropping
+    // those that hung up. Called with the key's node still latched.
+    fn publish(&self, key: &K, event: impl Fn() -> Event<K, V>) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|(range, sender)| !range.contains(key) || sender.send(event()).is_ok());
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Drop
+    for SharedBTree<K, V>
+{
+    fn drop(&mut self) {
+        let mut nodes = vec![*self.root.get_mut().unwrap()];
+        while let Some(node) = nodes.pop() {
+            let node = unsafe { Box::from_raw(node.as_ptr()) };
+            nodes.extend(node.into_inner().unwrap().children);
+        }
+    }
+}
+
+fn split<K, V>(node: &mut Node<K, V>) -> (K, V, Node<K, V>) {
+    let mid = node.keys.len() / 2;
+
+    let mut keys = node.keys.split_off(mid);
+    let mut values = node.values.split_off(mid);
+    let children = if node.children.is_empty() {
+        Vec::new()
+    } else {
+        node.children.split_off(mid + 1)
+    };
+
+    (
+        keys.remove(0),
+        values.remove(0),
+        Node {
+            keys,
+            values,
+            children,
+        },
+    )
+}
+
+// Moves the key at `index` of `parent` and everything in `right` into `left`.
+fn merge<K, V>(
+    parent: &mut Node<K, V>,
+    index: usize,
+    left: &mut Node<K, V>,
+    right: &mut Node<K, V>,
+) {
+    left.keys.push(parent.keys.remove(index));
+    left.values.push(parent.values.remove(index));
+    left.keys.append(&mut right.keys);
+    left.values.append(&mut right.values);
+    left.children.append(&mut right.children);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::atomic::Ordering;
+
+    use crate::shared::{CasError, Event, SharedBTree, STALL_THRESHOLD};
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_metrics() {
+        let tree = SharedBTree::<u64, u64>::new(3);
+        for key in 0..100 {
+            tree.insert(key, key);
+        }
+        let metrics = tree.metrics();
+        assert!(metrics.latches >= 200);
+        assert_eq!(metrics.contended, 0);
+
+        // A writer blocked behind a held root latch is counted as stalled.
+        // The latch is held until the writer is known to be blocked, and then
+        // past the threshold, so the count doesn't depend on scheduling.
+        let root = tree.root.read().unwrap();
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| tree.insert(1000, 1000));
+            while tree.counters.waiting.load(Ordering::Relaxed) == 0 {
+                std::thread::yield_now();
+            }
+            std::thread::sleep(STALL_THRESHOLD * 2);
+            drop(root);
+            writer.join().unwrap();
+        });
+
+        let metrics = tree.metrics();
+        assert_eq!(metrics.contended, 1);
+        assert_eq!(metrics.writer_stalls, 1);
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let tree = SharedBTree::<u64, u64>::new(3);
+        assert_eq!(tree.compare_and_swap(1, None, Some(10)), Ok(()));
+        assert_eq!(
+            tree.compare_and_swap(1, None, Some(20)),
+            Err(CasError { current: Some(10) })
+        );
+        assert_eq!(tree.compare_and_swap(1, Some(&10), Some(20)), Ok(()));
+        assert_eq!(
+            tree.compare_and_swap(1, Some(&10), None),
+            Err(CasError { current: Some(20) })
+        );
+        assert_eq!(tree.compare_and_swap(1, Some(&20), None), Ok(()));
+        assert_eq!(tree.compare_and_swap(1, None, None), Ok(()));
+        assert_eq!(
+            tree.compare_and_swap(1, Some(&20), None),
+            Err(CasError { current: None })
+        );
+
+        // Counters bumped from several threads through retry loops lose no
+        // increments.
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..500 {
+                        let key = i % 20;
+                        let mut current = tree.get(&key);
+                        while let Err(error) = tree.compare_and_swap(
+                            key,
+                            current.as_ref(),
+                            Some(current.unwrap_or(0) + 1),
+                        ) {
+                            current = error.current;
+                        }
+                    }
+                });
+            }
+        });
+        let values: Vec<u64> = tree
+            .range_snapshot(..)
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect();
+        assert_eq!(values, vec![100; 20]);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let tree = SharedBTree::<u64, u64>::new(3);
+        let events = tree.subscribe(10..20);
+        let all = tree.subscribe(..);
+
+        for key in 0..30 {
+            tree.insert(key, key);
+        }
+        tree.insert(15, 150);
+        tree.remove(&15);
+        tree.remove(&25);
+        drop(all);
+        tree.remove(&16);
+
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(events.len(), 10 + 3);
+        assert_eq!(events[0], Event::Insert { key: 10, value: 10 });
+        assert_eq!(
+            events[10..],
+            [
+                Event::Update {
+                    key: 15,
+                    old: 15,
+                    new: 150
+                },
+                Event::Remove {
+                    key: 15,
+                    value: 150
+                },
+                Event::Remove { key: 16, value: 16 },
+            ]
+        );
+        assert_eq!(tree.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_shared_btree() {
+        for node_size in [3, 8] {
+            let tree = SharedBTree::<u64, u64>::new(node_size);
+
+            std::thread::scope(|scope| {
+                for thread in 0..4u64 {
+                    let tree = &tree;
+                    scope.spawn(move || {
+                        for key in (thread * 1000)..(thread * 1000 + 1000) {
+                            assert_eq!(tree.insert(key, key * 2), None);
+                        }
+                        for key in (thread * 1000..thread * 1000 + 1000).step_by(2) {
+                            assert_eq!(tree.remove(&key), Some(key * 2));
+                        }
+                    });
+                }
+
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        let snapshot = tree.range_snapshot(..);
+                        assert!(snapshot.windows(2).all(|pair| pair[0].0 < pair[1].0));
+                    }
+                });
+            });
+
+            assert_eq!(tree.len(), 2000);
+            assert_eq!(tree.get(&1001), Some(2002));
+            assert_eq!(tree.get(&1000), None);
+            assert_eq!(tree.range_snapshot(10..15), vec![(11, 22), (13, 26)]);
+
+            // Random single-threaded work against a std map exercises every
+            // split, borrow and merge path.
+            let mut expected: BTreeMap<u64, u64> = tree.range_snapshot(..).into_iter().collect();
+            let mut rng = Rng::with_seed(88172645463325252);
+            for _ in 0..5000 {
+                let state = rng.next_u64();
+                let key = state % 4000;
+
+                if state.is_multiple_of(3) {
+                    assert_eq!(tree.insert(key, state), expected.insert(key, state));
+                } else {
+                    assert_eq!(tree.remove(&key), expected.remove(&key));
+                }
+            }
+            assert_eq!(
+                tree.range_snapshot(..),
+                expected.into_iter().collect::<Vec<_>>()
+            );
+        }
+    }
+}
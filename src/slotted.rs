@@ -0,0 +1,313 @@
+// Slotted page layout for variable-length entries inside a page payload:
+//
+//   | count | free_start | free_end | fragmented | slots ... -> free <- ... cells |
+//
+// Slots are u16 cell offsets kept in key order. Cells grow down from the end of
+// the page and hold a u16 key length, a u16 value length, the key and the value.
+// The top bit of the value length marks values stored on overflow pages, in
+// which case the cell holds the first overflow page id and the value length.
+
+use crate::pager::PageId;
+
+const HEADER_SIZE: usize = 8;
+const SLOT_SIZE: usize = 2;
+const CELL_HEADER_SIZE: usize = 4;
+const OVERFLOW_FLAG: usize = 0x8000;
+const OVERFLOW_POINTER_SIZE: usize = 16;
+
+pub enum Value<'a> {
+    Inline(&'a [u8]),
+    Overflow { first_page: PageId, len: u64 },
+}
+
+pub struct SlottedPage<B> {
+    data: B,
+}
+
+impl<B: AsRef<[u8]>> SlottedPage<B> {
+    pub fn new(data: B) -> SlottedPage<B> {
+        SlottedPage { data }
+    }
+
+    pub fn len(&self) -> usize {
+        self.field(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn key_at(&self, index: usize) -> &[u8] {
+        let cell = self.cell_offset(index);
+        let key_len = self.read_u16(cell);
+
+        &self.data.as_ref()[cell + CELL_HEADER_SIZE..cell + CELL_HEADER_SIZE + key_len]
+    }
+
+    pub fn value_at(&self, index: usize) -> Value<'_> {
+        let cell = self.cell_offset(index);
+        let key_len = self.read_u16(cell);
+        let value_len = self.read_u16(cell + 2);
+        let start = cell + CELL_HEADER_SIZE + key_len;
+        let bytes = &self.data.as_ref()[start..start + (value_len & !OVERFLOW_FLAG)];
+
+        if value_len & OVERFLOW_FLAG == 0 {
+            return Value::Inline(bytes);
+        }
+
+        Value::Overflow {
+            first_page: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            len: u64::from_le_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Value<'_>> {
+        match self.search(key) {
+            Ok(index) => Some(self.value_at(index)),
+            Err(_) => None,
+        }
+    }
+
+    // Bytes available for new cells and slots, counting space that is only
+    // usable after compaction.
+    pub fn free_space(&self) -> usize {
+        self.field(4) - self.field(2) + self.field(6)
+    }
+
+    pub fn fragmented(&self) -> usize {
+        self.field(6)
+    }
+
+    pub fn clone_bytes(&self) -> Vec<u8> {
+        self.data.as_ref().to_vec()
+    }
+
+    fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        let mut low = 0;
+        let mut high = self.len();
+
+        while low != high {
+            let mid = (low + high) / 2;
+            match key.cmp(self.key_at(mid)) {
+                std::cmp::Ordering::Less => high = mid,
+                std::cmp::Ordering::Greater => low = mid + 1,
+                std::cmp::Ordering::Equal => return Ok(mid),
+            }
+        }
+
+        Err(low)
+    }
+
+    fn cell_offset(&self, index: usize) -> usize {
+        assert!(index < self.len(), "slot {} out of bounds", index);
+        self.read_u16(HEADER_SIZE + index * SLOT_SIZE)
+    }
+
+    fn cell_size(&self, index: usize) -> usize {
+        let cell = self.cell_offset(index);
+        CELL_HEADER_SIZE + self.read_u16(cell) + (self.read_u16(cell + 2) & !OVERFLOW_FLAG)
+    }
+
+    fn field(&self, offset: usize) -> usize {
+        self.read_u16(offset)
+    }
+
+    fn read_u16(&self, offset: usize) -> usize {
+        u16::from_le_bytes([self.data.as_ref()[offset], self.data.as_ref()[offset + 1]]) as usize
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> SlottedPage<B> {
+    // Formats `data` as an empty slotted page.
+    pub fn init(data: B) -> SlottedPage<B> {
+        let len = data.as_ref().len();
+        assert!(len > HEADER_SIZE && len <= u16::MAX as usize);
+
+        let mut page = SlottedPage { data };
+        page.set_field(0, 0);
+        page.set_field(2, HEADER_SIZE);
+        page.set_field(4, len);
+        page.set_field(6, 0);
+        page
+    }
+
+    // Inserts or replaces `key`. Returns false, leaving the page unchanged, if
+    // the entry does not fit even after compaction.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> bool {
+        value.len() < OVERFLOW_FLAG && self.insert_cell(key, value, 0)
+    }
+
+    // Inserts or replaces `key` with a pointer to a value on overflow pages.
+    pub fn insert_overflow(&mut self, key: &[u8], first_page: PageId, len: u64) -> bool {
+        let mut pointer = [0; OVERFLOW_POINTER_SIZE];
+        pointer[..8].copy_from_slice(&first_page.to_le_bytes());
+        pointer[8..].copy_from_slice(&len.to_le_bytes());
+
+        self.insert_cell(key, &pointer, OVERFLOW_FLAG)
+    }
+
+    fn insert_cell(&mut self, key: &[u8], value: &[u8], flags: usize) -> bool {
+        let needed = CELL_HEADER_SIZE + key.len() + value.len();
+        let existing = self.search(key);
+
+        let available = match existing {
+            Ok(index) => self.free_space() + self.cell_size(index) + SLOT_SIZE,
+            Err(_) => self.free_space(),
+        };
+        if needed + SLOT_SIZE > available {
+            return false;
+        }
+
+        if let Ok(index) = existing {
+            self.remove_at(index);
+        }
+        if self.free_end() - self.field(2) < needed + SLOT_SIZE {
+            self.compact();
+        }
+
+        let cell = self.free_end() - needed;
+        let data = self.data.as_mut();
+        data[cell..cell + 2].copy_from_slice(&(key.len() as u16).to_le_bytes());
+        data[cell + 2..cell + 4].copy_from_slice(&((value.len() | flags) as u16).to_le_bytes());
+        data[cell + CELL_HEADER_SIZE..cell + CELL_HEADER_SIZE + key.len()].copy_from_slice(key);
+        data[cell + CELL_HEADER_SIZE + key.len()..cell + needed].copy_from_slice(value);
+        self.set_free_end(cell);
+
+        let index = match self.search(key) {
+            Ok(index) | Err(index) => index,
+        };
+        let count = self.len();
+        let slots = HEADER_SIZE + index * SLOT_SIZE;
+        let slots_end = HEADER_SIZE + count * SLOT_SIZE;
+        self.data
+            .as_mut()
+            .copy_within(slots..slots_end, slots + SLOT_SIZE);
+        self.write_u16(slots, cell);
+
+        self.set_field(0, count + 1);
+        self.set_field(2, slots_end + SLOT_SIZE);
+
+        true
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        match self.search(key) {
+            Ok(index) => {
+                self.remove_at(index);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    // Moves all cells to the end of the page so fragmented space becomes
+    // contiguous free space again.
+    pub fn compact(&mut self) {
+        let count = self.len();
+        let old = self.clone_bytes();
+        let old = SlottedPage::new(&old[..]);
+
+        let mut free_end = self.data.as_ref().len();
+        for index in 0..count {
+            let cell = old.cell_offset(index);
+            let size = old.cell_size(index);
+
+            free_end -= size;
+            self.data.as_mut()[free_end..free_end + size]
+                .copy_from_slice(&old.data[cell..cell + size]);
+            self.write_u16(HEADER_SIZE + index * SLOT_SIZE, free_end);
+        }
+
+        self.set_free_end(free_end);
+        self.set_field(6, 0);
+    }
+
+    fn remove_at(&mut self, index: usize) {
+        let size = self.cell_size(index);
+        let count = self.len();
+
+        let slots = HEADER_SIZE + index * SLOT_SIZE;
+        let slots_end = HEADER_SIZE + count * SLOT_SIZE;
+        self.data
+            .as_mut()
+            .copy_within(slots + SLOT_SIZE..slots_end, slots);
+
+        self.set_field(0, count - 1);
+        self.set_field(2, slots_end - SLOT_SIZE);
+        self.set_field(6, self.fragmented() + size);
+    }
+
+    fn free_end(&self) -> usize {
+        self.field(4)
+    }
+
+    fn set_free_end(&mut self, free_end: usize) {
+        self.set_field(4, free_end);
+    }
+
+    fn set_field(&mut self, offset: usize, value: usize) {
+        self.write_u16(offset, value);
+    }
+
+    fn write_u16(&mut self, offset: usize, value: usize) {
+        self.data.as_mut()[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slotted::{SlottedPage, Value};
+
+    fn inline<'a>(value: Option<Value<'a>>) -> Option<&'a [u8]> {
+        match value {
+            Some(Value::Inline(bytes)) => Some(bytes),
+            Some(Value::Overflow { .. }) => panic!("unexpected overflow value"),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn test_slotted_page() {
+        let mut buf = vec![0; 256];
+        let mut page = SlottedPage::init(&mut buf[..]);
+
+        let mut inserted = Vec::new();
+        for i in 0..100u32 {
+            let key = format!("key-{}", (i * 7) % 100);
+            let value = "v".repeat(i as usize % 9);
+            if !page.insert(key.as_bytes(), value.as_bytes()) {
+                break;
+            }
+            inserted.push((key, value));
+        }
+        assert!(inserted.len() > 5);
+        assert_eq!(page.len(), inserted.len());
+
+        for index in 1..page.len() {
+            assert!(page.key_at(index - 1) < page.key_at(index));
+        }
+        for (key, value) in inserted.iter() {
+            assert_eq!(inline(page.get(key.as_bytes())), Some(value.as_bytes()));
+        }
+
+        // Free scattered cells, then insert something that only fits once the
+        // holes are compacted together.
+        for (key, _) in inserted.iter().step_by(2) {
+            assert!(page.remove(key.as_bytes()));
+        }
+        assert!(page.fragmented() > 0);
+
+        let big = "x".repeat(page.free_space() - 4 - 2 - 3);
+        assert!(page.insert(b"big", big.as_bytes()));
+        assert_eq!(page.fragmented(), 0);
+        assert_eq!(inline(page.get(b"big")), Some(big.as_bytes()));
+        assert!(!page.insert(b"more", b"!"));
+
+        for (key, value) in inserted.iter().skip(1).step_by(2) {
+            assert_eq!(inline(page.get(key.as_bytes())), Some(value.as_bytes()));
+        }
+        for (key, _) in inserted.iter().step_by(2) {
+            assert!(page.get(key.as_bytes()).is_none());
+        }
+    }
+}
@@ -0,0 +1,192 @@
+// Snapshots of in-memory trees, for loading one back quickly at startup.
+// Unlike the page file this is a single sequential stream, read once and
+// built bottom-up with from_sorted. The envelope says what it is and how
+// the rest is encoded:
+//
+//   | magic | version u32 | codec u8 | node size | entry count | entries ... |
+//
+// With CODEC_VARINT, the sizes and counts are LEB128 varints and each key
+// and value is a varint length followed by its Codec encoding.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use crate::codec::{invalid_data, Codec};
+use crate::{check_node_size, BTree};
+
+const MAGIC: [u8; 8] = *b"c-snap\0\0";
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+pub const CODEC_VARINT: u8 = 1;
+
+fn write_varint<W: Write>(out: &mut W, mut n: u64) -> io::Result<()> {
+    let mut bytes = [0; 10];
+    let mut len = 0;
+    while n >= 0x80 {
+        bytes[len] = n as u8 | 0x80;
+        n >>= 7;
+        len += 1;
+    }
+    bytes[len] = n as u8;
+    out.write_all(&bytes[..=len])
+}
+
+fn read_varint<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        n |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] < 0x80 {
+            return Ok(n);
+        }
+    }
+    Err(invalid_data("varint too long"))
+}
+
+fn write_field<W: Write, T: Codec>(out: &mut W, item: &T, buf: &mut Vec<u8>) -> io::Result<()> {
+    buf.clear();
+    item.encode(buf);
+    write_varint(out, buf.len() as u64)?;
+    out.write_all(buf)
+}
+
+fn read_field<R: Read, T: Codec>(input: &mut R, buf: &mut Vec<u8>) -> io::Result<T> {
+    let len = read_varint(input)?;
+    buf.clear();
+    if input.take(len).read_to_end(buf)? as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    T::decode(buf)
+}
+
+pub fn write_snapshot<K, V, W>(tree: &BTree<K, V>, out: W) -> io::Result<()>
+where
+    K: Ord + Clone + std::fmt::Debug + Codec,
+    V: Ord + Clone + std::fmt::Debug + Codec,
+    W: Write,
+{
+    let mut out = BufWriter::new(out);
+    out.write_all(&MAGIC)?;
+    out.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    out.write_all(&[CODEC_VARINT])?;
+    write_varint(&mut out, tree.node_size() as u64)?;
+    write_varint(&mut out, tree.len() as u64)?;
+
+    let mut buf = Vec::new();
+    for (key, value) in tree.iter() {
+        write_field(&mut out, key, &mut buf)?;
+        write_field(&mut out, value, &mut buf)?;
+    }
+    out.flush()
+}
+
+pub fn read_snapshot<K, V, R>(input: R) -> io::Result<BTree<K, V>>
+where
+    K: Ord + Clone + std::fmt::Debug + Codec,
+    V: Ord + Clone + std::fmt::Debug + Codec,
+    R: Read,
+{
+    let mut input = BufReader::new(input);
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid_data("not a c-tree snapshot"));
+    }
+
+    let mut version = [0; 4];
+    input.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version > SNAPSHOT_VERSION {
+        return Err(invalid_data(format!(
+            "snapshot version {} is newer than supported version {}",
+            version, SNAPSHOT_VERSION
+        )));
+    }
+    let mut codec = [0];
+    input.read_exact(&mut codec)?;
+    if codec[0] != CODEC_VARINT {
+        return Err(invalid_data(format!("unknown snapshot codec {}", codec[0])));
+    }
+
+    let node_size = check_node_size(read_varint(&mut input)?)?;
+    let len = read_varint(&mut input)?;
+
+    let mut buf = Vec::new();
+    // The count comes from the file, so it only caps the reservation.
+    let mut entries: Vec<(K, V)> = Vec::with_capacity(len.min(1 << 16) as usize);
+    for _ in 0..len {
+        let key: K = read_field(&mut input, &mut buf)?;
+        let value: V = read_field(&mut input, &mut buf)?;
+        if let Some((last, _)) = entries.last() {
+            if *last >= key {
+                return Err(invalid_data("keys are not in ascending order"));
+            }
+        }
+        entries.push((key, value));
+    }
+
+    Ok(BTree::from_sorted(node_size, entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::snapshot::{read_snapshot, write_snapshot};
+    use crate::testutil::{Rng, TempDir};
+    use crate::BTree;
+
+    #[test]
+    fn test_snapshot() {
+        let mut tree = BTree::<u64, String>::new(5);
+        let mut rng = Rng::new();
+        for _ in 0..3000 {
+            let state = rng.next_u64();
+            tree.add(state % 100_000, "x".repeat((state >> 40) as usize % 200));
+        }
+
+        let mut bytes = Vec::new();
+        write_snapshot(&tree, &mut bytes).unwrap();
+        let loaded = read_snapshot::<u64, String, _>(bytes.as_slice()).unwrap();
+        assert!(loaded.iter().eq(tree.iter()));
+        assert_eq!(loaded.node_size(), 5);
+
+        // Varint lengths take one or two bytes where save spends four.
+        let dir = TempDir::new("snapshot");
+        let path = dir.join("tree");
+        tree.save(&path).unwrap();
+        let saved = std::fs::metadata(&path).unwrap().len() as usize;
+        assert!(
+            bytes.len() + 5 * tree.len() <= saved,
+            "{} vs {}",
+            bytes.len(),
+            saved
+        );
+
+        let empty = BTree::<u64, String>::new(3);
+        let mut empty_bytes = Vec::new();
+        write_snapshot(&empty, &mut empty_bytes).unwrap();
+        assert!(read_snapshot::<u64, String, _>(empty_bytes.as_slice())
+            .unwrap()
+            .is_empty());
+
+        let read = |bytes: &[u8]| {
+            read_snapshot::<u64, String, _>(bytes)
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        let mut bad = bytes.clone();
+        bad[0] = b'x';
+        assert_eq!(read(&bad), "not a c-tree snapshot");
+        let mut bad = bytes.clone();
+        bad[8] = 2;
+        assert!(read(&bad).starts_with("snapshot version 2"));
+        let mut bad = bytes.clone();
+        bad[12] = 9;
+        assert_eq!(read(&bad), "unknown snapshot codec 9");
+        let mut bad = bytes.clone();
+        bad[13..16].copy_from_slice(&[0x80, 0x80, 0x40]);
+        assert!(read(&bad).starts_with("node size 1048576 is not between"));
+        assert!(read_snapshot::<u64, String, _>(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,502 @@
+// Immutable sorted-run files:
+//
+//   | data blocks | index | bloom filter | footer |
+//
+// Data blocks hold length-prefixed entries in key order. The index has the
+// first key, offset and length of every block, and the footer points at the
+// index and bloom filter.
+
+use std::borrow::Borrow;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::ops::RangeBounds;
+use std::path::Path;
+
+use crate::codec::{invalid_data, read_item, write_item, Codec};
+use crate::BTree;
+
+const MAGIC: [u8; 8] = *b"c-sstab\0";
+const FOOTER_SIZE: usize = 48;
+const BLOCK_SIZE: usize = 4096;
+const BLOOM_BITS_PER_KEY: usize = 10;
+const BLOOM_HASHES: u32 = 7;
+
+struct Bloom {
+    bits: Vec<u8>,
+    hashes: u32,
+}
+
+impl Bloom {
+    fn new(keys: usize) -> Bloom {
+        Bloom {
+            bits: vec![0; (keys * BLOOM_BITS_PER_KEY).div_ceil(8).max(8)],
+            hashes: BLOOM_HASHES,
+        }
+    }
+
+    fn insert(&mut self, hash: u64) {
+        for bit in self.bit_positions(hash) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_positions(fnv1a(key))
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    // Double hashing over two halves of a 64 bit FNV-1a hash.
+    fn bit_positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let (h1, h2) = (hash as u32, (hash >> 32) as u32 | 1);
+        let len = self.bits.len() as u64 * 8;
+
+        (0..self.hashes).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) as u64 % len) as usize)
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+// Writes entries, which must be in ascending key order, to a new file at `path`.
+// Entries out of order are an InvalidInput error.
+pub fn write<'a, K, V, I, P>(path: P, entries: I) -> io::Result<()>
+where
+    K: Codec + Ord + 'a,
+    V: Codec + 'a,
+    I: IntoIterator<Item = (&'a K, &'a V)>,
+    P: AsRef<Path>,
+{
+    write_entries::<K, V, _, _, _>(path.as_ref(), entries)
+}
+
+// Like write, for entries produced on the fly rather than borrowed.
+pub fn write_owned<K, V, I, P>(path: P, entries: I) -> io::Result<()>
+where
+    K: Codec + Ord,
+    V: Codec,
+    I: IntoIterator<Item = (K, V)>,
+    P: AsRef<Path>,
+{
+    write_entries::<K, V, _, _, _>(path.as_ref(), entries)
+}
+
+// Removes the file again if writing it fails, so no partial table is left
+// where a whole one is expected.
+fn write_entries<K, V, KR, VR, I>(path: &Path, entries: I) -> io::Result<()>
+where
+    K: Codec + Ord,
+    V: Codec,
+    KR: Borrow<K>,
+    VR: Borrow<V>,
+    I: IntoIterator<Item = (KR, VR)>,
+{
+    let file = File::create(path)?;
+    let result = write_file::<K, V, _, _, _>(file, entries);
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+fn write_file<K, V, KR, VR, I>(file: File, entries: I) -> io::Result<()>
+where
+    K: Codec + Ord,
+    V: Codec,
+    KR: Borrow<K>,
+    VR: Borrow<V>,
+    I: IntoIterator<Item = (KR, VR)>,
+{
+    let mut file = BufWriter::new(file);
+    let mut buf = Vec::new();
+    let mut key_buf = Vec::new();
+
+    let mut index = Vec::new();
+    let mut key_hashes = Vec::new();
+    let mut offset = 0u64;
+    let mut block = Vec::with_capacity(BLOCK_SIZE);
+    let mut block_first_key: Option<Vec<u8>> = None;
+    let mut count = 0u64;
+    let mut last: Option<KR> = None;
+
+    for (key, value) in entries {
+        let value: &V = value.borrow();
+        if last
+            .as_ref()
+            .is_some_and(|last| last.borrow() >= key.borrow())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sstable entries must be in ascending key order",
+            ));
+        }
+        let key: &K = (*last.insert(key)).borrow();
+
+        key_buf.clear();
+        key.encode(&mut key_buf);
+        key_hashes.push(fnv1a(&key_buf));
+
+        if block_first_key.is_none() {
+            block_first_key = Some(key_buf.clone());
+        }
+        write_item(&mut block, key, &mut buf)?;
+        write_item(&mut block, value, &mut buf)?;
+        count += 1;
+
+        if block.len() >= BLOCK_SIZE {
+            file.write_all(&block)?;
+            index.push((block_first_key.take().unwrap(), offset, block.len() as u64));
+            offset += block.len() as u64;
+            block.clear();
+        }
+    }
+    if let Some(first_key) = block_first_key {
+        file.write_all(&block)?;
+        index.push((first_key, offset, block.len() as u64));
+        offset += block.len() as u64;
+    }
+
+    let index_offset = offset;
+    let mut index_bytes = Vec::new();
+    for (first_key, block_offset, block_len) in index.iter() {
+        write_item(&mut index_bytes, first_key, &mut buf)?;
+        index_bytes.extend_from_slice(&block_offset.to_le_bytes());
+        index_bytes.extend_from_slice(&block_len.to_le_bytes());
+    }
+    file.write_all(&index_bytes)?;
+
+    let mut bloom = Bloom::new(key_hashes.len());
+    for hash in key_hashes {
+        bloom.insert(hash);
+    }
+    let bloom_offset = index_offset + index_bytes.len() as u64;
+    file.write_all(&bloom.hashes.to_le_bytes())?;
+    file.write_all(&bloom.bits)?;
+    let bloom_len = 4 + bloom.bits.len() as u64;
+
+    for word in [
+        index_offset,
+        index.len() as u64,
+        bloom_offset,
+        bloom_len,
+        count,
+    ] {
+        file.write_all(&word.to_le_bytes())?;
+    }
+    file.write_all(&MAGIC)?;
+
+    file.into_inner()?.sync_data()
+}
+
+pub struct SSTable<K: Codec + Ord, V: Codec> {
+    file: BufReader<File>,
+    // First key, offset and length of every data block.
+    index: Vec<(K, u64, u64)>,
+    bloom: Bloom,
+    len: u64,
+    values: PhantomData<V>,
+}
+
+impl<K: Codec + Ord, V: Codec> SSTable<K, V> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<SSTable<K, V>> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let size = file.seek(SeekFrom::End(0))?;
+        if size < FOOTER_SIZE as u64 {
+            return Err(invalid_data("file too short for an sstable"));
+        }
+        let mut footer = [0; FOOTER_SIZE];
+        file.seek(SeekFrom::Start(size - FOOTER_SIZE as u64))?;
+        file.read_exact(&mut footer)?;
+
+        if footer[40..] != MAGIC {
+            return Err(invalid_data("not a c-tree sstable"));
+        }
+        let word = |i: usize| u64::from_le_bytes(footer[i * 8..i * 8 + 8].try_into().unwrap());
+        let (index_offset, blocks, bloom_offset, bloom_len, len) =
+            (word(0), word(1), word(2), word(3), word(4));
+
+        // Everything the footer says is checked against the file before it
+        // is used to seek or allocate.
+        let data_end = size - FOOTER_SIZE as u64;
+        if index_offset > bloom_offset || bloom_offset.checked_add(bloom_len) != Some(data_end) {
+            return Err(invalid_data("sstable footer offsets don't match the file"));
+        }
+        // An entry takes at least 8 bytes and an index record 20.
+        if len > index_offset / 8 || blocks > (bloom_offset - index_offset) / 20 {
+            return Err(invalid_data("sstable footer counts don't fit the file"));
+        }
+        if bloom_len <= 4 {
+            return Err(invalid_data("sstable bloom filter is empty"));
+        }
+
+        let mut index_bytes = vec![0; (bloom_offset - index_offset) as usize];
+        file.seek(SeekFrom::Start(index_offset))?;
+        file.read_exact(&mut index_bytes)?;
+        let mut buf = Vec::new();
+        let mut input = index_bytes.as_slice();
+        let mut index = Vec::with_capacity(blocks as usize);
+        for _ in 0..blocks {
+            let first_key: K = read_item(&mut input, &mut buf)?;
+            if input.len() < 16 {
+                return Err(invalid_data("sstable index is cut short"));
+            }
+            let (words, rest) = input.split_at(16);
+            input = rest;
+            let offset = u64::from_le_bytes(words[..8].try_into().unwrap());
+            let block_len = u64::from_le_bytes(words[8..].try_into().unwrap());
+            if offset
+                .checked_add(block_len)
+                .is_none_or(|end| end > index_offset)
+            {
+                return Err(invalid_data("sstable block lies outside the data"));
+            }
+            index.push((first_key, offset, block_len));
+        }
+
+        let mut bloom_bytes = vec![0; bloom_len as usize];
+        file.seek(SeekFrom::Start(bloom_offset))?;
+        file.read_exact(&mut bloom_bytes)?;
+        // Every probe costs a pass, so a corrupt count would make lookups
+        // crawl.
+        let hashes = u32::from_le_bytes(bloom_bytes[..4].try_into().unwrap());
+        if !(1..=BLOOM_HASHES).contains(&hashes) {
+            return Err(invalid_data("sstable bloom filter has a bad hash count"));
+        }
+        let bloom = Bloom {
+            hashes,
+            bits: bloom_bytes.split_off(4),
+        };
+
+        Ok(SSTable {
+            file,
+            index,
+            bloom,
+            len,
+            values: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&mut self, key: &K) -> io::Result<Option<V>> {
+        let mut buf = Vec::new();
+        key.encode(&mut buf);
+        if !self.bloom.may_contain(&buf) {
+            return Ok(None);
+        }
+
+        let block = self
+            .index
+            .partition_point(|(first_key, _, _)| first_key <= key);
+        if block == 0 {
+            return Ok(None);
+        }
+        let (_, offset, len) = self.index[block - 1];
+
+        let mut bytes = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut bytes)?;
+
+        let mut block = &bytes[..];
+        while !block.is_empty() {
+            let entry_key: K = read_item(&mut block, &mut buf)?;
+            let value: V = read_item(&mut block, &mut buf)?;
+            if entry_key == *key {
+                return Ok(Some(value));
+            }
+            if entry_key > *key {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Reads all entries in key order.
+    pub fn entries(&mut self) -> io::Result<Vec<(K, V)>> {
+        let mut buf = Vec::new();
+        let mut entries = Vec::with_capacity(self.len as usize);
+
+        self.file.seek(SeekFrom::Start(0))?;
+        for _ in 0..self.len {
+            let key: K = read_item(&mut self.file, &mut buf)?;
+            let value: V = read_item(&mut self.file, &mut buf)?;
+            entries.push((key, value));
+        }
+
+        Ok(entries)
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug + Codec, V: Ord + Clone + std::fmt::Debug + Codec>
+    BTree<K, V>
+{
+    pub fn export_sstable<P: AsRef<Path>, R: RangeBounds<K>>(
+        &self,
+        path: P,
+        range: R,
+    ) -> io::Result<()> {
+        write(path, self.range(range))
+    }
+
+    // Merges the entries of the sstable at `path` into the tree, replacing
+    // values of keys present in both, and rebuilds the tree bottom-up.
+    pub fn ingest<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let incoming = SSTable::<K, V>::open(path)?.entries()?;
+
+        let node_size = self.root.node_size;
+        let existing = std::mem::replace(self, BTree::new(node_size)).into_sorted();
+
+        let mut merged = Vec::with_capacity(existing.len() + incoming.len());
+        let mut existing = existing.into_iter().peekable();
+        let mut incoming = incoming.into_iter().peekable();
+        loop {
+            let take_existing = match (existing.peek(), incoming.peek()) {
+                (Some((a, _)), Some((b, _))) => a < b,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+
+            if take_existing {
+                merged.push(existing.next().unwrap());
+            } else {
+                let entry = incoming.next().unwrap();
+                if existing.peek().map(|(key, _)| key) == Some(&entry.0) {
+                    existing.next();
+                }
+                merged.push(entry);
+            }
+        }
+
+        *self = BTree::from_sorted(node_size, merged);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use crate::sstable::{self, SSTable};
+    use crate::testutil::TempDir;
+    use crate::BTree;
+
+    #[test]
+    fn test_sstable_export_ingest() {
+        let dir = TempDir::new("sstable");
+        let path = dir.join("table");
+
+        let mut source = BTree::<u64, String>::new(8);
+        for key in 0..2000 {
+            source.add(key, format!("source {}", key));
+        }
+        source.export_sstable(&path, 500..1500).unwrap();
+
+        let mut table = SSTable::<u64, String>::open(&path).unwrap();
+        assert_eq!(table.len(), 1000);
+        assert_eq!(table.get(&500).unwrap(), Some("source 500".to_string()));
+        assert_eq!(table.get(&1499).unwrap(), Some("source 1499".to_string()));
+        assert_eq!(table.get(&1500).unwrap(), None);
+        assert_eq!(table.get(&7).unwrap(), None);
+
+        let mut target = BTree::<u64, String>::new(8);
+        for key in (0..3000).step_by(2) {
+            target.add(key, format!("target {}", key));
+        }
+        target.ingest(&path).unwrap();
+
+        assert_eq!(target.len(), 1500 + 500);
+        assert_eq!(target.find(498), Some("target 498".to_string()));
+        assert_eq!(target.find(500), Some("source 500".to_string()));
+        assert_eq!(target.find(501), Some("source 501".to_string()));
+        assert_eq!(target.find(1500), Some("target 1500".to_string()));
+        assert!(target
+            .iter()
+            .zip(target.iter().skip(1))
+            .all(|(a, b)| a.0 < b.0));
+    }
+
+    #[test]
+    fn test_sstable_corrupt() {
+        let dir = TempDir::new("sstable-bad");
+        let path = dir.join("table");
+        let tree = BTree::from_sorted(8, (0..1000u64).map(|key| (key, key)).collect());
+        tree.export_sstable(&path, ..).unwrap();
+        let good = std::fs::read(&path).unwrap();
+        let footer = good.len() - 48;
+        let word = |bytes: &[u8], i: usize| {
+            u64::from_le_bytes(
+                bytes[footer + i * 8..footer + i * 8 + 8]
+                    .try_into()
+                    .unwrap(),
+            )
+        };
+
+        let open = |bytes: &[u8]| {
+            std::fs::write(&path, bytes).unwrap();
+            SSTable::<u64, u64>::open(&path).err().unwrap().kind()
+        };
+        let with_word = |i: usize, value: u64| {
+            let mut bad = good.clone();
+            bad[footer + i * 8..footer + i * 8 + 8].copy_from_slice(&value.to_le_bytes());
+            bad
+        };
+
+        assert_eq!(open(&good[..good.len() / 2]), ErrorKind::InvalidData);
+        assert_eq!(open(&good[..10]), ErrorKind::InvalidData);
+        for (i, value) in [
+            (0, u64::MAX),
+            (1, u64::MAX),
+            (2, 0),
+            (3, 1 << 40),
+            (4, u64::MAX),
+        ] {
+            assert_eq!(
+                open(&with_word(i, value)),
+                ErrorKind::InvalidData,
+                "word {}",
+                i
+            );
+        }
+
+        // A bloom filter with no bits, with the index grown to cover the rest.
+        let bloom_end = word(&good, 2) + word(&good, 3);
+        let mut bad = with_word(2, bloom_end - 4);
+        bad[footer + 24..footer + 32].copy_from_slice(&4u64.to_le_bytes());
+        assert_eq!(open(&bad), ErrorKind::InvalidData);
+
+        // The length of the first block, after its 4 byte length and 8 byte key.
+        let mut bad = good.clone();
+        let first = word(&good, 0) as usize + 4 + 8 + 8;
+        bad[first..first + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(open(&bad), ErrorKind::InvalidData);
+
+        // The bloom filter's hash count, in front of its bits.
+        for hashes in [0, 8, u32::MAX] {
+            let mut bad = good.clone();
+            let bloom = word(&good, 2) as usize;
+            bad[bloom..bloom + 4].copy_from_slice(&hashes.to_le_bytes());
+            assert_eq!(open(&bad), ErrorKind::InvalidData, "{} hashes", hashes);
+        }
+
+        // Unsorted input is the caller's mistake, and leaves no file behind.
+        let unsorted = dir.join("unsorted");
+        let err = sstable::write(&unsorted, [(&2u64, &1u64), (&1, &1)]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(!unsorted.exists());
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Byte storage underneath the pager. Offsets are always page aligned.
+pub trait PageStore {
+    fn size(&mut self) -> io::Result<u64>;
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()>;
+    fn set_size(&mut self, size: u64) -> io::Result<()>;
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+pub struct FileStore {
+    file: File,
+}
+
+impl FileStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileStore> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        Ok(FileStore { file })
+    }
+}
+
+impl PageStore for FileStore {
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(buf)
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(buf)
+    }
+
+    fn set_size(&mut self, size: u64) -> io::Result<()> {
+        self.file.set_len(size)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    bytes: Vec<u8>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore { bytes: Vec::new() }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl From<Vec<u8>> for MemoryStore {
+    fn from(bytes: Vec<u8>) -> MemoryStore {
+        MemoryStore { bytes }
+    }
+}
+
+impl PageStore for MemoryStore {
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.bytes.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let start = offset as usize;
+        match self.bytes.get(start..start + buf.len()) {
+            Some(bytes) => {
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+            None => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+        }
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let start = offset as usize;
+        if self.bytes.len() < start + buf.len() {
+            self.bytes.resize(start + buf.len(), 0);
+        }
+        self.bytes[start..start + buf.len()].copy_from_slice(buf);
+
+        Ok(())
+    }
+
+    fn set_size(&mut self, size: u64) -> io::Result<()> {
+        self.bytes.resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pager::Pager;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn test_memory_store() {
+        let mut pager = Pager::with_store(MemoryStore::new(), 512, 3, 2).unwrap();
+        for i in 0..8u8 {
+            let id = pager.allocate().unwrap();
+            pager.page_mut(id).fill(i);
+            pager.unpin(id, true).unwrap();
+        }
+        pager.free(4).unwrap();
+        pager.flush().unwrap();
+
+        let bytes = pager.store().bytes().to_vec();
+        assert_eq!(bytes.len(), 9 * 512);
+
+        let mut pager = Pager::with_store(MemoryStore::from(bytes), 512, 3, 2).unwrap();
+        assert_eq!(pager.free_pages(), 1);
+        assert!(pager.verify().unwrap().is_empty());
+        pager.pin(8).unwrap();
+        assert!(pager.page(8).iter().all(|byte| *byte == 7));
+    }
+}
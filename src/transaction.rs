@@ -0,0 +1,171 @@
+// Writes made through a Transaction are buffered and only reach the tree on
+// commit, all at once. Dropping the transaction without committing discards
+// them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::BTree;
+
+pub struct Transaction<'a, K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    tree: &'a mut BTree<K, V>,
+    // Pending value of every written key, None for a removal.
+    writes: BTreeMap<K, Option<V>>,
+    reads: BTreeSet<K>,
+    // Buffered entry each write replaced, newest last, so writes can be undone
+    // back to a savepoint.
+    undo: Vec<(K, Option<Option<V>>)>,
+}
+
+// Position in a transaction's writes to roll back to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Savepoint(usize);
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> BTree<K, V> {
+    pub fn transaction(&mut self) -> Transaction<'_, K, V> {
+        Transaction {
+            tree: self,
+            writes: BTreeMap::new(),
+            reads: BTreeSet::new(),
+            undo: Vec::new(),
+        }
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> Transaction<'_, K, V> {
+    // Reads `key` as this transaction sees it, including its own writes.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.reads.insert(key.clone());
+        self.peek(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        match self.writes.get(key) {
+            Some(write) => write.as_ref(),
+            None => self.tree.get(key),
+        }
+    }
+
+    // Returns the value the key had within this transaction.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old = self.peek(&key).cloned();
+        self.write(key, Some(value));
+        old
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let old = self.peek(key).cloned();
+        self.write(key.clone(), None);
+        old
+    }
+
+    fn write(&mut self, key: K, write: Option<V>) {
+        let replaced = self.writes.insert(key.clone(), write);
+        self.undo.push((key, replaced));
+    }
+
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint(self.undo.len())
+    }
+
+    // Undoes the writes made since `savepoint`. Later savepoints become
+    // invalid, `savepoint` itself can be rolled back to again.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) {
+        assert!(savepoint.0 <= self.undo.len(), "savepoint was rolled back");
+
+        for (key, replaced) in self.undo.drain(savepoint.0..).rev() {
+            match replaced {
+                Some(write) => self.writes.insert(key, write),
+                None => self.writes.remove(&key),
+            };
+        }
+    }
+
+    // Keys read so far, in order.
+    pub fn reads(&self) -> impl Iterator<Item = &K> {
+        self.reads.iter()
+    }
+
+    pub fn commit(self) {
+        for (key, write) in self.writes {
+            match write {
+                Some(value) => {
+                    self.tree.add(key, value);
+                }
+                None => {
+                    self.tree.remove(&key);
+                }
+            }
+        }
+    }
+
+    pub fn rollback(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BTree;
+
+    #[test]
+    fn test_savepoints() {
+        let mut tree = BTree::<u64, u64>::new(4);
+        tree.add(1, 1);
+
+        // Import records one by one, undoing only the ones that fail.
+        let mut txn = tree.transaction();
+        for key in 2..10 {
+            let savepoint = txn.savepoint();
+            txn.insert(key, key);
+            txn.remove(&1);
+            if key % 3 == 0 {
+                txn.rollback_to(savepoint);
+                assert_eq!(txn.get(&key), None);
+            }
+        }
+
+        let outer = txn.savepoint();
+        txn.insert(2, 200);
+        let inner = txn.savepoint();
+        txn.insert(2, 300);
+        txn.rollback_to(inner);
+        assert_eq!(txn.get(&2), Some(&200));
+        txn.rollback_to(outer);
+        assert_eq!(txn.get(&2), Some(&2));
+        txn.commit();
+
+        let keys: Vec<u64> = tree.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, [2, 4, 5, 7, 8]);
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut tree = BTree::<u64, String>::new(4);
+        for key in 0..10 {
+            tree.add(key, key.to_string());
+        }
+
+        let mut txn = tree.transaction();
+        assert_eq!(txn.insert(3, "three".to_string()), Some("3".to_string()));
+        assert_eq!(txn.remove(&4), Some("4".to_string()));
+        assert_eq!(txn.insert(20, "twenty".to_string()), None);
+        assert_eq!(txn.get(&3), Some(&"three".to_string()));
+        assert_eq!(txn.get(&4), None);
+        assert_eq!(txn.get(&5), Some(&"5".to_string()));
+        assert_eq!(txn.reads().copied().collect::<Vec<_>>(), [3, 4, 5]);
+        txn.rollback();
+
+        assert_eq!(tree.get(&3), Some(&"3".to_string()));
+        assert_eq!(tree.get(&4), Some(&"4".to_string()));
+        assert_eq!(tree.get(&20), None);
+
+        let mut txn = tree.transaction();
+        txn.insert(3, "three".to_string());
+        txn.remove(&4);
+        txn.insert(20, "twenty".to_string());
+        txn.commit();
+
+        assert_eq!(tree.get(&3), Some(&"three".to_string()));
+        assert_eq!(tree.get(&4), None);
+        assert_eq!(tree.get(&20), Some(&"twenty".to_string()));
+        assert_eq!(tree.len(), 10);
+    }
+}
@@ -0,0 +1,166 @@
+// Entries that expire. Expired entries are hidden from reads right away and
+// removed by sweep, which a background sweeper thread can call periodically.
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::BTree;
+
+pub struct TtlBTree<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> {
+    // Values with the instant they expire at, if any.
+    tree: BTree<K, (V, Option<Instant>)>,
+    // Every expiring key, soonest first.
+    expiries: BTreeSet<(Instant, K)>,
+}
+
+fn live<V>(entry: &(V, Option<Instant>), now: Instant) -> Option<&V> {
+    match entry.1 {
+        Some(expiry) if expiry <= now => None,
+        _ => Some(&entry.0),
+    }
+}
+
+impl<K: Ord + Clone + std::fmt::Debug, V: Ord + Clone + std::fmt::Debug> TtlBTree<K, V> {
+    pub fn new(node_size: usize) -> TtlBTree<K, V> {
+        TtlBTree {
+            tree: BTree::new(node_size),
+            expiries: BTreeSet::new(),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.put(key, value, None)
+    }
+
+    // Inserts an entry that expires `ttl` from now. A ttl too long for an
+    // Instant to hold never expires.
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let Some(expiry) = Instant::now().checked_add(ttl) else {
+            return self.put(key, value, None);
+        };
+        let old = self.put(key.clone(), value, Some(expiry));
+        self.expiries.insert((expiry, key));
+        old
+    }
+
+    // Returns the old value unless it had expired.
+    fn put(&mut self, key: K, value: V, expiry: Option<Instant>) -> Option<V> {
+        let (old, old_expiry) = self.tree.add(key.clone(), (value, expiry))?;
+        if let Some(old_expiry) = old_expiry {
+            self.expiries.remove(&(old_expiry, key));
+        }
+
+        live(&(old, old_expiry), Instant::now()).cloned()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        live(self.tree.get(key)?, Instant::now())
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, expiry) = self.tree.remove(key)?;
+        if let Some(expiry) = expiry {
+            self.expiries.remove(&(expiry, key.clone()));
+        }
+
+        live(&(value, expiry), Instant::now()).cloned()
+    }
+
+    // Removes every expired entry, returning how many there were.
+    pub fn sweep(&mut self) -> usize {
+        let now = Instant::now();
+        let mut removed = 0;
+
+        while let Some((expiry, _)) = self.expiries.first() {
+            if *expiry > now {
+                break;
+            }
+            let (_, key) = self.expiries.pop_first().unwrap();
+            self.tree.remove(&key);
+            removed += 1;
+        }
+
+        removed
+    }
+
+    // Number of entries, including expired ones not yet swept.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Sweeps `tree` every `interval` on a background thread, which exits once the
+// last other handle to the tree is dropped.
+pub fn spawn_sweeper<K, V>(tree: &Arc<Mutex<TtlBTree<K, V>>>, interval: Duration) -> JoinHandle<()>
+where
+    K: Ord + Clone + std::fmt::Debug + Send + Sync + 'static,
+    V: Ord + Clone + std::fmt::Debug + Send + Sync + 'static,
+{
+    let tree = Arc::downgrade(tree);
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        match tree.upgrade() {
+            Some(tree) => {
+                tree.lock().unwrap().sweep();
+            }
+            None => break,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::ttl::{spawn_sweeper, TtlBTree};
+
+    #[test]
+    fn test_ttl() {
+        let hour = Duration::from_secs(3600);
+        let mut tree = TtlBTree::<u64, u64>::new(4);
+        for key in 0..30 {
+            match key % 3 {
+                0 => tree.insert_with_ttl(key, key, Duration::ZERO),
+                1 => tree.insert_with_ttl(key, key, hour),
+                _ => tree.insert(key, key),
+            };
+        }
+
+        assert_eq!(tree.get(&0), None);
+        assert_eq!(tree.get(&1), Some(&1));
+        assert_eq!(tree.get(&2), Some(&2));
+        assert_eq!(tree.insert(3, 30), None);
+        assert_eq!(tree.insert_with_ttl(4, 40, Duration::ZERO), Some(4));
+        assert_eq!(tree.remove(&6), None);
+
+        assert_eq!(tree.len(), 29);
+        assert_eq!(tree.sweep(), 9);
+        assert_eq!(tree.len(), 20);
+        assert_eq!(tree.get(&3), Some(&30));
+        assert_eq!(tree.insert_with_ttl(3, 31, Duration::MAX), Some(30));
+        assert_eq!(tree.sweep(), 0);
+        assert_eq!(tree.get(&3), Some(&31));
+
+        // The sweeper clears short-lived entries on its own and stops once the
+        // tree is dropped.
+        let shared = Arc::new(Mutex::new(tree));
+        let sweeper = spawn_sweeper(&shared, Duration::from_millis(5));
+        shared
+            .lock()
+            .unwrap()
+            .insert_with_ttl(100, 100, Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(shared.lock().unwrap().len(), 20);
+
+        drop(shared);
+        sweeper.join().unwrap();
+    }
+}